@@ -1,40 +1,31 @@
 mod oscillators {
     use serde::{Serialize, Deserialize};
-    use std::sync::{RwLock};
     use crate::logger::Logger;
 
-    #[derive(Default, Serialize, Deserialize)]
-    struct VolumeEnvelopeParams {
-        add_mode: bool,
-        period: u8,
-        current_level: u8,
-        frequency_timer: u32,
+    /// Whether each of the 8 steps of a duty cycle is high, for 12.5%, 25%, 50% and 75%
+    const DUTY_PATTERNS: [u8; 4] = [0b1000_0000, 0b1100_0000, 0b1111_0000, 0b0011_1111];
+
+    /// What a channel's DAC puts out for `level`, from 0.0 to 1.0. A powered down DAC sits in
+    /// the middle of that range instead, not at the level a silent channel with its DAC on sits
+    /// at, so powering one up or down pops like it does on hardware.
+    fn dac(powered: bool, level: u8) -> f32 {
+        if powered { level as f32 / 15.0 } else { 0.5 }
     }
 
+    /// Clocked at 64 Hz by the frame sequencer, moves the volume a step every `period` clocks.
     #[derive(Default, Serialize, Deserialize)]
     struct VolumeEnvelope {
-        sample_rate: u32,
-        params: VolumeEnvelopeParams,
-        last_val: u8,
+        add_mode: bool,
+        period: u8,
+        current_level: u8,
+        timer: u8,
         current_settings: u8,
     }
 
     impl VolumeEnvelope {
-        pub(crate) fn new(sample_rate: u32) -> VolumeEnvelope {
-            VolumeEnvelope { sample_rate, ..Default::default() }
-        }
-
         pub(crate) fn write_settings(&mut self, val: u8) {
-            let starting_vol = val >> 4;
-            let add_mode = ((val & 0x08) >> 3) > 0;
-            let period = val & 0x07;
-
-            // Get the lock for all items
-            self.params.current_level = starting_vol;
-            self.params.add_mode = add_mode;
-            self.params.period = period;
-            self.params.frequency_timer = (self.sample_rate / 64) * ((period) as u32);
-
+            self.add_mode = ((val & 0x08) >> 3) > 0;
+            self.period = val & 0x07;
             self.current_settings = val;
         }
 
@@ -42,25 +33,58 @@ mod oscillators {
             self.current_settings
         }
 
-        pub(crate) fn generate_sample(&mut self) -> u8 {
-            self.last_val = self.params.current_level;
-            let output_sample = self.params.current_level;
-            if self.params.period == 0 {
-                return output_sample;
+        /// Restarts from the starting volume, which only takes effect when the channel triggers.
+        pub(crate) fn trigger(&mut self) {
+            self.current_level = self.current_settings >> 4;
+            self.timer = self.period;
+        }
+
+        pub(crate) fn clock(&mut self) {
+            if self.period == 0 {
+                return;
             }
-            // Apply envelope
-            // Check if level change is needed
-            if self.params.frequency_timer == 0 {
-                self.params.frequency_timer = (self.sample_rate / 64) * ((self.params.period) as u32);
-
-                if self.params.add_mode && self.params.current_level < 15 {
-                    self.params.current_level += 1;
-                } else if !self.params.add_mode && self.params.current_level > 0 {
-                    self.params.current_level -= 1;
-                }
+
+            self.timer = self.timer.saturating_sub(1);
+            if self.timer > 0 {
+                return;
+            }
+            self.timer = self.period;
+
+            if self.add_mode && self.current_level < 15 {
+                self.current_level += 1;
+            } else if !self.add_mode && self.current_level > 0 {
+                self.current_level -= 1;
             }
-            self.params.frequency_timer -= 1;
-            output_sample
+        }
+
+        pub(crate) fn level(&self) -> u8 {
+            self.current_level
+        }
+    }
+
+    /// Clocked at 256 Hz by the frame sequencer while enabled, disables its channel once it
+    /// reaches 0.
+    #[derive(Default, Serialize, Deserialize)]
+    struct LengthCounter {
+        counter: u16,
+        enabled: bool,
+    }
+
+    impl LengthCounter {
+        /// Triggering a channel whose length already ran out starts over from the full `length`.
+        pub(crate) fn trigger(&mut self, length: u16) {
+            if self.counter == 0 {
+                self.counter = length;
+            }
+        }
+
+        /// Returns whether the length just ran out.
+        pub(crate) fn clock(&mut self) -> bool {
+            if !self.enabled || self.counter == 0 {
+                return false;
+            }
+            self.counter -= 1;
+            self.counter == 0
         }
     }
 
@@ -68,20 +92,20 @@ mod oscillators {
     pub struct SquareWaveGenerator {
         frequency: u16,
 
+        /// Dots until the duty cycle moves on to its next step
         frequency_timer: u32,
-        timer_leftover: RwLock<f32>,
-        sample_rate: u32,
         sweep: bool,
         position: u8,
         duty: u8,
         trigger: u8,
         enabled: bool,
+        /// Powered by the upper 5 bits of NRx2, a channel can't be enabled while its DAC is off
+        dac_enabled: bool,
         length: u8,
-        length_counter: u32,
-        length_enabled: bool,
+        length_counter: LengthCounter,
         env: VolumeEnvelope,
         sweep_period: u8,
-        sweep_timer: u32,
+        sweep_timer: u8,
         sweep_negate: bool,
         sweep_shift: u8,
         sweep_enabled: bool,
@@ -89,12 +113,10 @@ mod oscillators {
     }
 
     impl SquareWaveGenerator {
-        pub(crate) fn new(sample_rate: u32, sweep: bool) -> SquareWaveGenerator {
+        pub(crate) fn new(sweep: bool) -> SquareWaveGenerator {
             SquareWaveGenerator {
-                sample_rate,
                 sweep,
                 duty: 2,
-                env: VolumeEnvelope::new(sample_rate),
                 ..Default::default()
             }
         }
@@ -103,29 +125,17 @@ mod oscillators {
             match reg {
                 0 => {
                     if self.sweep {
-                        let period = (val & 0x70) >> 4;
-                        let negate = (val & 0x08) > 0;
-                        let shift = val & 0x07;
-
-                        self.sweep_period = period;
-                        self.sweep_negate = negate;
-                        self.sweep_shift = shift;
+                        self.sweep_period = (val & 0x70) >> 4;
+                        self.sweep_negate = (val & 0x08) > 0;
+                        self.sweep_shift = val & 0x07;
                     }
                 }
 
                 // Duty and length
                 1 => {
-                    let new_duty = val >> 6;
-                    self.duty = new_duty;
-
-                    let length = val & 0x3F;
-                    self.length = length;
-
-                    let length_256hz = 64 - length;
-                    let length_samples = ((self.sample_rate as f32 / 256.0) * length_256hz as f32).ceil() as u32;
-
-                    // Here we set the length counter making sure nothing can use it while it is set
-                    self.length_counter = length_samples;
+                    self.duty = val >> 6;
+                    self.length = val & 0x3F;
+                    self.length_counter.counter = 64 - self.length as u16;
                 }
 
                 // Volume envelope
@@ -133,27 +143,21 @@ mod oscillators {
                     self.env.write_settings(val);
 
                     // Disable channel if no DAC power
-                    if val & 0xF0 == 0 {
+                    self.dac_enabled = val & 0xF8 != 0;
+                    if !self.dac_enabled {
                         self.enabled = false;
                     }
                 }
 
                 // Frequency 8 least significant bits
                 3 => {
-                    // No need to worry about safety since this thread is the only one which will ever change frequency
-                    let new_frequency = (val as u16 & 0xFF) | (self.frequency & 0xFF00);
-                    self.frequency = new_frequency;
+                    self.frequency = (self.frequency & 0x0700) | val as u16;
                 }
 
                 // Frequency 3 most significant bits and trigger
                 4 => {
-
-                    // No need to worry about safety since this thread is the only one which will ever change frequency
-                    let msb = ((val as u16 & 0x7) << 8) | 0xFF;
-                    let new_frequency = (msb & 0xFF00) | (self.frequency & 0xFF);
-                    self.frequency = new_frequency;
-
-                    self.length_enabled = (val & 0x40) > 0;
+                    self.frequency = ((val as u16 & 0x07) << 8) | (self.frequency & 0xFF);
+                    self.length_counter.enabled = (val & 0x40) > 0;
 
                     let trigger = val & 0x80;
                     self.trigger = trigger;
@@ -162,63 +166,23 @@ mod oscillators {
                         return;
                     }
 
-                    // If length == 0 reset it to 64
-                    if self.length_counter == 0 {
-                        self.length_counter = ((self.sample_rate as f32 / 256.0) * 64.0).ceil() as u32;
-                    }
+                    // Triggering only enables the channel if its DAC is powered
+                    self.enabled = self.dac_enabled;
+                    self.length_counter.trigger(64);
+                    self.frequency_timer = self.period();
+                    self.env.trigger();
 
-                    // Sweep data
                     if self.sweep {
                         // Copy frequency to shadow register
-                        self.sweep_frequency = new_frequency;
-
-                        let sweep_period = self.sweep_period;
-                        let sweep_shift = self.sweep_shift;
-
-                        // Reload sweep timer
-                        let sweep_num_samples = ((self.sample_rate as f32 / 128.0) * sweep_period as f32) as u32;
-                        self.sweep_timer = sweep_num_samples;
-
-                        // Set sweep enabled flag
-                        let sweep_enabled = sweep_period != 0 && sweep_shift != 0;
-                        self.sweep_enabled = sweep_enabled;
-
-                        // Perform frequency and overflow calcs
-                        if sweep_enabled {
-                            let (overflow, new_sweep_freq) = self.calculate_sweep_freq();
-
-                            if overflow {
-                                self.enabled = false;
-                                return;
-                            }
-
-                            self.sweep_frequency = new_sweep_freq;
-                            self.frequency = new_sweep_freq;
-
-                            let (overflow_2, _) = self.calculate_sweep_freq();
+                        self.sweep_frequency = self.frequency;
+                        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+                        self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
 
-                            if overflow_2 {
-                                self.enabled = false;
-                                return;
-                            }
-                        }
-                    }
-
-                    // Reset frequency timer and timer leftover
-                    let cycles_till_next = (2048 - self.frequency as u32) * 4;
-                    let samples_till_next = (self.sample_rate as f32 / 4194304.0) * cycles_till_next as f32;
-                    self.frequency_timer = samples_till_next.floor() as u32;
-
-                    // Store the remainder from the conversion from length in cycles to samples in timer leftover
-                    match self.timer_leftover.write() {
-                        Ok(mut timer_leftover) => {
-                            *timer_leftover = samples_till_next - samples_till_next.floor();
+                        // The new frequency is only checked for overflow until the first sweep clock
+                        if self.sweep_shift != 0 && self.calculate_sweep_freq().0 {
+                            self.enabled = false;
                         }
-                        Err(error) => Logger::error(format!("Square Wave: Could not write to timer leftover: {error}")),
                     }
-
-                    // Set enabled
-                    self.enabled = true;
                 }
 
                 reg => Logger::error(format!("Square Wave Osc: Unrecognised register ({reg})")),
@@ -254,7 +218,7 @@ mod oscillators {
 
                 4 => {
                     let mut reg_value = self.trigger;
-                    reg_value |= (self.length_enabled as u8) << 6;
+                    reg_value |= (self.length_counter.enabled as u8) << 6;
                     reg_value |= ((self.frequency & 0x0F00) >> 8) as u8;
                     reg_value
                 }
@@ -263,122 +227,70 @@ mod oscillators {
             }
         }
 
-        pub(crate) fn generate_sample(&mut self) -> f32 {
-            if !self.enabled {
-                return 0.0;
-            }
-
-            if self.frequency_timer == 0 {
-                // Reset frequency timer
-                let cycles_till_next = (2048 - self.frequency as u32) * 4;
-                let mut samples_till_next = (self.sample_rate as f32 / 4194304.0) * cycles_till_next as f32;
-
-                // If leftover plus current remainder is more than one we should make this period another sample long to make up for the lost time
-                match self.timer_leftover.write() {
-                    Ok(mut timer_leftover) => {
-                        *timer_leftover += samples_till_next - samples_till_next.floor();
-
-                        if *timer_leftover > 1.0 {
-                            *timer_leftover -= 1.0;
-                            samples_till_next += 1.0;
-                        }
-                    }
-                    Err(error) => {
-                        Logger::error(format!("Square Wave - Could not write to timer leftover: {error}"));
-                    }
-                }
-
-                self.frequency_timer = samples_till_next.floor() as u32;
-
-                let current_position = self.position;
-
-                let mut new_position = current_position + 1;
-                if new_position >= 8 {
-                    new_position = 0;
-                }
+        /// Dots between steps of the duty cycle
+        fn period(&self) -> u32 {
+            (2048 - self.frequency as u32) * 4
+        }
 
-                self.position = new_position;
+        /// Runs the frequency timer for `dots` 4 MiHz clocks.
+        pub(crate) fn step(&mut self, mut dots: u32) {
+            while dots >= self.frequency_timer {
+                dots -= self.frequency_timer;
+                self.frequency_timer = self.period();
+                self.position = (self.position + 1) % 8;
             }
+            self.frequency_timer -= dots;
+        }
 
-            self.frequency_timer -= 1;
-
-            if self.sweep {
-                if self.sweep_timer == 0 && self.sweep_enabled && self.sweep_period > 0 {
-                    // Reload sweep timer
-                    let sweep_num_samples = ((self.sample_rate as f32 / 128.0) * self.sweep_period as f32) as u32;
-                    self.sweep_timer = sweep_num_samples;
-
-                    let (overflow, new_sweep_freq) = self.calculate_sweep_freq();
-
-                    if overflow {
-                        self.enabled = false;
-                        return 0.0;
-                    }
-
-                    self.sweep_frequency = new_sweep_freq;
-                    self.frequency = new_sweep_freq;
-
-                    let (overflow_2, _) = self.calculate_sweep_freq();
-
-                    if overflow_2 {
-                        self.enabled = false;
-                        return 0.0;
-                    }
-                }
-
-                self.sweep_timer -= 1;
+        pub(crate) fn clock_length(&mut self) {
+            if self.length_counter.clock() {
+                self.enabled = false;
             }
+        }
 
-            let mut wave_sample = 0;
-            let envelope_sample = self.env.generate_sample();
-
-            match self.duty {
-                // 12.5%
-                0 => {
-                    if self.position == 7 {
-                        wave_sample = 1;
-                    }
-                }
-
-                // 25%
-                1 => {
-                    if self.position >= 6 {
-                        wave_sample = 1;
-                    }
-                }
+        pub(crate) fn clock_envelope(&mut self) {
+            self.env.clock();
+        }
 
-                // 50%
-                2 => {
-                    if self.position >= 4 {
-                        wave_sample = 1;
-                    }
-                }
+        pub(crate) fn clock_sweep(&mut self) {
+            self.sweep_timer = self.sweep_timer.saturating_sub(1);
+            if self.sweep_timer > 0 {
+                return;
+            }
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
 
-                // 75%
-                3 => {
-                    if self.position < 6 {
-                        wave_sample = 1;
-                    }
-                }
+            if !self.sweep_enabled || self.sweep_period == 0 {
+                return;
+            }
 
-                _ => {}
+            let (overflow, new_sweep_freq) = self.calculate_sweep_freq();
+            if overflow {
+                self.enabled = false;
+                return;
             }
 
-            if self.length_enabled {
-                // Try and decrement the length counter, if we can't get access to it that means it's being reset and we don't want to decrement it anyway
-                self.length_counter = if let Some(val) = self.length_counter.checked_sub(1) { val } else { 0 };
-                if self.length_counter == 0 {
+            if self.sweep_shift != 0 {
+                self.sweep_frequency = new_sweep_freq;
+                self.frequency = new_sweep_freq;
+
+                // The next frequency is checked right away too
+                if self.calculate_sweep_freq().0 {
                     self.enabled = false;
                 }
             }
+        }
 
-            let dac_input_sample = if wave_sample != 0 {
-                envelope_sample
-            } else {
-                0
-            };
+        /// Level the channel feeds its DAC, from 0 to 15.
+        pub(crate) fn output(&self) -> u8 {
+            if !self.enabled || (DUTY_PATTERNS[self.duty as usize] >> self.position) & 1 == 0 {
+                return 0;
+            }
+            self.env.level()
+        }
 
-            dac_input_sample as f32 / 15.0
+        /// What the channel puts out through its DAC, see [`dac`].
+        pub(crate) fn dac_output(&self) -> f32 {
+            dac(self.dac_enabled, self.output())
         }
 
         fn calculate_sweep_freq(&self) -> (bool, u16) {
@@ -406,15 +318,12 @@ mod oscillators {
 
     #[derive(Default, Serialize, Deserialize)]
     pub struct WaveTable {
-        sample_rate: u32,
-
         sound_data: [u8; 32],
 
         frequency: u16,
 
+        /// Dots until the next sample of the wave
         frequency_timer: u32,
-        timer_leftover: RwLock<f32>,
-
         position: u8,
 
         trigger: u8,
@@ -422,94 +331,50 @@ mod oscillators {
         enabled: bool,
 
         length: u8,
-        length_counter: RwLock<u32>,
+        length_counter: LengthCounter,
 
-        length_enabled: bool,
+        dac_enabled: bool,
 
         volume_code: u8,
     }
 
     impl WaveTable {
-        pub(crate) fn new(sample_rate: u32) -> WaveTable {
-            WaveTable { sample_rate, ..Default::default() }
-        }
-
         pub(crate) fn write_reg(&mut self, reg: usize, val: u8) {
             match reg {
                 0 => {
-                    if val == 0x00 {
+                    self.dac_enabled = val & 0x80 != 0;
+                    if !self.dac_enabled {
                         self.enabled = false;
                     }
                 }
                 1 => {
                     self.length = val;
-                    let length_256hz = 256 - val as u32;
-                    let length_samples = ((self.sample_rate as f32 / 256.0) * length_256hz as f32).ceil() as u32;
-
-                    // Here we set the length counter making sure nothing can use it while it is set
-                    match self.length_counter.write() {
-                        Ok(mut length_counter) => {
-                            *length_counter = length_samples;
-                        }
-                        Err(error) => Logger::error(format!("Could not set wave table length: {error}")),
-                    }
+                    self.length_counter.counter = 256 - val as u16;
                 }
 
                 2 => {
-                    self.volume_code = (val & 60) >> 5;
+                    self.volume_code = (val & 0x60) >> 5;
                 }
 
                 // Frequency 8 least significant bits
                 3 => {
-                    // No need to worry about safety since this thread is the only one which will ever change frequency
-                    let new_frequency = (val as u16 & 0xFF) | (self.frequency & 0xFF00);
-                    self.frequency = new_frequency;
+                    self.frequency = (self.frequency & 0x0700) | val as u16;
                 }
 
                 // Frequency 3 most significant bits and Trigger
                 4 => {
-
-                    // No need to worry about safety since this thread is the only one which will ever change frequency
-                    let msb = ((val as u16 & 0x7) << 8) | 0xFF;
-                    let new_frequency = (msb & 0xFF00) | (self.frequency & 0xFF);
-                    self.frequency = new_frequency;
-
-                    self.length_enabled = (val & 0x40) > 0;
+                    self.frequency = ((val as u16 & 0x07) << 8) | (self.frequency & 0xFF);
+                    self.length_counter.enabled = (val & 0x40) > 0;
 
                     let trigger = val & 0x80;
                     self.trigger = trigger;
 
                     if trigger > 0 {
-                        // If length == 0 reset it to 256
-                        match self.length_counter.write() {
-                            Ok(mut length_counter) => {
-                                if *length_counter == 0 {
-                                    *length_counter = ((self.sample_rate as f32 / 256.0) * 256.0).ceil() as u32;
-                                }
-                            }
-                            Err(_error) => {
-                                Logger::error("Could not set square wave length");
-                            }
-                        }
-
-                        // Reset frequency timer
-                        let cycles_till_next = (2048 - self.frequency as u32) * 2;
-                        let samples_till_next = (self.sample_rate as f32 / 4194304.0) * cycles_till_next as f32;
-                        self.frequency_timer = samples_till_next as u32;
-
-                        // See square wave for an explanation on timer leftover
-                        match self.timer_leftover.write() {
-                            Ok(mut timer_leftover) => {
-                                *timer_leftover = samples_till_next - samples_till_next.floor();
-                            }
-                            Err(_) => {
-                                Logger::error("Wave table: Could not write to timer leftover")
-                            }
-                        }
-
+                        self.length_counter.trigger(256);
+                        self.frequency_timer = self.period();
                         self.position = 0;
 
-                        self.enabled = true;
+                        self.enabled = self.dac_enabled;
                     }
                 }
 
@@ -523,15 +388,17 @@ mod oscillators {
 
         pub(crate) fn read_reg(&self, reg: usize) -> u8 {
             match reg {
+                0 => (self.dac_enabled as u8) << 7,
+
                 1 => self.length,
 
-                2 => self.volume_code << 6,
+                2 => self.volume_code << 5,
 
                 3 => (self.frequency & 0x00FF) as u8,
 
                 4 => {
                     let mut reg_value = self.trigger;
-                    reg_value |= (self.length_enabled as u8) << 6;
+                    reg_value |= (self.length_counter.enabled as u8) << 6;
                     reg_value |= ((self.frequency & 0x0F00) >> 8) as u8;
                     reg_value
                 }
@@ -558,109 +425,46 @@ mod oscillators {
             reg_val
         }
 
-        pub(crate) fn generate_sample(&mut self) -> f32 {
-            if !self.enabled {
-                return 0.0;
-            }
-
-            let mut current_position = self.position;
-
-            if self.frequency_timer == 0 {
-
-                // Reset frequency timer
-                let cycles_till_next = (2048 - self.frequency as u32) * 2;
-                let mut samples_till_next = (self.sample_rate as f32 / 4194304.0) * cycles_till_next as f32;
-
-                // See square wave for explanation on timer leftover
-                match self.timer_leftover.write() {
-                    Ok(mut timer_leftover) => {
-                        *timer_leftover += samples_till_next - samples_till_next.floor();
-
-                        if *timer_leftover > 1.0 {
-                            *timer_leftover -= 1.0;
-                            samples_till_next += 1.0;
-                        }
-                    }
-                    Err(_) => {
-                        Logger::error("Wave table: Could not write to timer leftover");
-                    }
-                }
-
-                self.frequency_timer = samples_till_next as u32;
-
-                // Move one position forward
-                let new_position = if current_position == 31 {
-                    0
-                } else {
-                    current_position + 1
-                };
+        /// Dots between samples of the wave
+        fn period(&self) -> u32 {
+            (2048 - self.frequency as u32) * 2
+        }
 
-                self.position = new_position;
-                current_position = new_position;
+        /// Runs the frequency timer for `dots` 4 MiHz clocks.
+        pub(crate) fn step(&mut self, mut dots: u32) {
+            while dots >= self.frequency_timer {
+                dots -= self.frequency_timer;
+                self.frequency_timer = self.period();
+                self.position = (self.position + 1) % 32;
             }
+            self.frequency_timer -= dots;
+        }
 
-            self.frequency_timer -= 1;
-
-            let mut wave_sample = self.sound_data[current_position as usize];
-
-            let volume_shift = match self.volume_code {
-                0 => {
-                    4
-                }
-
-                1 => {
-                    0
-                }
-
-                2 => {
-                    1
-                }
-
-                3 => {
-                    2
-                }
-
-                _ => {
-                    Logger::error("Wave table: unexpected volume code");
-                    4
-                }
-            };
-
-            wave_sample >>= volume_shift;
-
-            if self.length_enabled {
-                // Try and decrement the length counter, if we can't get access to it that means it's being reset and we don't want to decrement it anyway
-                match self.length_counter.try_write() {
-                    Ok(mut length_counter) => {
-
-                        // Just in case there's an underflow
-                        let new_length = match length_counter.checked_sub(1) {
-                            Some(val) => {
-                                val
-                            }
-                            None => {
-                                0
-                            }
-                        };
-
-                        *length_counter = new_length;
+        pub(crate) fn clock_length(&mut self) {
+            if self.length_counter.clock() {
+                self.enabled = false;
+            }
+        }
 
-                        // If we've reached the end of the current length disable the channel
-                        if *length_counter == 0 {
-                            self.enabled = false;
-                        }
-                    }
-                    Err(_error) => {}
-                }
+        /// Level the channel feeds its DAC, from 0 to 15.
+        pub(crate) fn output(&self) -> u8 {
+            if !self.enabled {
+                return 0;
             }
 
-            wave_sample as f32 / 15.0
+            // Muted, 100%, 50% and 25%
+            let volume_shift = [4, 0, 1, 2][self.volume_code as usize];
+            self.sound_data[self.position as usize] >> volume_shift
+        }
+
+        /// What the channel puts out through its DAC, see [`dac`].
+        pub(crate) fn dac_output(&self) -> f32 {
+            dac(self.dac_enabled, self.output())
         }
     }
 
-    #[derive(Default, Serialize, Deserialize)]
+    #[derive(Serialize, Deserialize)]
     pub struct NoiseGenerator {
-        sample_rate: u32,
         env: VolumeEnvelope,
 
         divisor_code: u8,
@@ -669,9 +473,9 @@ mod oscillators {
 
         clock_shift: u8,
 
+        /// Dots until the LFSR shifts
         frequency_timer: u32,
-        timer_leftover: RwLock<f32>,
-        lfsr: [bool; 15],
+        lfsr: u16,
 
         width: bool,
 
@@ -679,48 +483,47 @@ mod oscillators {
 
         enabled: bool,
 
-        length: u8,
-        length_counter: RwLock<u32>,
+        dac_enabled: bool,
 
-        length_enabled: bool,
+        length: u8,
+        length_counter: LengthCounter,
     }
 
-    impl NoiseGenerator {
-        pub(crate) fn new(sample_rate: u32) -> NoiseGenerator {
+    impl Default for NoiseGenerator {
+        fn default() -> Self {
             NoiseGenerator {
-                sample_rate,
-                env: VolumeEnvelope::new(sample_rate),
-                lfsr: [true; 15],
-                ..Default::default()
+                env: VolumeEnvelope::default(),
+                divisor_code: 0,
+                divisor: 8,
+                clock_shift: 0,
+                frequency_timer: 0,
+                lfsr: 0x7FFF,
+                width: false,
+                trigger: 0,
+                enabled: false,
+                dac_enabled: false,
+                length: 0,
+                length_counter: LengthCounter::default(),
             }
         }
+    }
 
+    impl NoiseGenerator {
         pub(crate) fn write_reg(&mut self, reg: usize, val: u8) {
             match reg {
                 0 => {}
 
                 1 => {
-                    let length = val & 0x3F;
-                    let length_256hz = 64 - length;
-                    let length_samples = ((self.sample_rate as f32 / 256.0) * length_256hz as f32).ceil() as u32;
-                    self.length = length;
-
-                    // Here we set the length counter making sure nothing can use it while it is set
-                    match self.length_counter.write() {
-                        Ok(mut length_counter) => {
-                            *length_counter = length_samples;
-                        }
-                        Err(_error) => {
-                            Logger::error("Could not set noise generator length");
-                        }
-                    }
+                    self.length = val & 0x3F;
+                    self.length_counter.counter = 64 - self.length as u16;
                 }
 
                 2 => {
                     self.env.write_settings(val);
 
                     // Disable channel if no DAC power
-                    if val & 0xF0 == 0 {
+                    self.dac_enabled = val & 0xF8 != 0;
+                    if !self.dac_enabled {
                         self.enabled = false;
                     }
                 }
@@ -744,45 +547,21 @@ mod oscillators {
                 }
 
                 4 => {
-                    self.length_enabled = (val & 0x40) > 0;
+                    self.length_counter.enabled = (val & 0x40) > 0;
 
                     let trigger = val & 0x80;
                     self.trigger = trigger;
 
                     if trigger > 0 {
-                        // If length == 0 reset it to 64
-                        match self.length_counter.write() {
-                            Ok(mut length_counter) => {
-                                if *length_counter == 0 {
-                                    *length_counter = ((self.sample_rate as f32 / 256.0) * 64.0).ceil() as u32;
-                                }
-                            }
-                            Err(_error) => {
-                                Logger::error("Could not set square wave length");
-                            }
-                        }
+                        self.length_counter.trigger(64);
 
                         // Fill LFSR with 1s
-                        for bit in self.lfsr.iter_mut() {
-                            *bit = true;
-                        }
+                        self.lfsr = 0x7FFF;
 
-                        // Set frequency timer
-                        let frequency = (self.divisor as u32) << (self.clock_shift as u32);
-                        let samples_till_next = (self.sample_rate as f32 / 4194304.0) * frequency as f32;
-                        self.frequency_timer = samples_till_next as u32;
-
-                        // See square wave for an explanation on timer leftover
-                        match self.timer_leftover.write() {
-                            Ok(mut timer_leftover) => {
-                                *timer_leftover = samples_till_next - samples_till_next.floor();
-                            }
-                            Err(_) => {
-                                Logger::error("Noise osc: Could not write to timer leftover")
-                            }
-                        }
+                        self.frequency_timer = self.period();
+                        self.env.trigger();
 
-                        self.enabled = true;
+                        self.enabled = self.dac_enabled;
                     }
                 }
 
@@ -812,7 +591,7 @@ mod oscillators {
 
                 4 => {
                     let mut reg_value = self.trigger;
-                    reg_value |= (self.length_enabled as u8) << 6;
+                    reg_value |= (self.length_counter.enabled as u8) << 6;
                     reg_value
                 }
 
@@ -820,89 +599,84 @@ mod oscillators {
             }
         }
 
-        pub(crate) fn generate_sample(&mut self) -> f32 {
-            if !self.enabled {
-                return 0.0;
-            }
-
-            let env_sample = self.env.generate_sample();
-            if self.frequency_timer == 0 {
-                // Reset frequency timer
-                let frequency = (self.divisor as u32) << (self.clock_shift as u32);
-                let mut samples_till_next = (self.sample_rate as f32 / 4194304.0) * frequency as f32;
-
-                // See square wave for explanation on timer leftover
-                match self.timer_leftover.write() {
-                    Ok(mut timer_leftover) => {
-                        *timer_leftover += samples_till_next - samples_till_next.floor();
-
-                        if *timer_leftover > 1.0 {
-                            *timer_leftover -= 1.0;
-                            samples_till_next += 1.0;
-                        }
-                    }
-                    Err(_) => {
-                        Logger::error("Square Wave: Could not write to timer leftover");
-                    }
-                }
-
-                self.frequency_timer = samples_till_next.ceil() as u32;
-
-                // Move self.lfsr on
-                let new_val = self.lfsr[0] != self.lfsr[1];
-                self.lfsr.rotate_left(1);
+        /// Dots between shifts of the LFSR
+        fn period(&self) -> u32 {
+            (self.divisor as u32) << (self.clock_shift as u32)
+        }
 
-                self.lfsr[14] = new_val;
+        /// Runs the frequency timer for `dots` 4 MiHz clocks.
+        pub(crate) fn step(&mut self, mut dots: u32) {
+            while dots >= self.frequency_timer {
+                dots -= self.frequency_timer;
+                self.frequency_timer = self.period();
 
+                // The XOR of the two lowest bits is shifted in at the top, and into bit 6 as well
+                // in 7 bit mode
+                let new_val = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                self.lfsr = (self.lfsr >> 1) | (new_val << 14);
                 if self.width {
-                    self.lfsr[6] = new_val;
+                    self.lfsr = (self.lfsr & !(1 << 6)) | (new_val << 6);
                 }
             }
+            self.frequency_timer -= dots;
+        }
 
-            self.frequency_timer -= 1;
-
-            let noise_sample = i32::from(self.lfsr[0]);
-
-            if self.length_enabled {
-
-                // Try and decrement the length counter, if we can't get access to it that means it's being reset and we don't want to decrement it anyway
-                if let Ok(mut length_counter) = self.length_counter.try_write() {
-
-                    // Just in case there's an underflow
-                    let new_length = length_counter.checked_sub(1).unwrap_or(0);
+        pub(crate) fn clock_length(&mut self) {
+            if self.length_counter.clock() {
+                self.enabled = false;
+            }
+        }
 
-                    *length_counter = new_length;
+        pub(crate) fn clock_envelope(&mut self) {
+            self.env.clock();
+        }
 
-                    // If we've reached the end of the current length disable the channel
-                    if *length_counter == 0 {
-                        self.enabled = false;
-                    }
-                }
+        /// Level the channel feeds its DAC, from 0 to 15.
+        pub(crate) fn output(&self) -> u8 {
+            if !self.enabled || self.lfsr & 1 != 0 {
+                return 0;
             }
+            self.env.level()
+        }
 
-            let dac_input_sample = if noise_sample != 0 {
-                env_sample
-            } else {
-                0
-            };
-
-            dac_input_sample as f32 / 15.0
+        /// What the channel puts out through its DAC, see [`dac`].
+        pub(crate) fn dac_output(&self) -> f32 {
+            dac(self.dac_enabled, self.output())
         }
     }
 }
 
 use std::cmp;
+#[cfg(feature = "frontend")]
 use std::cmp::min;
-use std::sync::{Arc, Mutex};
-
-use cpal::{traits::{HostTrait, DeviceTrait}, StreamConfig, StreamError, Stream, SupportedStreamConfig, SampleRate};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+#[cfg(any(unix, windows))]
+use std::fs::File;
+#[cfg(any(unix, windows))]
+use std::io::BufWriter;
+use std::mem::{replace, swap};
+#[cfg(feature = "frontend")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "frontend")]
+use cpal::{traits::{HostTrait, DeviceTrait, StreamTrait}, StreamConfig, StreamError, Stream, SupportedStreamConfig, SampleRate};
+#[cfg(any(unix, windows))]
+use hound::{SampleFormat, WavSpec, WavWriter};
+use clap::ValueEnum;
 use serde::{Serialize, Deserialize};
 use crate::logger::Logger;
 
-#[derive(Default, Serialize, Deserialize)]
+/// Dots (4 MiHz clocks) in a second, the rate the channels run at regardless of the CPU speed
+const CLOCK_RATE: f32 = 4194304.0;
+
+/// Dots between steps of the 512 Hz frame sequencer
+const SEQUENCER_PERIOD: u32 = 8192;
+
+#[derive(Serialize, Deserialize)]
 struct AudioProcessingState {
-    sample_rate: u32,
-    num_channels: u16,
     osc_1: oscillators::SquareWaveGenerator,
     osc_2: oscillators::SquareWaveGenerator,
     osc_3: oscillators::WaveTable,
@@ -917,42 +691,345 @@ struct AudioProcessingState {
     right_master_vol: u8,
 
     power_control: bool,
+
+    /// Dots since the frame sequencer's last step
+    sequencer_timer: u32,
+    /// Next step of the frame sequencer, out of 8
+    sequencer_step: u8,
 }
 
-impl AudioProcessingState {
-    pub(crate) fn new() -> Arc<Mutex<AudioProcessingState>> {
-        let config = Self::load_config();
-        let sample_rate = config.sample_rate().0;
-        let out_dev = cpal::default_host().default_output_device().expect("No available output device found");
+impl Default for AudioProcessingState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        // Display device name
-        if let Ok(name) = out_dev.name() {
-            Logger::info(format!("Using {} at {}Hz with {} channels", name, sample_rate, config.channels()))
+/// Charge the output capacitors keep every 4 MiHz clock, as measured on a DMG. The closer to 1.0
+/// the lower the cutoff of the high-pass filter they make up (a CGB's is around 0.998943).
+const HIGH_PASS_CHARGE: f32 = 0.999958;
+
+/// Sub-samples per output sample the sinc resampler averages the mix into before filtering it
+const OVERSAMPLING: usize = 2;
+
+/// Length of the sinc resampler's filter, in sub-samples
+const SINC_TAPS: usize = 128;
+
+/// How the mix, which changes every 4 MiHz clock, is brought down to the output sample rate.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Resampler {
+    /// Average of the mix over each sample period, a box filter that keeps most aliasing out
+    #[default]
+    Average,
+    /// The mix at each sample point, interpolated between the M-cycles on either side of it.
+    /// Cheapest, but high pitched sounds alias into audible tones
+    Linear,
+    /// Windowed sinc low-pass filter, which cuts off cleanly just below the output's Nyquist
+    /// frequency at the cost of a few milliseconds of latency
+    Sinc,
+}
+
+/// Low-pass FIR over the mix averaged at `OVERSAMPLING` times the output rate, decimating it to
+/// the output rate: a Blackman windowed sinc cut off at 90% of the output's Nyquist frequency.
+struct SincFilter {
+    taps: Vec<f32>,
+    /// The last `SINC_TAPS` sub-samples, oldest first
+    history: VecDeque<(f32, f32)>,
+    /// Sub-samples since the last output sample
+    phase: usize,
+}
+
+impl Default for SincFilter {
+    fn default() -> Self {
+        // In cycles per sub-sample, the output's Nyquist frequency being 0.5 / OVERSAMPLING
+        let cutoff = 0.9 * 0.5 / OVERSAMPLING as f32;
+        let center = (SINC_TAPS - 1) as f32 / 2.0;
+        let taps: Vec<f32> = (0..SINC_TAPS)
+            .map(|n| {
+                let x = n as f32 - center;
+                let sinc = if x == 0.0 { 2.0 * cutoff } else { (2.0 * PI * cutoff * x).sin() / (PI * x) };
+                let phase = 2.0 * PI * n as f32 / (SINC_TAPS - 1) as f32;
+                sinc * (0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos())
+            })
+            .collect();
+        // Unity gain, so constant levels come out unchanged
+        let gain: f32 = taps.iter().sum();
+        Self {
+            taps: taps.iter().map(|tap| tap / gain).collect(),
+            history: VecDeque::from(vec![(0.0, 0.0); SINC_TAPS]),
+            phase: 0,
         }
+    }
+}
+
+impl SincFilter {
+    /// Adds a sub-sample, returning the next output sample every `OVERSAMPLING` of them.
+    fn push(&mut self, sample: (f32, f32)) -> Option<(f32, f32)> {
+        self.history.pop_front();
+        self.history.push_back(sample);
+        self.phase = (self.phase + 1) % OVERSAMPLING;
+        if self.phase != 0 {
+            return None;
+        }
+        let filtered = self.history.iter().zip(&self.taps).fold((0.0, 0.0), |(left, right), (sample, tap)| {
+            (left + sample.0 * tap, right + sample.1 * tap)
+        });
+        Some(filtered)
+    }
+}
+
+/// Output stage between the channels and the speaker, kept across resets and save states: the
+/// settings chosen by the user rather than the game, the capacitors filtering the DC offset, the
+/// resampling down to the output stream's rate and the ring the samples go out through.
+struct Mixer {
+    sample_rate: u32,
+    num_channels: u16,
+    volume: f32,
+    muted: [bool; 4],
+    /// Charge of the left and right capacitors
+    capacitors: [f32; 2],
+    resampler: Resampler,
+    /// Dots and the sum of the mix over them since the last sample
+    dots: f32,
+    sum: (f32, f32),
+    /// The mix as of the previous M-cycle, for linear interpolation
+    previous: (f32, f32),
+    sinc: SincFilter,
+    /// WAV file the output is copied to, if recording
+    #[cfg(any(unix, windows))]
+    recorder: Option<WavWriter<BufWriter<File>>>,
+    /// The emulation's end of the sample ring
+    samples: Arc<SampleRing>,
+    /// The other end, until an output stream takes it
+    queue: Option<SampleQueue>,
+}
 
-        Arc::new(Mutex::new(AudioProcessingState {
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::with_format(44100, 2)
+    }
+}
+
+impl Mixer {
+    fn with_format(sample_rate: u32, num_channels: u16) -> Self {
+        let queue = SampleQueue::with_format(sample_rate, num_channels);
+        Self {
             sample_rate,
-            num_channels: config.channels(),
-            osc_1: oscillators::SquareWaveGenerator::new(sample_rate, true),
-            osc_2: oscillators::SquareWaveGenerator::new(sample_rate, false),
-            osc_3: oscillators::WaveTable::new(sample_rate),
-            osc_4: oscillators::NoiseGenerator::new(sample_rate),
-            ..Default::default()
-        }))
+            num_channels,
+            volume: 1.0,
+            muted: [false; 4],
+            capacitors: [0.0; 2],
+            resampler: Resampler::default(),
+            dots: 0.0,
+            sum: (0.0, 0.0),
+            previous: (0.0, 0.0),
+            sinc: SincFilter::default(),
+            #[cfg(any(unix, windows))]
+            recorder: None,
+            samples: queue.ring.clone(),
+            queue: Some(queue),
+        }
+    }
+
+    fn output(&self, channel: usize, sample: f32) -> f32 {
+        if self.muted[channel] { 0.0 } else { sample }
+    }
+
+    /// Adds the mix as it was for the last `dots`, returning the next sample of the output stream
+    /// once a whole sample period has gone by.
+    fn resample(&mut self, mix: (f32, f32), dots: f32) -> Option<(f32, f32)> {
+        let period = CLOCK_RATE / self.sample_rate as f32;
+        let sample = match self.resampler {
+            Resampler::Average => self.average(mix, dots, period),
+            Resampler::Linear => self.interpolate(mix, dots, period),
+            Resampler::Sinc => {
+                let sample = self.average(mix, dots, period / OVERSAMPLING as f32)?;
+                self.sinc.push(sample)
+            }
+        }?;
+        Some(self.finish(sample))
+    }
+
+    /// The average of the mix over each `period`, which keeps frequencies above what the output
+    /// rate can hold from aliasing.
+    fn average(&mut self, (left, right): (f32, f32), dots: f32, period: f32) -> Option<(f32, f32)> {
+        self.dots += dots;
+        self.sum = (self.sum.0 + left * dots, self.sum.1 + right * dots);
+        if self.dots < period {
+            return None;
+        }
+
+        // The dots past the end of the period belong to the next sample
+        let overflow = self.dots - period;
+        let sample = ((self.sum.0 - left * overflow) / period, (self.sum.1 - right * overflow) / period);
+        self.dots = overflow;
+        self.sum = (left * overflow, right * overflow);
+        Some(sample)
+    }
+
+    /// The mix at the point each `period` ends, interpolated between the previous M-cycle's and
+    /// this one's.
+    fn interpolate(&mut self, mix: (f32, f32), dots: f32, period: f32) -> Option<(f32, f32)> {
+        let previous = replace(&mut self.previous, mix);
+        self.dots += dots;
+        if self.dots < period {
+            return None;
+        }
+
+        self.dots -= period;
+        // How far into this M-cycle the period ended
+        let t = 1.0 - self.dots / dots;
+        Some((previous.0 + (mix.0 - previous.0) * t, previous.1 + (mix.1 - previous.1) * t))
+    }
+
+    /// High-pass filters the sample, scales it by the master volume and copies it to the
+    /// recording if any.
+    fn finish(&mut self, sample: (f32, f32)) -> (f32, f32) {
+        let (left, right) = self.high_pass(sample);
+        let (left, right) = (left * self.volume, right * self.volume);
+
+        #[cfg(any(unix, windows))]
+        if let Some(recorder) = &mut self.recorder {
+            let written = if self.num_channels == 1 {
+                recorder.write_sample((left + right) / 2.0)
+            } else {
+                recorder.write_sample(left).and_then(|_| recorder.write_sample(right))
+            };
+            if let Err(e) = written {
+                Logger::error(format!("Audio recording stopped: {e}"));
+                self.recorder = None;
+            }
+        }
+
+        (left, right)
+    }
+
+    /// Passes the mix through the capacitors, which let the waveform through but slowly charge up
+    /// to any constant offset so that silence settles at 0.0 whatever the DAC levels are.
+    fn high_pass(&mut self, (left, right): (f32, f32)) -> (f32, f32) {
+        let charge = HIGH_PASS_CHARGE.powf(CLOCK_RATE / self.sample_rate as f32);
+        let filter = |sample: f32, capacitor: &mut f32| {
+            let output = sample - *capacitor;
+            *capacitor = sample - output * charge;
+            output
+        };
+        (filter(left, &mut self.capacitors[0]), filter(right, &mut self.capacitors[1]))
     }
+}
 
-    pub(crate) fn load_stream(processor: &Arc<Mutex<AudioProcessingState>>) -> Option<Stream> {
-        let audio_callback_ref = processor.clone();
-        let audio_error_ref = processor.clone();
+/// Samples the emulation mixed and the output stream hasn't played yet. The emulation thread
+/// writes them as the channels run and the stream's callback reads them, neither of them waiting
+/// on a lock. When the emulation runs ahead of real time the oldest unplayed samples get
+/// overwritten, which keeps the latency bounded.
+struct SampleRing {
+    /// Left and right samples, packed as the bits of two f32s
+    samples: Box<[AtomicU64]>,
+    /// Samples written and read since the ring was created, only ever growing
+    written: AtomicUsize,
+    read: AtomicUsize,
+    /// Times the stream ran dry and had to wait for the emulation
+    underruns: AtomicUsize,
+}
 
-        let config = Self::load_config();
-        let out_dev = cpal::default_host().default_output_device().expect("No available output device found");
+impl SampleRing {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: (0..capacity.max(1)).map(|_| AtomicU64::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            underruns: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called from the emulation thread only.
+    fn push(&self, (left, right): (f32, f32)) {
+        let written = self.written.load(Ordering::Relaxed);
+        let bits = (left.to_bits() as u64) << 32 | right.to_bits() as u64;
+        self.samples[written % self.samples.len()].store(bits, Ordering::Relaxed);
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    /// Called from the stream's callback only.
+    fn pop(&self) -> Option<(f32, f32)> {
+        let written = self.written.load(Ordering::Acquire);
+        // Skip whatever the emulation already wrote over
+        let read = self.read.load(Ordering::Relaxed).max(written.saturating_sub(self.samples.len()));
+        if read == written {
+            return None;
+        }
+        let bits = self.samples[read % self.samples.len()].load(Ordering::Relaxed);
+        self.read.store(read + 1, Ordering::Release);
+        Some((f32::from_bits((bits >> 32) as u32), f32::from_bits(bits as u32)))
+    }
+
+    fn len(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        (written - self.read.load(Ordering::Acquire).min(written)).min(self.samples.len())
+    }
+}
+
+/// The output stream's end of the sample ring. The emulation runs in bursts of a frame, so the
+/// stream waits for a couple of frames' worth of samples whenever it runs dry, playing silence
+/// in the meantime, rather than playing each burst as it comes.
+struct SampleQueue {
+    ring: Arc<SampleRing>,
+    num_channels: u16,
+    /// Samples held back before playing resumes after running dry
+    latency: usize,
+    buffering: bool,
+}
+
+impl Default for SampleQueue {
+    fn default() -> Self {
+        Self::with_format(44100, 2)
+    }
+}
+
+impl SampleQueue {
+    /// A queue over a new ring, holding up to an eighth of a second of samples.
+    fn with_format(sample_rate: u32, num_channels: u16) -> Self {
+        Self {
+            ring: Arc::new(SampleRing::new(sample_rate as usize / 8)),
+            num_channels,
+            latency: sample_rate as usize / 30,
+            buffering: true,
+        }
+    }
+
+    fn pop(&mut self) -> (f32, f32) {
+        if self.buffering && self.ring.len() >= self.latency {
+            self.buffering = false;
+        }
+        if self.buffering {
+            return (0.0, 0.0);
+        }
+
+        self.ring.pop().unwrap_or_else(|| {
+            self.buffering = true;
+            self.ring.underruns.fetch_add(1, Ordering::Relaxed);
+            (0.0, 0.0)
+        })
+    }
+
+    /// Builds a stream on the default output device playing from `queue`. Losing the device
+    /// afterwards sets `lost`.
+    #[cfg(feature = "frontend")]
+    fn load_stream(mut queue: SampleQueue, config: SupportedStreamConfig, lost: Arc<AtomicBool>) -> Option<Stream> {
+        let out_dev = cpal::default_host().default_output_device()?;
+        let on_error = move |error: StreamError| {
+            if let StreamError::DeviceNotAvailable = error {
+                lost.store(true, Ordering::Relaxed);
+            }
+            Self::audio_error(error);
+        };
 
         let stream = match config.sample_format() {
-            cpal::SampleFormat::F32 => out_dev.build_output_stream(&StreamConfig::from(config), move |audio, _| audio_callback_ref.lock().unwrap().audio_block_f32(audio), move |stream_error| audio_error_ref.lock().unwrap().audio_error(stream_error), None),
-            cpal::SampleFormat::I16 => out_dev.build_output_stream(&StreamConfig::from(config), move |audio, _| audio_callback_ref.lock().unwrap().audio_block_i16(audio), move |stream_error| audio_error_ref.lock().unwrap().audio_error(stream_error), None),
-            cpal::SampleFormat::U16 => out_dev.build_output_stream(&StreamConfig::from(config), move |audio, _| audio_callback_ref.lock().unwrap().audio_block_u16(audio), move |stream_error| audio_error_ref.lock().unwrap().audio_error(stream_error), None),
-            _unsupported => panic!("Unsupported stream format: {_unsupported}")
+            cpal::SampleFormat::F32 => out_dev.build_output_stream(&StreamConfig::from(config), move |audio, _| queue.audio_block_f32(audio), on_error, None),
+            cpal::SampleFormat::I16 => out_dev.build_output_stream(&StreamConfig::from(config), move |audio, _| queue.audio_block_i16(audio), on_error, None),
+            cpal::SampleFormat::U16 => out_dev.build_output_stream(&StreamConfig::from(config), move |audio, _| queue.audio_block_u16(audio), on_error, None),
+            unsupported => {
+                Logger::error(format!("Unsupported stream format: {unsupported}"));
+                return None;
+            }
         };
 
         if let Err(ref error) = stream {
@@ -962,22 +1039,163 @@ impl AudioProcessingState {
         stream.ok()
     }
 
-    fn load_config() -> SupportedStreamConfig {
+    /// The output device's config closest to 44100Hz, or to `sample_rate` if requested and
+    /// supported. None if there's no output device to play on.
+    #[cfg(feature = "frontend")]
+    fn load_config(sample_rate: Option<u32>) -> Option<SupportedStreamConfig> {
         // Setup audio interfacing
-        let out_dev = cpal::default_host().default_output_device().expect("No available output device found");
+        let Some(out_dev) = cpal::default_host().default_output_device() else {
+            Logger::error("No available output device found, running muted");
+            return None;
+        };
+
+        // Display device name
+        if let Ok(name) = out_dev.name() {
+            Logger::info(format!("Using {name}"))
+        }
+
+        let supported_configs: Vec<_> = match out_dev.supported_output_configs() {
+            Ok(configs) => configs.collect(),
+            Err(e) => {
+                Logger::error(format!("Could not obtain device configs: {e}"));
+                return None;
+            }
+        };
+
+        let requested = sample_rate.map(SampleRate).and_then(|rate| {
+            let config = supported_configs.iter().find(|c| (c.min_sample_rate()..=c.max_sample_rate()).contains(&rate));
+            if config.is_none() {
+                Logger::error(format!("The output device doesn't support {}Hz, falling back to the default rate", rate.0));
+            }
+            config.map(|c| c.with_sample_rate(rate))
+        });
+
+        let config = requested.or_else(|| {
+            supported_configs
+                .iter()
+                .find(|c| c.max_sample_rate() >= SampleRate(44100))
+                .or(supported_configs.first())
+                .map(|a| {
+                    let rate = a.max_sample_rate();
+                    a.with_sample_rate(min(SampleRate(44100), rate))
+                })
+        });
+        match &config {
+            Some(config) => Logger::info(format!("Playing at {}Hz with {} channels", config.sample_rate().0, config.channels())),
+            None => Logger::error("No valid audio config found, running muted"),
+        }
+        config
+    }
+
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    fn audio_block_f32(&mut self, audio: &mut [f32]) {
+        let num_samples = audio.len() / self.num_channels as usize;
+
+        for sample_index in 0..num_samples {
+            let generated_samples = self.pop();
 
-        let mut supported_configs_range = out_dev.supported_output_configs().expect("Could not obtain device configs");
+            let first_channel_index = sample_index * self.num_channels as usize;
+
+            match self.num_channels.cmp(&1) {
+                cmp::Ordering::Equal => audio[first_channel_index] = (generated_samples.0 + generated_samples.1) / 2.0,
+                cmp::Ordering::Greater => {
+                    audio[first_channel_index] = generated_samples.0;
+                    audio[first_channel_index + 1] = generated_samples.1;
+                }
+                cmp::Ordering::Less => (),
+            }
+        }
+    }
 
-        supported_configs_range
-            .find(|c| c.max_sample_rate() >= SampleRate(44100))
-            .or(supported_configs_range.next())
-            .map(|a| {
-                let rate = a.max_sample_rate();
-                a.with_sample_rate(min(SampleRate(44100), rate))
-            }).expect("No valid audio config found.")
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    fn audio_block_i16(&mut self, audio: &mut [i16]) {
+        let num_samples = audio.len() / self.num_channels as usize;
+
+        for sample_index in 0..num_samples {
+            let f32_samples = self.pop();
+
+            let left_sample = (f32_samples.0 * i16::MAX as f32) as i16;
+            let right_sample = (f32_samples.1 * i16::MAX as f32) as i16;
+
+            let first_channel_index = sample_index * self.num_channels as usize;
+
+            match self.num_channels.cmp(&1) {
+                cmp::Ordering::Equal => audio[first_channel_index] = (left_sample + right_sample) / 2,
+                cmp::Ordering::Greater => {
+                    audio[first_channel_index] = left_sample;
+                    audio[first_channel_index + 1] = right_sample;
+                }
+                cmp::Ordering::Less => (),
+            }
+        }
+    }
+
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    fn audio_block_u16(&mut self, audio: &mut [u16]) {
+        let num_samples = audio.len() / self.num_channels as usize;
+
+        for sample_index in 0..num_samples {
+            let f32_samples = self.pop();
+
+            let left_sample = ((f32_samples.0 + 1.0) * u16::MAX as f32) as u16;
+            let right_sample = ((f32_samples.1 + 1.0) * u16::MAX as f32) as u16;
+
+            let first_channel_index = sample_index * self.num_channels as usize;
+
+            match self.num_channels.cmp(&1) {
+                cmp::Ordering::Equal => audio[first_channel_index] = (left_sample + right_sample) / 2,
+                cmp::Ordering::Greater => {
+                    audio[first_channel_index] = left_sample;
+                    audio[first_channel_index + 1] = right_sample;
+                }
+                cmp::Ordering::Less => (),
+            }
+        }
+    }
+
+    #[cfg(feature = "frontend")]
+    fn audio_error(error: StreamError) {
+        Logger::error(format!("Audio Error: {:?}", error));
+    }
+}
+
+impl AudioProcessingState {
+    /// Bits that always read back as 1 for each register from NR10 (FF10) to NR52 (FF26),
+    /// either because they are unused or because they are write-only.
+    const READ_MASKS: [u8; 0x17] = [
+        0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+        0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+        0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+        0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+        0x00, 0x00, 0x70, // NR50-NR52
+    ];
+
+    fn new() -> AudioProcessingState {
+        AudioProcessingState {
+            osc_1: oscillators::SquareWaveGenerator::new(true),
+            osc_2: oscillators::SquareWaveGenerator::new(false),
+            osc_3: oscillators::WaveTable::default(),
+            osc_4: oscillators::NoiseGenerator::default(),
+            left_osc_enable: [false; 4],
+            right_osc_enable: [false; 4],
+            left_master_vol: 0,
+            right_master_vol: 0,
+            power_control: false,
+            sequencer_timer: 0,
+            sequencer_step: 0,
+        }
     }
 
     pub(crate) fn write_register(&mut self, address: usize, value: u8) {
+        // Powered off, only NR52, wave RAM and the length timers (as on DMG) accept writes
+        let value = match address {
+            _ if self.power_control || address >= 0xFF26 => value,
+            // Keep the duty cycle sharing a register with the length
+            0xFF11 | 0xFF16 => self.read_raw_register(address) & 0xC0 | value & 0x3F,
+            0xFF1B | 0xFF20 => value,
+            _ => return,
+        };
+
         if address < 0xFF24 {
             let rel_address = address - 0xFF10;
 
@@ -1016,7 +1234,11 @@ impl AudioProcessingState {
                 }
 
                 0xFF26 => {
-                    self.power_control = (value >> 7) > 0;
+                    let power_control = (value >> 7) > 0;
+                    if self.power_control && !power_control {
+                        self.power_off();
+                    }
+                    self.power_control = power_control;
                 }
 
                 _ => {
@@ -1026,7 +1248,24 @@ impl AudioProcessingState {
         }
     }
 
+    /// Clears every register from NR10 to NR51, wave RAM keeps its contents.
+    fn power_off(&mut self) {
+        let wave_ram: Vec<u8> = (0xFF30..=0xFF3F).map(|address| self.osc_3.read_sound_data(address)).collect();
+        *self = Self::new();
+        for (address, value) in (0xFF30..=0xFF3F).zip(wave_ram) {
+            self.osc_3.write_sound_data(address, value);
+        }
+    }
+
     pub(crate) fn read_register(&self, address: usize) -> u8 {
+        match address {
+            0xFF30..=0xFF3F => self.osc_3.read_sound_data(address),
+            0xFF27..=0xFF2F => 0xFF,
+            _ => self.read_raw_register(address) | Self::READ_MASKS[address - 0xFF10],
+        }
+    }
+
+    fn read_raw_register(&self, address: usize) -> u8 {
         if address < 0xFF24 {
             let rel_address = address - 0xFF10;
 
@@ -1054,8 +1293,6 @@ impl AudioProcessingState {
                     0x00
                 }
             }
-        } else if (0xFF30..=0xFF3F).contains(&address) {
-            self.osc_3.read_sound_data(address)
         } else {
             match address {
                 0xFF24 => {
@@ -1094,74 +1331,45 @@ impl AudioProcessingState {
         }
     }
 
-    fn audio_block_f32(&mut self, audio: &mut [f32]) {
-        let num_samples = audio.len() / self.num_channels as usize;
-
-        for sample_index in 0..num_samples {
-            let generated_samples = self.generate_samples();
-
-            let first_channel_index = sample_index * self.num_channels as usize;
-
-            match self.num_channels.cmp(&1) {
-                cmp::Ordering::Equal => audio[first_channel_index] = (generated_samples.0 + generated_samples.1) / 2.0,
-                cmp::Ordering::Greater => {
-                    audio[first_channel_index] = generated_samples.0;
-                    audio[first_channel_index + 1] = generated_samples.1;
-                }
-                cmp::Ordering::Less => (),
-            }
+    /// Runs the channels for `dots` 4 MiHz clocks, stepping the frame sequencer every 8192 of
+    /// them: the length counters on even steps, the sweep on steps 2 and 6 and the envelopes on
+    /// step 7.
+    fn machine_cycle(&mut self, dots: u32) {
+        if !self.power_control {
+            return;
         }
-    }
 
-    fn audio_block_i16(&mut self, audio: &mut [i16]) {
-        let num_samples = audio.len() / self.num_channels as usize;
-
-        for sample_index in 0..num_samples {
-            let f32_samples = self.generate_samples();
+        self.osc_1.step(dots);
+        self.osc_2.step(dots);
+        self.osc_3.step(dots);
+        self.osc_4.step(dots);
 
-            let left_sample = (f32_samples.0 * i16::MAX as f32) as i16;
-            let right_sample = (f32_samples.1 * i16::MAX as f32) as i16;
-
-            let first_channel_index = sample_index * self.num_channels as usize;
-
-            match self.num_channels.cmp(&1) {
-                cmp::Ordering::Equal => audio[first_channel_index] = (left_sample + right_sample) / 2,
-                cmp::Ordering::Greater => {
-                    audio[first_channel_index] = left_sample;
-                    audio[first_channel_index + 1] = right_sample;
-                }
-                cmp::Ordering::Less => (),
-            }
+        self.sequencer_timer += dots;
+        if self.sequencer_timer < SEQUENCER_PERIOD {
+            return;
         }
-    }
-
-    fn audio_block_u16(&mut self, audio: &mut [u16]) {
-        let num_samples = audio.len() / self.num_channels as usize;
-
-        for sample_index in 0..num_samples {
-            let f32_samples = self.generate_samples();
-
-            let left_sample = ((f32_samples.0 + 1.0) * u16::MAX as f32) as u16;
-            let right_sample = ((f32_samples.1 + 1.0) * u16::MAX as f32) as u16;
-
-            let first_channel_index = sample_index * self.num_channels as usize;
-
-            match self.num_channels.cmp(&1) {
-                cmp::Ordering::Equal => audio[first_channel_index] = (left_sample + right_sample) / 2,
-                cmp::Ordering::Greater => {
-                    audio[first_channel_index] = left_sample;
-                    audio[first_channel_index + 1] = right_sample;
-                }
-                cmp::Ordering::Less => (),
-            }
+        self.sequencer_timer -= SEQUENCER_PERIOD;
+
+        let step = self.sequencer_step;
+        self.sequencer_step = (step + 1) % 8;
+        if step & 1 == 0 {
+            self.osc_1.clock_length();
+            self.osc_2.clock_length();
+            self.osc_3.clock_length();
+            self.osc_4.clock_length();
+        }
+        if step == 2 || step == 6 {
+            self.osc_1.clock_sweep();
+        }
+        if step == 7 {
+            self.osc_1.clock_envelope();
+            self.osc_2.clock_envelope();
+            self.osc_4.clock_envelope();
         }
     }
 
-    fn audio_error(&self, error: StreamError) {
-        Logger::error(format!("Audio Error: {:?}", error));
-    }
-
-    fn generate_samples(&mut self) -> (f32, f32) {
+    /// The analog output of the channels at this point, panned and scaled by the master volumes.
+    fn mix(&self, mixer: &Mixer) -> (f32, f32) {
         if !self.power_control {
             return (0.0, 0.0);
         }
@@ -1169,7 +1377,7 @@ impl AudioProcessingState {
         let mut mixed_left_sample = self.left_master_vol as f32 / 15.0;
         let mut mixed_right_sample = self.right_master_vol as f32 / 15.0;
 
-        let osc_1_sample = self.osc_1.generate_sample();
+        let osc_1_sample = mixer.output(0, self.osc_1.dac_output());
         if self.left_osc_enable[0] {
             mixed_left_sample += osc_1_sample;
         }
@@ -1177,7 +1385,7 @@ impl AudioProcessingState {
             mixed_right_sample += osc_1_sample;
         }
 
-        let osc_2_sample = self.osc_2.generate_sample();
+        let osc_2_sample = mixer.output(1, self.osc_2.dac_output());
         if self.left_osc_enable[1] {
             mixed_left_sample += osc_2_sample;
         }
@@ -1185,7 +1393,7 @@ impl AudioProcessingState {
             mixed_right_sample += osc_2_sample;
         }
 
-        let osc_3_sample = self.osc_3.generate_sample();
+        let osc_3_sample = mixer.output(2, self.osc_3.dac_output());
         if self.left_osc_enable[2] {
             mixed_left_sample += osc_3_sample;
         }
@@ -1193,7 +1401,7 @@ impl AudioProcessingState {
             mixed_right_sample += osc_3_sample;
         }
 
-        let osc_4_sample = self.osc_4.generate_sample();
+        let osc_4_sample = mixer.output(3, self.osc_4.dac_output());
         if self.left_osc_enable[3] {
             mixed_left_sample += osc_4_sample;
         }
@@ -1214,27 +1422,209 @@ impl AudioProcessingState {
 #[allow(dead_code)]
 #[derive(Serialize, Deserialize, Default)]
 pub struct AudioProcessingUnit {
-    state: Arc<Mutex<AudioProcessingState>>,
+    state: AudioProcessingState,
     #[serde(skip)]
-    pub(crate) stream: Option<Stream>,
+    mixer: Mixer,
+    #[cfg(feature = "frontend")]
+    #[serde(skip)]
+    pub stream: Option<Stream>,
+    /// Whether the APU plays through an output device, rather than being silent on purpose
+    #[cfg(feature = "frontend")]
+    #[serde(skip)]
+    output: bool,
+    /// Set by the stream when its device goes away
+    #[cfg(feature = "frontend")]
+    #[serde(skip)]
+    lost: Arc<AtomicBool>,
+    /// Sample rate asked for by the user, asked for again whenever the stream is reopened
+    #[cfg(feature = "frontend")]
+    #[serde(skip)]
+    requested_rate: Option<u32>,
 }
 
 impl AudioProcessingUnit {
+    #[cfg(feature = "frontend")]
+    pub(crate) fn new() -> AudioProcessingUnit {
+        let mut apu = Self::silent();
+        apu.init();
+        apu
+    }
+
+    /// Headless builds have no audio output to open.
+    #[cfg(not(feature = "frontend"))]
     pub(crate) fn new() -> AudioProcessingUnit {
-        let state = AudioProcessingState::new();
-        let stream = AudioProcessingState::load_stream(&state);
-        AudioProcessingUnit { state, stream }
+        Self::silent()
     }
 
+    /// Emulates the APU without looking for an output device or opening a stream.
+    pub(crate) fn silent() -> AudioProcessingUnit {
+        AudioProcessingUnit::default()
+    }
+
+    /// Opens the output stream, mixing for its format from now on.
     pub(crate) fn init(&mut self) {
-        self.stream = AudioProcessingState::load_stream(&self.state);
+        #[cfg(feature = "frontend")]
+        self.open_stream(None);
+    }
+
+    /// Opens the output stream at the device's default rate or the requested one. Returns whether
+    /// it opened, without an output device the APU runs muted.
+    #[cfg(feature = "frontend")]
+    fn open_stream(&mut self, sample_rate: Option<u32>) -> bool {
+        // The device may not take a second stream while the first is open
+        self.stream = None;
+        self.output = true;
+        self.lost = Arc::new(AtomicBool::new(false));
+        let Some(config) = SampleQueue::load_config(sample_rate) else {
+            return false;
+        };
+        self.set_format(config.sample_rate().0, config.channels());
+        let queue = self.mixer.queue.take().unwrap();
+        self.stream = SampleQueue::load_stream(queue, config, self.lost.clone());
+        self.stream.is_some()
+    }
+
+    /// Whether the output device went away, or there was none to open the stream on. Nothing
+    /// plays until `reinit_audio` finds one.
+    #[cfg(feature = "frontend")]
+    pub fn audio_lost(&self) -> bool {
+        self.output && (self.stream.is_none() || self.lost.load(Ordering::Relaxed))
+    }
+
+    /// Reopens the output stream on the current default device, at its default sample rate or
+    /// the requested one. Returns whether it opened, the new stream starts paused.
+    #[cfg(feature = "frontend")]
+    pub fn reinit_audio(&mut self) -> bool {
+        // Checked first so that looking for a device while there's none doesn't log every time
+        if cpal::default_host().default_output_device().is_none() {
+            self.stream = None;
+            return false;
+        }
+        // Recordings are written at the rate mixed at, so they keep it if the new device has it
+        #[cfg(any(unix, windows))]
+        let sample_rate = if self.mixer.recorder.is_some() { Some(self.mixer.sample_rate) } else { self.requested_rate };
+        #[cfg(not(any(unix, windows)))]
+        let sample_rate = self.requested_rate;
+        let opened = self.open_stream(sample_rate);
+        if let Some(stream) = &self.stream {
+            stream.pause().ok();
+        }
+        opened
+    }
+
+    /// Mixes for `sample_rate` and `num_channels` from now on, into a new ring.
+    fn set_format(&mut self, sample_rate: u32, num_channels: u16) {
+        let queue = SampleQueue::with_format(sample_rate, num_channels);
+        self.mixer.sample_rate = sample_rate;
+        self.mixer.num_channels = num_channels;
+        self.mixer.samples = queue.ring.clone();
+        self.mixer.queue = Some(queue);
+    }
+
+    /// Takes over the channels of a loaded save state, the output stream keeps playing from them.
+    pub(crate) fn restore(&mut self, loaded: &mut AudioProcessingUnit) {
+        swap(&mut self.state, &mut loaded.state);
+    }
+
+    /// Runs the channels for an M-cycle of `dots` 4 MiHz clocks, 2 of them in double speed.
+    pub(crate) fn machine_cycle(&mut self, dots: u16) {
+        self.state.machine_cycle(dots as u32);
+        let mix = self.state.mix(&self.mixer);
+        if let Some(sample) = self.mixer.resample(mix, dots as f32) {
+            self.mixer.samples.push(sample);
+        }
+    }
+
+    /// Mixes at `sample_rate` from now on, reopening the output stream at that rate if one is
+    /// open. Rates the output device doesn't support fall back to its default with a warning.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        #[cfg(feature = "frontend")]
+        {
+            self.requested_rate = Some(sample_rate);
+            if self.stream.is_some() {
+                self.open_stream(Some(sample_rate));
+                return;
+            }
+        }
+        self.set_format(sample_rate, self.mixer.num_channels);
+    }
+
+    pub fn set_resampler(&mut self, resampler: Resampler) {
+        self.mixer.resampler = resampler;
+        self.mixer.dots = 0.0;
+        self.mixer.sum = (0.0, 0.0);
+        self.mixer.sinc = SincFilter::default();
+    }
+
+    /// Scales the output from 0.0 (silent) to 1.0 (full volume).
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.mixer.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Starts copying the output to a WAV file at the output device's sample rate, replacing any
+    /// recording in progress.
+    #[cfg(any(unix, windows))]
+    pub fn start_recording(&mut self, path: &str) -> Result<(), String> {
+        let spec = WavSpec {
+            channels: self.mixer.num_channels.min(2),
+            sample_rate: self.mixer.sample_rate,
+            bits_per_sample: 32,
+            sample_format: SampleFormat::Float,
+        };
+        self.mixer.recorder = Some(WavWriter::create(path, spec).map_err(|e| e.to_string())?);
+        Ok(())
+    }
+
+    /// Stops recording and finishes the WAV file. Returns whether a recording was in progress.
+    #[cfg(any(unix, windows))]
+    pub fn stop_recording(&mut self) -> bool {
+        let Some(recorder) = self.mixer.recorder.take() else {
+            return false;
+        };
+        if let Err(e) = recorder.finalize() {
+            Logger::error(format!("Unable to finish audio recording: {e}"));
+        }
+        true
+    }
+
+    /// Mutes or unmutes channel `n` (1 to 4) regardless of the game's panning. Returns whether
+    /// it's muted now.
+    pub fn toggle_channel(&mut self, n: usize) -> bool {
+        self.mixer.muted[n - 1] = !self.mixer.muted[n - 1];
+        self.mixer.muted[n - 1]
+    }
+
+    /// Fills `audio` with interleaved stereo samples the way the output stream would, as long as
+    /// no output stream was opened.
+    #[cfg(test)]
+    pub(crate) fn render(&mut self, audio: &mut [f32]) {
+        if let Some(queue) = &mut self.mixer.queue {
+            queue.audio_block_f32(audio);
+        }
+    }
+
+    /// Samples mixed but not played yet, out of the eighth of a second the ring holds. Hovering
+    /// near empty means the emulation is falling behind the output, near full that it's running
+    /// ahead.
+    pub fn buffered_samples(&self) -> usize {
+        self.mixer.samples.len()
+    }
+
+    /// Times the output ran out of samples and played silence while the emulation caught up.
+    pub fn underruns(&self) -> usize {
+        self.mixer.samples.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Returns every channel to its power-on state while keeping the output stream alive.
+    pub(crate) fn reset(&mut self) {
+        self.state = AudioProcessingState::new();
     }
 
     pub(crate) fn write(&mut self, address: usize, value: u8) -> bool {
         if !(0xFF10..=0xFF3F).contains(&address) {
             false
         } else {
-            self.state.lock().unwrap().write_register(address, value);
+            self.state.write_register(address, value);
             true
         }
     }
@@ -1243,7 +1633,7 @@ impl AudioProcessingUnit {
         if !(0xFF10..=0xFF3F).contains(&address) {
             None
         } else {
-            Some(self.state.lock().unwrap().read_register(address))
+            Some(self.state.read_register(address))
         }
     }
-}
\ No newline at end of file
+}
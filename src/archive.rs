@@ -0,0 +1,58 @@
+use std::io::{Cursor, Read};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Extracts the ROM from a zip (its first .gb or .gbc file) or gzip archive, told apart by their
+/// magic bytes. Returns the file under the archive's path with the extension of the file inside,
+/// so saves land next to the archive. Anything else is returned as is.
+pub fn unpack(path: &str, data: Vec<u8>) -> Result<(String, Vec<u8>), String> {
+    if data.starts_with(&ZIP_MAGIC) {
+        let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(|e| e.to_string())?;
+            let name = file.name().to_lowercase();
+            if file.is_file() && (name.ends_with(".gb") || name.ends_with(".gbc")) {
+                let mut rom = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut rom).map_err(|e| e.to_string())?;
+                return Ok((with_extension(strip_extension(path), &name), rom));
+            }
+        }
+        Err("The archive has no .gb or .gbc file".to_string())
+    } else if data.starts_with(&GZIP_MAGIC) {
+        let mut decoder = GzDecoder::new(&data[..]);
+        let mut rom = vec![];
+        decoder.read_to_end(&mut rom).map_err(|e| e.to_string())?;
+        // game.gb.gz keeps its inner extension, game.gz takes the one it was compressed with
+        let stem = strip_extension(path);
+        let name = if strip_extension(stem) != stem {
+            stem.to_string()
+        } else {
+            match decoder.header().and_then(|header| header.filename()) {
+                Some(name) => with_extension(stem, &String::from_utf8_lossy(name).to_lowercase()),
+                None => format!("{stem}.gb"),
+            }
+        };
+        Ok((name, rom))
+    } else {
+        Ok((path.to_string(), data))
+    }
+}
+
+/// The path without the extension of its file name, if it has one.
+fn strip_extension(path: &str) -> &str {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains(['/', '\\']) => stem,
+        _ => path,
+    }
+}
+
+fn with_extension(stem: &str, name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((_, extension)) => format!("{stem}.{extension}"),
+        None => stem.to_string(),
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs::write;
+use std::thread::{self, JoinHandle};
+
+use instant::{Duration, Instant};
+use iron_boy::{Gameboy, Logger};
+
+/// Writes the battery save on a timer while the game keeps changing it, so a crash loses at most
+/// an interval of progress. Only the cartridge RAM is written, not the full state.
+pub struct Autosave {
+    path: String,
+    interval: Duration,
+    last: Instant,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Autosave {
+    pub fn new(path: String, interval: Duration) -> Self {
+        Self { path, interval, last: Instant::now(), writer: None }
+    }
+
+    /// Called after every frame, starts writing the battery save once the interval is up if the
+    /// game wrote to its RAM since the last save.
+    pub fn tick(&mut self, gameboy: &mut Gameboy) {
+        if self.last.elapsed() < self.interval || self.writer.as_ref().is_some_and(|writer| !writer.is_finished()) {
+            return;
+        }
+        self.last = Instant::now();
+        if let Some(ram) = gameboy.mmu.unsaved_battery_ram() {
+            let path = self.path.clone();
+            self.writer = Some(thread::spawn(move || write_ram(&path, ram)));
+        }
+    }
+
+    /// Waits for the battery save being written, before a full save writes it as well. The
+    /// interval starts over, since the full save is just as recent.
+    pub fn wait(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.join().unwrap();
+        }
+        self.last = Instant::now();
+    }
+
+    /// Writes whatever the game changed since the last save, before exiting.
+    pub fn flush(&mut self, gameboy: &mut Gameboy) {
+        self.wait();
+        if let Some(ram) = gameboy.mmu.unsaved_battery_ram() {
+            write_ram(&self.path, ram);
+        }
+    }
+}
+
+fn write_ram(path: &str, ram: Vec<u8>) {
+    if let Err(e) = write(path, ram) {
+        Logger::error(format!("Unable to autosave battery save {path}: {e}"));
+    }
+}
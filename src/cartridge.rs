@@ -7,11 +7,15 @@ use crate::logger::Logger;
 pub struct Cartridge {
     pub(crate) title: Option<String>,
     publisher: Option<String>,
-    pub(crate) mbc: u8,
+    pub mbc: u8,
     pub(crate) rom_size: usize,
     pub(crate) rom_bank_count: u16,
     pub(crate) ram_bank_count: u8,
     pub(crate) ram_size: u8,
+    /// Whether the cartridge supports the Game Boy Color, from bit 7 of 0x143
+    pub(crate) cgb: bool,
+    /// Whether the cartridge has Super Game Boy functions, from 0x146
+    sgb: bool,
     destination: u8,
     old_publisher: u8,
     rom_version: u8,
@@ -20,27 +24,45 @@ pub struct Cartridge {
 }
 
 impl Cartridge {
-    pub fn new(rom: &[u8]) -> Self {
-        let title: Vec<u8> = rom[0x134..=0x143]
+    /// The name in the header's title bytes, ending at the zero padding or at the CGB flag newer
+    /// games keep in the last byte. None if there's nothing printable.
+    pub(crate) fn parse_title(bytes: &[u8]) -> Option<String> {
+        let title: String = bytes
             .iter()
-            .copied()
-            .take_while(|c| *c != 0)
+            .take_while(|c| c.is_ascii_graphic() || **c == b' ')
+            .map(|c| *c as char)
             .collect();
+        let title = title.trim();
+        (!title.is_empty()).then(|| title.to_string())
+    }
+
+    /// Parses the header, failing for ROMs too short to have one or with a ROM or RAM size it
+    /// doesn't know.
+    pub fn new(rom: &[u8]) -> Result<Self, String> {
+        if rom.len() < 0x150 {
+            return Err(format!("{} bytes is too small for a ROM, the header alone ends at 0x150", rom.len()));
+        }
         let s = Self {
-            title: from_utf8(title.as_slice()).map(|t| t.to_string()).ok(),
+            title: Self::parse_title(&rom[0x134..=0x143]),
             publisher: from_utf8(&rom[0x144..=0x145]).map(|t| t.to_string()).ok(),
             mbc: rom[0x147],
-            rom_size: 32 << rom[0x148],
-            rom_bank_count: 2_u16.pow(rom[0x148] as u32 + 1) as u16,
+            rom_size: match rom[0x148] {
+                size @ 0x00..=0x08 => 32 << size,
+                size => return Err(format!("unknown ROM size {size:#04X} in the header")),
+            },
+            rom_bank_count: 2 << rom[0x148],
             ram_bank_count: match rom[0x149] {
                 0x00 => 0,
                 0x02 => 1,
                 0x03 => 4,
                 0x04 => 16,
                 0x05 => 8,
-                _ => unreachable!()
+                size => return Err(format!("unknown RAM size {size:#04X} in the header")),
             },
             ram_size: rom[0x149],
+            cgb: rom[0x143] & 0x80 != 0,
+            // The SGB ignores the flag unless the old publisher code defers to the new one
+            sgb: rom[0x146] == 0x03 && rom[0x14B] == 0x33,
             destination: rom[0x14A],
             old_publisher: rom[0x14B],
             rom_version: rom[0x14C],
@@ -48,6 +70,51 @@ impl Cartridge {
             global_checksum: u16::from_be_bytes([rom[0x14E], rom[0x14F]]),
         };
         Logger::info(format!("Cartridge: {s:?}"));
-        s
+        Ok(s)
+    }
+
+    /// Checks the header checksum the boot ROM locks up on, and the global checksum over the whole
+    /// ROM that only cartridge tools look at, logging any mismatch. Returns whether both match, a
+    /// mismatch usually means a bad dump.
+    pub fn verify(&self, rom: &[u8]) -> bool {
+        let header = rom[0x134..=0x14C].iter().fold(0_u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1));
+        let global = rom
+            .iter()
+            .enumerate()
+            .filter(|(address, _)| !(0x14E..=0x14F).contains(address))
+            .fold(0_u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16));
+
+        if header != self.header_checksum {
+            Logger::error(format!(
+                "Header checksum mismatch: computed {header:#04X}, cartridge says {:#04X}",
+                self.header_checksum
+            ));
+        }
+        if global != self.global_checksum {
+            Logger::error(format!(
+                "Global checksum mismatch: computed {global:#06X}, cartridge says {:#06X}",
+                self.global_checksum
+            ));
+        }
+        header == self.header_checksum && global == self.global_checksum
+    }
+
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb
+    }
+
+    pub(crate) fn has_battery(&self) -> bool {
+        matches!(self.mbc, 0x03 | 0x06 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0xFC)
+    }
+
+    /// Size of the external RAM declared in the header.
+    pub(crate) fn ram_bytes(&self) -> usize {
+        self.ram_bank_count as usize * 0x2000
+    }
+
+    /// Where a RAM bank starts in RAM sized by the header. Banks past the end wrap around, as the
+    /// extra bank lines just aren't connected.
+    pub(crate) fn ram_bank_offset(&self, bank: u8) -> usize {
+        bank as usize * 0x2000 % self.ram_bytes().max(0x2000)
     }
 }
@@ -0,0 +1,104 @@
+use crate::logger::Logger;
+
+/// A Game Genie code, replacing a ROM byte whenever the game reads it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct RomPatch {
+    address: u16,
+    value: u8,
+    /// Only patch while the ROM holds this byte, so the code doesn't hit other banks
+    compare: Option<u8>,
+}
+
+/// A GameShark code, forcing a RAM byte once per frame.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct RamWrite {
+    pub(crate) address: u16,
+    pub(crate) value: u8,
+}
+
+/// Game Genie and GameShark codes applied to the running game. They're user settings rather than
+/// emulated state, so they aren't saved and survive resets and state loads.
+#[derive(Default, Debug)]
+pub struct Cheats {
+    patches: Vec<RomPatch>,
+    pub(crate) writes: Vec<RamWrite>,
+}
+
+impl Cheats {
+    /// Adds a Game Genie (`ABC-DEF-GHI`, or `ABC-DEF` without a compare value) or GameShark
+    /// (`01DDAAAA`) code. Malformed codes are logged and skipped, returning false.
+    pub fn add(&mut self, code: &str) -> bool {
+        let parsed = if code.contains('-') {
+            Self::game_genie(code).map(|patch| self.patches.push(patch))
+        } else {
+            Self::game_shark(code).map(|write| self.writes.push(write))
+        };
+        match &parsed {
+            Ok(()) => Logger::info(format!("Added cheat {code}")),
+            Err(e) => Logger::error(format!("Invalid cheat {code}: {e}")),
+        }
+        parsed.is_ok()
+    }
+
+    pub fn clear(&mut self) {
+        self.patches.clear();
+        self.writes.clear();
+    }
+
+    /// The byte the game sees when reading `value` from `address`, with Game Genie codes applied.
+    #[inline]
+    pub(crate) fn patch(&self, address: usize, value: u8) -> u8 {
+        if address >= 0x8000 {
+            return value;
+        }
+        self.patches
+            .iter()
+            .find(|patch| patch.address as usize == address && patch.compare.is_none_or(|compare| compare == value))
+            .map_or(value, |patch| patch.value)
+    }
+
+    /// `ABC-DEF-GHI`: AB is the new value, FCDE the address with its top digit inverted, and GI
+    /// the compare value rotated left by 2 and XORed with 0xBA. H is ignored.
+    fn game_genie(code: &str) -> Result<RomPatch, String> {
+        let groups: Vec<&str> = code.split('-').collect();
+        if !matches!(groups.as_slice(), [_, _] | [_, _, _]) || groups.iter().any(|group| group.len() != 3) {
+            return Err("Game Genie codes look like ABC-DEF-GHI or ABC-DEF".to_string());
+        }
+        let digits = Self::hex_digits(&groups.concat())?;
+
+        let address = ((digits[5] ^ 0xF) as u16) << 12
+            | (digits[2] as u16) << 8
+            | (digits[3] as u16) << 4
+            | digits[4] as u16;
+        if address >= 0x8000 {
+            return Err(format!("{address:#06X} is outside the ROM"));
+        }
+        Ok(RomPatch {
+            address,
+            value: digits[0] << 4 | digits[1],
+            compare: (digits.len() == 9).then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA),
+        })
+    }
+
+    /// `01DDAAAA`: DD is the value and AAAA the little-endian address. The leading type byte picks
+    /// a RAM bank on some devices, here the code writes to whichever bank is mapped.
+    fn game_shark(code: &str) -> Result<RamWrite, String> {
+        if code.len() != 8 {
+            return Err("GameShark codes look like 01DDAAAA".to_string());
+        }
+        let digits = Self::hex_digits(code)?;
+        let byte = |index: usize| digits[index * 2] << 4 | digits[index * 2 + 1];
+
+        let address = u16::from_le_bytes([byte(2), byte(3)]);
+        if !(0xA000..=0xDFFF).contains(&address) {
+            return Err(format!("{address:#06X} is outside cartridge and work RAM"));
+        }
+        Ok(RamWrite { address, value: byte(1) })
+    }
+
+    fn hex_digits(code: &str) -> Result<Vec<u8>, String> {
+        code.chars()
+            .map(|digit| digit.to_digit(16).map(|digit| digit as u8).ok_or(format!("{digit} isn't a hex digit")))
+            .collect()
+    }
+}
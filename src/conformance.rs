@@ -0,0 +1,102 @@
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+use crate::cartridge::Cartridge;
+use crate::emulator::Emulator;
+use crate::gameboy::Gameboy;
+use crate::logger::Logger;
+use crate::mmu::MemoryManagementUnit;
+use crate::register::RegisterId::{B, C, D, E, H, L};
+use crate::CYCLES_PER_FRAME;
+
+/// Emulated time a ROM gets to report its result. The full blargg cpu_instrs needs about a minute.
+const TIMEOUT: u64 = CYCLES_PER_FRAME as u64 * 60 * 120;
+
+/// Software breakpoint mooneye ROMs execute once they're done.
+const LD_B_B: u8 = 0x40;
+
+/// B, C, D, E, H and L after a passing mooneye ROM, the first Fibonacci numbers.
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    Passed,
+    Failed(String),
+    Timeout,
+}
+
+/// Runs a test ROM until it reports its result with one of the usual conventions: blargg ROMs
+/// print "Passed" or "Failed" over the serial port, mooneye ROMs run `LD B,B` with a register
+/// signature.
+pub fn run_rom(emulator: &mut Emulator) -> Verdict {
+    emulator.gameboy.mmu.capture_serial(false);
+
+    let mut cycles = 0;
+    let mut printed = 0;
+    while cycles < TIMEOUT {
+        let gameboy = &emulator.gameboy;
+        let breakpoint =
+            !gameboy.halted && gameboy.peek(gameboy.reg.pc.value()) == LD_B_B;
+        cycles += emulator.step() as u64;
+        let gameboy = &emulator.gameboy;
+
+        if breakpoint {
+            let registers = [B, C, D, E, H, L].map(|id| gameboy[id].value);
+            if registers == MOONEYE_PASS {
+                return Verdict::Passed;
+            } else if registers == MOONEYE_FAIL {
+                return Verdict::Failed("mooneye failure signature".to_string());
+            }
+        }
+
+        let sent = gameboy.mmu.serial.output.as_ref().map_or(0, Vec::len);
+        if sent == printed {
+            continue;
+        }
+        printed = sent;
+        let output = gameboy.mmu.serial_log();
+        if output.contains("Passed") {
+            return Verdict::Passed;
+        } else if output.contains("Failed") {
+            return Verdict::Failed(output.trim().to_string());
+        }
+    }
+    Verdict::Timeout
+}
+
+/// Runs every ROM in `dir` headless, logging a verdict for each. Returns whether all of them passed.
+pub fn run_dir(dir: &Path) -> bool {
+    let mut roms: Vec<_> = read_dir(dir)
+        .expect("Unable to read conformance directory")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "gb"))
+        .collect();
+    roms.sort();
+
+    let mut passed = 0;
+    for rom in &roms {
+        let path = rom.to_str().unwrap().to_string();
+        let data = read(rom).unwrap();
+        let cartridge = match Cartridge::new(&data) {
+            Ok(cartridge) => cartridge,
+            Err(e) => {
+                Logger::error(format!("FAIL    {path}: {e}"));
+                continue;
+            }
+        };
+        let mut gameboy = Gameboy::new(MemoryManagementUnit::new(data, cartridge, None, rom, false));
+        gameboy.mmu.start();
+        match run_rom(&mut Emulator::new(gameboy)) {
+            Verdict::Passed => {
+                passed += 1;
+                Logger::info(format!("PASS    {path}"));
+            }
+            Verdict::Failed(reason) => Logger::error(format!("FAIL    {path}: {reason}")),
+            Verdict::Timeout => Logger::error(format!("TIMEOUT {path}")),
+        }
+    }
+
+    Logger::info(format!("{passed}/{} conformance ROMs passed", roms.len()));
+    passed == roms.len()
+}
@@ -0,0 +1,99 @@
+use crate::gameboy::{CycleResult, Gameboy};
+
+/// Upper bound for a single run-to-target operation, so stepping over a call that never
+/// returns (or out of the main loop) doesn't hang the emulator. One second of emulation.
+const STEP_LIMIT: usize = crate::CYCLES_PER_FRAME as usize * 60;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StepResult {
+    /// The step finished where it was meant to.
+    Done,
+    /// A user breakpoint was hit before the step finished.
+    Breakpoint(u16),
+    /// The step limit ran out before the step finished.
+    Timeout,
+}
+
+/// Stepping commands on top of [`Gameboy::advance`]. Breakpoints live on the [`Gameboy`], so
+/// steps and frames stop at the same ones.
+#[derive(Default, Debug)]
+pub struct Debugger;
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn toggle_breakpoint(&self, gameboy: &mut Gameboy, address: u16) {
+        if !gameboy.breakpoints.remove(&address) {
+            gameboy.add_breakpoint(address);
+        }
+    }
+
+    /// Executes a single instruction (or a single halted cycle).
+    pub fn step(&self, gameboy: &mut Gameboy) -> StepResult {
+        gameboy.step();
+        StepResult::Done
+    }
+
+    /// Executes the next instruction, running a CALL or RST through its whole subroutine.
+    /// A temporary breakpoint is placed on the return address, and only counts once SP is back
+    /// to its entry value so recursive calls to the same subroutine don't stop early.
+    pub fn step_over(&self, gameboy: &mut Gameboy) -> StepResult {
+        let pc = gameboy.reg.pc.value();
+        let size = match gameboy.peek(pc) {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+            opcode if opcode & 0xC7 == 0xC7 => 1,
+            _ => return self.step(gameboy),
+        };
+        let return_address = pc.wrapping_add(size);
+        let entry_sp = gameboy.reg.sp.value();
+
+        self.run_until(gameboy, |gameboy| {
+            gameboy.reg.pc.value() == return_address && gameboy.reg.sp.value() >= entry_sp
+        })
+    }
+
+    /// Runs until the current subroutine returns, i.e. a RET/RETI leaves SP above its value at
+    /// the time of the call.
+    pub fn step_out(&self, gameboy: &mut Gameboy) -> StepResult {
+        let entry_sp = gameboy.reg.sp.value();
+        let mut returning = false;
+
+        self.run_until(gameboy, |gameboy| {
+            let returned = returning && gameboy.reg.sp.value() > entry_sp;
+            let opcode = gameboy.peek(gameboy.reg.pc.value());
+            returning = matches!(opcode, 0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9);
+            returned
+        })
+    }
+
+    fn run_until(
+        &self,
+        gameboy: &mut Gameboy,
+        mut done: impl FnMut(&Gameboy) -> bool,
+    ) -> StepResult {
+        let mut cycles = 0;
+        // Evaluate the condition before the first instruction so step_out can see it
+        done(gameboy);
+        while cycles < STEP_LIMIT {
+            let pc = match gameboy.advance() {
+                CycleResult::Ran(ran) => {
+                    cycles += ran as usize;
+                    if done(gameboy) {
+                        return StepResult::Done;
+                    }
+                    gameboy.reg.pc.value()
+                }
+                CycleResult::Stopped(pc) => pc,
+            };
+            if gameboy.breakpoints.contains(&pc) {
+                if let Some(trace) = &gameboy.trace {
+                    trace.dump(&format!("breakpoint at {pc:04X}"));
+                }
+                return StepResult::Breakpoint(pc);
+            }
+        }
+        StepResult::Timeout
+    }
+}
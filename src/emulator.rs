@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use crate::cartridge::Cartridge;
+use crate::gameboy::{CycleResult, Gameboy};
+use crate::joypad::Buttons;
+use crate::mmu::MemoryManagementUnit;
+use crate::{CYCLES_PER_FRAME, HEIGHT, WIDTH};
+
+/// A Game Boy driven one frame at a time, for embedding the emulator in other programs.
+/// It doesn't open a window or an audio stream, frontends present the framebuffer themselves.
+pub struct Emulator {
+    pub gameboy: Gameboy,
+    /// The screen as 0xAARRGGBB pixels, updated after every frame
+    framebuffer: Vec<u32>,
+    /// PPU dots the last frame ran past its budget, taken out of the next one so that frames
+    /// average exactly `CYCLES_PER_FRAME`
+    overshoot: u32,
+}
+
+impl Emulator {
+    pub fn new(gameboy: Gameboy) -> Self {
+        Self {
+            gameboy,
+            framebuffer: vec![0; WIDTH * HEIGHT],
+            overshoot: 0,
+        }
+    }
+
+    /// Boots a ROM without a boot ROM or audio output. Fails if its header can't be read.
+    pub fn from_rom(bytes: &[u8]) -> Result<Self, String> {
+        let cartridge = Cartridge::new(bytes)?;
+        let mmu = MemoryManagementUnit::new(bytes.to_vec(), cartridge, None, Path::new("rom.gb"), false);
+        let mut gameboy = Gameboy::new(mmu);
+        gameboy.mmu.start();
+        Ok(Self::new(gameboy))
+    }
+
+    /// Executes a single instruction, ticking the rest of the system for any cycles it didn't
+    /// spend on memory accesses. Returns the M-cycles taken, or 0 when stopped at a breakpoint.
+    pub fn step(&mut self) -> u16 {
+        match self.gameboy.advance() {
+            CycleResult::Ran(cycles) => cycles as u16,
+            CycleResult::Stopped(_) => 0,
+        }
+    }
+
+    /// Runs a frame's worth of cycles with the buttons currently held, or the next ones of the
+    /// gameboy's playback. Returns the breakpoint the frame stopped at early, if any; the next
+    /// frame then picks up from it.
+    pub fn run_frame(&mut self) -> Option<u16> {
+        #[cfg(any(unix, windows))]
+        self.gameboy.frame_input();
+
+        // Counted in PPU dots, since a frame takes twice the CPU cycles in double speed mode
+        let mut elapsed_dots = self.overshoot;
+        let mut breakpoint = None;
+        while elapsed_dots < CYCLES_PER_FRAME as u32 * 4 {
+            match self.gameboy.advance() {
+                CycleResult::Ran(cycles) => {
+                    elapsed_dots += cycles as u32 * self.gameboy.mmu.dots_per_cycle() as u32
+                }
+                CycleResult::Stopped(pc) => {
+                    breakpoint = Some(pc);
+                    break;
+                }
+            }
+        }
+        self.overshoot = elapsed_dots.saturating_sub(CYCLES_PER_FRAME as u32 * 4);
+
+        let screen = self.gameboy.mmu.ppu.screen.chunks_exact(4);
+        for (pixel, rgba) in self.framebuffer.iter_mut().zip(screen) {
+            *pixel = u32::from_be_bytes([rgba[3], rgba[0], rgba[1], rgba[2]]);
+        }
+        breakpoint
+    }
+
+    /// Holds `buttons` for a frame and runs it.
+    pub fn step_frame(&mut self, buttons: Buttons) -> Option<u16> {
+        self.gameboy.mmu.joypad.held = buttons;
+        self.run_frame()
+    }
+
+    /// The screen after the last frame, 160x144 pixels in 0xAARRGGBB format.
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+}
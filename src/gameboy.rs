@@ -1,14 +1,17 @@
+use std::collections::HashSet;
 use std::ops::{Index, IndexMut};
 
 use crate::instruction::Command::*;
 use crate::instruction_fetcher::Fetcher;
 use crate::interrupt::IE_ADDRESS;
 use crate::interrupt::IF_ADDRESS;
+use crate::cartridge::Cartridge;
 use crate::mmu::MemoryManagementUnit;
 use crate::register::RegisterId::*;
 use crate::register::WordRegister::{ProgramCounter, StackPointer};
 use crate::register::{ByteRegister, Register, RegisterId, WordRegister};
 use std::cmp::max;
+use std::mem::swap;
 use instant::Instant;
 
 use serde::{Deserialize, Serialize};
@@ -17,6 +20,26 @@ use crate::instruction::Operand::{OpByte, OpHL, OpRegister};
 use crate::instruction::{Command, Operand};
 use crate::interrupt::InterruptId;
 use crate::interrupt::InterruptId::{Input, Serial, Stat, Timing, VBlank};
+use crate::logger::Logger;
+use crate::trace::TraceRing;
+use crate::CYCLES_PER_FRAME;
+#[cfg(any(unix, windows))]
+use crate::movie::{Playback, Recorder};
+#[cfg(any(unix, windows))]
+use crate::script::Script;
+use std::thread;
+
+/// M-cycles a CGB spends switching speed before the CPU resumes
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
+/// What a call to [`Gameboy::cycle`] did.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CycleResult {
+    /// The CPU ran for this many M-cycles.
+    Ran(u8),
+    /// The CPU reached a breakpoint at this address and executed nothing.
+    Stopped(u16),
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Gameboy {
@@ -28,80 +51,319 @@ pub struct Gameboy {
     halt_bug: bool,
     pub mmu: MemoryManagementUnit,
     pub halted: bool,
-    counter: usize,
+    /// M-cycles the CPU stays parked for while the clock settles after a speed switch
+    speed_switch: u16,
+    /// M-cycle at which PC first reached the cartridge entry point.
+    #[serde(skip)]
+    entry_cycle: Option<u64>,
+    /// Log every interrupt dispatch, along with the state that caused it.
+    #[serde(skip)]
+    pub log_interrupts: bool,
+    /// Last executed instructions, dumped if the emulator panics.
+    #[serde(skip)]
+    pub trace: Option<TraceRing>,
+    /// Script called between frames.
+    #[cfg(any(unix, windows))]
+    #[serde(skip)]
+    pub script: Option<Script>,
+    /// Recorded input held in place of live input, until it runs out.
+    #[cfg(any(unix, windows))]
+    #[serde(skip)]
+    pub playback: Option<Playback>,
+    /// Where the buttons held on every frame are written to.
+    #[cfg(any(unix, windows))]
+    #[serde(skip)]
+    pub recorder: Option<Recorder>,
+    /// Addresses the CPU stops at before executing the instruction there.
+    #[serde(skip)]
+    pub breakpoints: HashSet<u16>,
+    /// Breakpoint the CPU last stopped at, which the next cycle runs through instead of stopping
+    /// at it again.
+    #[serde(skip)]
+    stopped_at: Option<u16>,
+    /// Instructions executed since the emulator started, kept across resets and save states.
+    #[serde(skip)]
+    instructions: u64,
 }
 
 impl Gameboy {
+    /// Powers the Game Boy back on with the same cartridge, keeping everything the host attached.
     pub fn reset(&mut self) {
+        self.mmu.reset();
         self.pin = Some((0, Instant::now()));
         self.halt_bug = false;
-        self.reg = Register::new(self.mmu.boot_rom.is_some());
+        self.reg = Register::new(self.mmu.boot_rom.is_some(), self.mmu.ppu.cgb);
         self.ei_counter = -1;
         self.ime = false;
         self.halted = false;
-        self.counter = 0;
-        self.mmu.reset();
+        self.speed_switch = 0;
+        self.entry_cycle = None;
+        self.stopped_at = None;
     }
 
     pub fn new(mem: MemoryManagementUnit) -> Self {
         Self {
             pin: Some((0, Instant::now())),
             halt_bug: false,
-            reg: Register::new(mem.boot_rom.is_some()),
+            reg: Register::new(mem.boot_rom.is_some(), mem.ppu.cgb),
             mmu: mem,
             ei_counter: -1,
             ime: false,
             halted: false,
-            counter: 0,
+            speed_switch: 0,
+            entry_cycle: None,
+            log_interrupts: false,
+            trace: None,
+            #[cfg(any(unix, windows))]
+            script: None,
+            #[cfg(any(unix, windows))]
+            playback: None,
+            #[cfg(any(unix, windows))]
+            recorder: None,
+            breakpoints: HashSet::new(),
+            stopped_at: None,
+            instructions: 0,
+        }
+    }
+
+    /// Swaps the emulated state for a loaded save state while the game keeps running. Everything
+    /// the host attached stays in place: the window, the audio stream and any debugging hooks.
+    pub fn load_state(&mut self, mut state: Gameboy) {
+        self.mmu.apu.restore(&mut state.mmu.apu);
+        swap(&mut state.mmu.apu, &mut self.mmu.apu);
+        swap(&mut state.mmu.renderer, &mut self.mmu.renderer);
+        swap(&mut state.mmu.on_frame, &mut self.mmu.on_frame);
+        swap(&mut state.mmu.cheats, &mut self.mmu.cheats);
+        swap(&mut state.mmu.loaded_boot_rom, &mut self.mmu.loaded_boot_rom);
+        swap(&mut state.mmu.ppu.debug, &mut self.mmu.ppu.debug);
+        swap(&mut state.mmu.ppu.palette, &mut self.mmu.ppu.palette);
+        state.mmu.ppu.unlimited_sprites = self.mmu.ppu.unlimited_sprites;
+        swap(&mut state.mmu.serial.output, &mut self.mmu.serial.output);
+        state.mmu.serial.echo = self.mmu.serial.echo;
+        swap(&mut state.mmu.serial.printer, &mut self.mmu.serial.printer);
+        state.mmu.joypad.low_latency = self.mmu.joypad.low_latency;
+        state.mmu.ram_init = self.mmu.ram_init;
+        state.mmu.keep_clock_mode(&self.mmu);
+        state.log_interrupts = self.log_interrupts;
+        state.trace = self.trace.take();
+        swap(&mut state.breakpoints, &mut self.breakpoints);
+        state.instructions = self.instructions;
+        #[cfg(any(unix, windows))]
+        {
+            swap(&mut state.mmu.serial.peer, &mut self.mmu.serial.peer);
+            state.script = self.script.take();
+            state.playback = self.playback.take();
+            state.recorder = self.recorder.take();
         }
+        *self = state;
+        self.mmu.start();
     }
 
     pub fn init(&mut self) {
         self.mmu.apu.init();
     }
+
+    /// Runs the rest of the system for `cycles` M-cycles before the CPU executes anything,
+    /// mimicking the variable power-on delay of real hardware when starting without a boot ROM.
+    pub fn warm_up(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.mmu.cycle(4);
+        }
+        self.mmu.cycles = 0;
+    }
+
+    /// Holds the next frame of the playback, then records the buttons held, so that recordings
+    /// capture exactly the input every frame runs with.
+    #[cfg(any(unix, windows))]
+    pub(crate) fn frame_input(&mut self) {
+        if let Some(playback) = &mut self.playback {
+            match playback.next_frame() {
+                Some(held) => self.mmu.joypad.held = held,
+                None => {
+                    Logger::info("Playback finished, back to live input");
+                    self.playback = None;
+                }
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            if let Err(e) = recorder.record(self.mmu.joypad.held) {
+                Logger::error(format!("Input recording stopped: {e}"));
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Runs the boot ROM until it unmaps itself and hands over to the cartridge, skipping its
+    /// logo animation. Gives up after 10 emulated seconds, returning whether it finished.
+    pub fn skip_boot_rom(&mut self) -> bool {
+        let limit = CYCLES_PER_FRAME as u64 * 600;
+        let mut cycles = 0;
+        while self.mmu.boot_rom.is_some() && cycles < limit {
+            match self.advance() {
+                CycleResult::Ran(ran) => cycles += ran as u64,
+                CycleResult::Stopped(_) => break,
+            }
+        }
+        self.mmu.boot_rom.is_none()
+    }
+
+    /// Whether the CPU runs at twice the DMG clock after a CGB speed switch.
+    pub fn double_speed(&self) -> bool {
+        self.mmu.double_speed()
+    }
+
+    /// The M-cycle at which the game's code started executing at 0x0100, if it has yet.
+    pub fn entry_cycle(&self) -> Option<u64> {
+        self.entry_cycle
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.remove(&address);
+    }
+
+    /// A snapshot of the CPU registers.
+    pub fn registers(&self) -> Register {
+        self.reg.clone()
+    }
+
+    /// Base clock T-cycles run since reset, 4194304 a second at either CPU speed. Halted, stopped
+    /// and interrupt dispatch cycles all count, and the count carries over save states.
+    pub fn clock(&self) -> u64 {
+        self.mmu.clock
+    }
+
+    /// Instructions the CPU executed since the emulator started. Halted cycles and interrupt
+    /// dispatches don't count.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The game's name from the cartridge header, if it has a readable one.
+    pub fn title(&self) -> Option<String> {
+        let header: Vec<u8> = (0x134..=0x143).map(|address| self.peek(address)).collect();
+        Cartridge::parse_title(&header)
+    }
+
+    /// Reads memory as the CPU sees it, boot ROM included, without ticking the rest of the system.
+    pub fn peek(&self, address: u16) -> u8 {
+        match &self.mmu.boot_rom {
+            Some(boot_rom) if address < 0x100 => boot_rom[address as usize],
+            _ => self.mmu.internal_read(address as usize),
+        }
+    }
+
+    /// Writes memory without ticking the rest of the system. I/O registers still react to it.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.mmu.internal_write(address as usize, value);
+    }
+
+    /// Executes a single instruction (or a single halted cycle), even one with a breakpoint on it,
+    /// and returns the M-cycles it took.
+    pub fn step(&mut self) -> u8 {
+        self.stopped_at = Some(self.reg.pc.value());
+        match self.advance() {
+            CycleResult::Ran(cycles) => cycles,
+            CycleResult::Stopped(_) => 0,
+        }
+    }
+
+    /// Runs a CPU cycle, then ticks the rest of the system for the M-cycles the CPU spent without
+    /// accessing memory.
+    pub(crate) fn advance(&mut self) -> CycleResult {
+        let previously_halted = self.halted;
+        let cycles = match self.cycle() {
+            CycleResult::Ran(cycles) => cycles as u16,
+            stopped => return stopped,
+        };
+        let mem_cycles = cycles - self.mmu.cycles;
+        if mem_cycles != 0 && !previously_halted && !self.halted {
+            panic!("Cycle count after considering reads/writes: mem_cycles {} | cycles: {} | micro_ops: {}", mem_cycles, cycles, self.mmu.cycles)
+        }
+        (0..mem_cycles).for_each(|_| self.mmu.cycle(4));
+        self.mmu.cycles = 0;
+        CycleResult::Ran(cycles as u8)
+    }
+}
+
+impl Drop for Gameboy {
+    fn drop(&mut self) {
+        if let Some(trace) = self.trace.as_ref().filter(|_| thread::panicking()) {
+            trace.print("panic");
+        }
+    }
 }
 
 impl Gameboy {
     #[deny(unreachable_patterns)]
-    pub fn cycle(&mut self) -> u8 {
+    pub fn cycle(&mut self) -> CycleResult {
+        // The CPU executes nothing while STOP has the clock off or the speed is switching
+        if self.mmu.stopped || self.speed_switch > 0 {
+            self.speed_switch = self.speed_switch.saturating_sub(1);
+            self.machine_cycle();
+            return CycleResult::Ran(1);
+        }
+
+        let pc = self.reg.pc.value();
+        let resumed = self.stopped_at.take() == Some(pc);
+        if !self.halted && !resumed && self.breakpoints.contains(&pc) {
+            self.stopped_at = Some(pc);
+            return CycleResult::Stopped(pc);
+        }
+
+        // A halted CPU samples interrupts halfway through every machine cycle, so one the PPU
+        // raised later than that in the last cycle costs another before it wakes up
+        let late = self.mmu.ppu.interrupt_dot.is_some_and(|dot| dot >= self.mmu.dots_per_cycle() as usize / 2);
         let interrupt_cycles = if self.handle_interrupts() { 5 } else { 0 };
 
         if self.halted {
-            self.halted = interrupt_cycles == 0;
-            if self.halted
-                && !self.ime
-                && self.mmu.internal_read(IE_ADDRESS) & self.mmu.internal_read(IF_ADDRESS) & 0x1F
-                    != 0
-            {
+            if interrupt_cycles != 0 {
                 self.halted = false;
+                return CycleResult::Ran(u8::from(late) + interrupt_cycles);
+            }
+            // With IME off a pending interrupt just wakes the CPU up, running the next instruction
+            // right away unless the interrupt came too late in the last cycle
+            let pending = self.mmu.internal_read(IE_ADDRESS) & self.mmu.internal_read(IF_ADDRESS) & 0x1F != 0;
+            if !pending {
+                return CycleResult::Ran(1);
+            }
+            self.halted = false;
+            if late {
+                return CycleResult::Ran(1);
             }
-            return 1 + interrupt_cycles;
         }
 
         if interrupt_cycles != 0 {
-            return interrupt_cycles;
+            return CycleResult::Ran(interrupt_cycles);
+        }
+
+        if self.entry_cycle.is_none() && self.reg.pc.value() == 0x0100 {
+            self.entry_cycle = Some(self.mmu.total_cycles);
         }
 
         let instruction =
             Fetcher::fetch(self.halt_bug, self.reg.pc.value(), &self.reg, &mut self.mmu);
-        let (_, command) = (instruction.0, instruction.1);
+        let (opcode, command) = (instruction.0, instruction.1);
+        self.instructions += 1;
+
+        if let Some(trace) = &mut self.trace {
+            trace.push(&self.reg, opcode, command);
+        }
 
-        self.set_pc(self.reg.pc.value() + command.size() as u16, false);
+        // The HALT bug skips the PC increment after the opcode read, so that byte is read twice
+        let size = command.size() as u16 - u16::from(self.halt_bug);
+        self.set_pc(self.reg.pc.value() + size, false);
 
-        self.execute_instruction(command)
+        CycleResult::Ran(self.execute_instruction(command))
     }
 
     fn execute_instruction(&mut self, command: Command) -> u8 {
-        let command_cycles = self.handle_command(command);
-
         self.halt_bug = false;
+        let command_cycles = self.handle_command(command);
 
-        if !self.ime
-            && self.halted
-            && self.mmu.internal_read(IE_ADDRESS) & self.mmu.internal_read(IF_ADDRESS) & 0x1F != 0
-        {
-            self.halt_bug = true;
-        }
         if command != Halt {
             command_cycles
         } else {
@@ -135,11 +397,26 @@ impl Gameboy {
 
     fn trigger_interrupt(&mut self, interrupt_id: InterruptId) -> bool {
         if self.mmu.interrupt_handler.triggered(interrupt_id) {
+            if self.log_interrupts {
+                Logger::debug(format!(
+                    "Interrupt {:?} at cycle {}: PC={:04X} IE={:02X} IF={:02X} LY={}",
+                    interrupt_id,
+                    self.mmu.total_cycles,
+                    self.reg.pc.value(),
+                    self.mmu.internal_read(IE_ADDRESS),
+                    self.mmu.internal_read(IF_ADDRESS),
+                    self.mmu.ppu.ly,
+                ));
+            }
             self.machine_cycle();
             self.machine_cycle();
             self.ime = false;
             self.mmu.interrupt_handler.unset(interrupt_id);
-            let [lo, hi] = self.reg.pc.value().to_le_bytes();
+            // EI right before a bugged HALT dispatches the interrupt before the byte after HALT
+            // is read, and the missing increment makes it return to the HALT itself
+            let pc = self.reg.pc.value().wrapping_sub(u16::from(self.halt_bug));
+            self.halt_bug = false;
+            let [lo, hi] = pc.to_le_bytes();
             self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
             self.mmu.write(self.reg.sp, hi);
             self.reg.sp = StackPointer(self.reg.sp.value().wrapping_sub(1));
@@ -393,11 +670,7 @@ impl Gameboy {
             LdAU8(n) => self[A].value = n,
             LdAR16(n) => self[A].value = self.mmu.read(n),
             LdhAU16(n) => self[A].value = self.mmu.read(n),
-            LdhAU8(n) => {
-                self.counter += 1;
-                let x = self.mmu.read(n);
-                self[A].value = x;
-            }
+            LdhAU8(n) => self[A].value = self.mmu.read(n),
             LdhU8A(n) => {
                 self.mmu.write(n, self[A].value);
             }
@@ -460,7 +733,7 @@ impl Gameboy {
                 let hi = self.mmu.read(self.reg.sp.value().wrapping_add(1));
                 self.set_pc(u16::from_le_bytes([lo, hi]), true);
                 self.set_word_register(self.reg.sp.value().wrapping_add(2), self.reg.sp);
-                self.ei_counter = 1;
+                // Unlike EI, RETI enables interrupts immediately
                 self.ime = true;
             }
             Rst(rst_vec) => {
@@ -570,7 +843,16 @@ impl Gameboy {
             }
             DisableInterrupt => self.ime = false,
             EnableInterrupt => self.ei_counter = 2,
-            Halt => self.halted = true,
+            // With IME off and an interrupt already pending the CPU doesn't halt at all, but the
+            // PC increment of the next opcode read is lost, so the byte after HALT runs twice
+            Halt => {
+                let pending = self.mmu.internal_read(IE_ADDRESS) & self.mmu.internal_read(IF_ADDRESS) & 0x1F != 0;
+                if !self.ime && pending {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
             Scf => {
                 self.reg.flags.n = false;
                 self.reg.flags.h = false;
@@ -618,7 +900,11 @@ impl Gameboy {
                 }
             }
 
-            Stop => {}
+            Stop => {
+                if self.mmu.stop() {
+                    self.speed_switch = SPEED_SWITCH_CYCLES;
+                }
+            }
         };
         command.cycles(branch_taken)
     }
@@ -680,6 +966,8 @@ fn half_carry_8_add(a: u8, b: u8, c: u8) -> bool {
     (a & 0xF) + (b & 0xF) + c > 0xF
 }
 
+/// Whether subtracting `b` and the carry `c` from `a` borrows from bit 4. The low nibble of `b`
+/// plus the carry can reach 0x10, which every low nibble of `a` is below.
 fn half_carry_8_sub(a: u8, b: u8, c: u8) -> bool {
     (a & 0x0F) < (b & 0x0F) + c
 }
@@ -0,0 +1,116 @@
+#[cfg(any(unix, windows))]
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+#[cfg(any(unix, windows))]
+use gilrs::{Axis, Button, GamepadId, Gilrs};
+
+use iron_boy::Buttons;
+#[cfg(any(unix, windows))]
+use iron_boy::Logger;
+
+/// Analog stick deflection past which it counts as a d-pad press.
+#[cfg(any(unix, windows))]
+const DEADZONE: f32 = 0.5;
+
+#[cfg(any(unix, windows))]
+const BUTTONS: [(Button, Buttons); 8] = [
+    (Button::South, Buttons::A),
+    (Button::East, Buttons::B),
+    (Button::Select, Buttons::SELECT),
+    (Button::Start, Buttons::START),
+    (Button::DPadUp, Buttons::UP),
+    (Button::DPadDown, Buttons::DOWN),
+    (Button::DPadLeft, Buttons::LEFT),
+    (Button::DPadRight, Buttons::RIGHT),
+];
+
+/// Physical controllers, merged with the keyboard. Every connected gamepad drives the joypad.
+/// The browser build has its own virtual pad instead, so there are never any gamepads there.
+pub struct Gamepads {
+    #[cfg(any(unix, windows))]
+    gilrs: Option<Gilrs>,
+    /// Force feedback playing on every pad that supports it while the cartridge's motor runs
+    #[cfg(any(unix, windows))]
+    rumble: Option<Effect>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(any(unix, windows))]
+            gilrs: Gilrs::new()
+                .map_err(|e| Logger::error(format!("Gamepads unavailable: {e}")))
+                .ok(),
+            #[cfg(any(unix, windows))]
+            rumble: None,
+        }
+    }
+
+    /// Buttons held on any connected gamepad, with the left stick acting as a d-pad.
+    pub fn held(&mut self) -> Buttons {
+        #[cfg(any(unix, windows))]
+        if let Some(gilrs) = &mut self.gilrs {
+            // Gamepad state only updates as its events are consumed
+            while gilrs.next_event().is_some() {}
+
+            let mut held = Buttons::NONE;
+            for (_, gamepad) in gilrs.gamepads() {
+                for (button, held_button) in BUTTONS {
+                    if gamepad.is_pressed(button) {
+                        held |= held_button;
+                    }
+                }
+
+                let (x, y) = (gamepad.value(Axis::LeftStickX), gamepad.value(Axis::LeftStickY));
+                if x > DEADZONE {
+                    held |= Buttons::RIGHT;
+                } else if x < -DEADZONE {
+                    held |= Buttons::LEFT;
+                }
+                if y > DEADZONE {
+                    held |= Buttons::UP;
+                } else if y < -DEADZONE {
+                    held |= Buttons::DOWN;
+                }
+            }
+            return held;
+        }
+
+        Buttons::NONE
+    }
+
+    /// Starts or stops the motors of the connected gamepads that have force feedback.
+    pub fn set_rumble(&mut self, on: bool) {
+        #[cfg(any(unix, windows))]
+        {
+            if let Some(effect) = self.rumble.take() {
+                let _ = effect.stop();
+            }
+            let Some(gilrs) = &mut self.gilrs else { return };
+            let pads: Vec<GamepadId> = gilrs
+                .gamepads()
+                .filter(|(_, gamepad)| gamepad.is_ff_supported())
+                .map(|(id, _)| id)
+                .collect();
+            if !on || pads.is_empty() {
+                return;
+            }
+
+            // Repeated until stopped, since the game decides how long the motor runs
+            let motor = BaseEffect {
+                kind: BaseEffectType::Strong { magnitude: u16::MAX },
+                scheduling: Replay { play_for: Ticks::from_ms(1000), ..Default::default() },
+                ..Default::default()
+            };
+            self.rumble = EffectBuilder::new()
+                .add_effect(motor)
+                .gamepads(&pads)
+                .finish(gilrs)
+                .and_then(|effect| effect.play().map(|_| effect))
+                .map_err(|e| Logger::error(format!("Unable to rumble: {e}")))
+                .ok();
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        let _ = on;
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt::{self, Display, Formatter};
+
 use crate::instruction::Operand::{OpByte, OpHL, OpRegister};
 use Command::*;
 
@@ -206,3 +208,111 @@ impl Command {
         }
     }
 }
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OpRegister(id) => write!(f, "{id:?}"),
+            OpByte(n) => write!(f, "${n:02X}"),
+            OpHL => write!(f, "(HL)"),
+        }
+    }
+}
+
+/// Name of a 16-bit register operand.
+fn r16(register: &WordRegister) -> String {
+    match register {
+        WordRegister::Double(high, low) => format!("{:?}{:?}", high.id, low.id),
+        WordRegister::AccFlag(..) => "AF".to_string(),
+        WordRegister::StackPointer(_) => "SP".to_string(),
+        WordRegister::ProgramCounter(_) => "PC".to_string(),
+    }
+}
+
+/// Disassembly in the usual Game Boy syntax, like `LD A,$42` or `JR NZ,-5`.
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let cc = |cc: &ConditionCode| format!("{cc:?}");
+        match self {
+            AdcA(op) => write!(f, "ADC A,{op}"),
+            AddA(op) => write!(f, "ADD A,{op}"),
+            AddHlR16(r) => write!(f, "ADD HL,{}", r16(r)),
+            AddSpI8(n) => write!(f, "ADD SP,{n}"),
+            AndA(op) => write!(f, "AND A,{op}"),
+            BitU3(Bit(mask), op) => write!(f, "BIT {},{op}", mask.trailing_zeros()),
+            CallCcU16(c, n) => write!(f, "CALL {},${n:04X}", cc(c)),
+            CallU16(n) => write!(f, "CALL ${n:04X}"),
+            Ccf => write!(f, "CCF"),
+            Cpl => write!(f, "CPL"),
+            CpA(op) => write!(f, "CP A,{op}"),
+            Daa => write!(f, "DAA"),
+            DechHl => write!(f, "DEC (HL)"),
+            DecR16(r) => write!(f, "DEC {}", r16(r)),
+            DecR8(r) => write!(f, "DEC {r:?}"),
+            DisableInterrupt => write!(f, "DI"),
+            EnableInterrupt => write!(f, "EI"),
+            Halt => write!(f, "HALT"),
+            InchHl => write!(f, "INC (HL)"),
+            IncR16(r) => write!(f, "INC {}", r16(r)),
+            IncR8(r) => write!(f, "INC {r:?}"),
+            JpCcU16(c, n) => write!(f, "JP {},${n:04X}", cc(c)),
+            JpHl => write!(f, "JP HL"),
+            JpU16(n) => write!(f, "JP ${n:04X}"),
+            JrCcI8(c, n) => write!(f, "JR {},{n}", cc(c)),
+            JrI8(n) => write!(f, "JR {n}"),
+            LdhAC => write!(f, "LD A,($FF00+C)"),
+            LdhAU16(n) => write!(f, "LD A,(${n:04X})"),
+            LdhAU8(n) => write!(f, "LDH A,($FF{n:02X})"),
+            LdhCA => write!(f, "LD ($FF00+C),A"),
+            LdhHlU8(n) => write!(f, "LD (HL),${n:02X}"),
+            LdhU16A(n) => write!(f, "LD (${n:04X}),A"),
+            LdhU8A(n) => write!(f, "LDH ($FF{n:02X}),A"),
+            LdAHld => write!(f, "LD A,(HL-)"),
+            LdAHli => write!(f, "LD A,(HL+)"),
+            LdAR16(r) => write!(f, "LD A,({})", r16(r)),
+            LdAU8(n) => write!(f, "LD A,${n:02X}"),
+            LdHldA => write!(f, "LD (HL-),A"),
+            LdHliA => write!(f, "LD (HL+),A"),
+            LdHlR8(r) => write!(f, "LD (HL),{r:?}"),
+            LdHlSpI8(n) => write!(f, "LD HL,SP{n:+}"),
+            LdR16A(r) => write!(f, "LD ({}),A", r16(r)),
+            LdR16U16(r, n) => write!(f, "LD {},${n:04X}", r16(r)),
+            LdR8Hl(r) => write!(f, "LD {r:?},(HL)"),
+            LdR8R8(to, from) => write!(f, "LD {to:?},{from:?}"),
+            LdR8U8(r, n) => write!(f, "LD {r:?},${n:02X}"),
+            LdSpHl => write!(f, "LD SP,HL"),
+            LdU16Sp(n) => write!(f, "LD (${n:04X}),SP"),
+            Nop => write!(f, "NOP"),
+            OrA(op) => write!(f, "OR A,{op}"),
+            PopR16(r) => write!(f, "POP {}", r16(r)),
+            PushAf => write!(f, "PUSH AF"),
+            PushR16(r) => write!(f, "PUSH {}", r16(r)),
+            ResU3Hl(Bit(mask)) => write!(f, "RES {},(HL)", mask.trailing_zeros()),
+            ResU3R8(Bit(mask), r) => write!(f, "RES {},{r:?}", mask.trailing_zeros()),
+            Ret => write!(f, "RET"),
+            Reti => write!(f, "RETI"),
+            RetCc(c) => write!(f, "RET {}", cc(c)),
+            Rl(op, true) => write!(f, "RL{op}"),
+            Rl(op, false) => write!(f, "RL {op}"),
+            Rlc(op, true) => write!(f, "RLC{op}"),
+            Rlc(op, false) => write!(f, "RLC {op}"),
+            Rr(op, true) => write!(f, "RR{op}"),
+            Rr(op, false) => write!(f, "RR {op}"),
+            Rrc(op, true) => write!(f, "RRC{op}"),
+            Rrc(op, false) => write!(f, "RRC {op}"),
+            Rst(vector) => write!(f, "RST ${:02X}", *vector as u8),
+            SbcA(op) => write!(f, "SBC A,{op}"),
+            Scf => write!(f, "SCF"),
+            SetU3Hl(Bit(mask)) => write!(f, "SET {},(HL)", mask.trailing_zeros()),
+            SetU3R8(Bit(mask), r) => write!(f, "SET {},{r:?}", mask.trailing_zeros()),
+            Sla(op) => write!(f, "SLA {op}"),
+            Sra(op) => write!(f, "SRA {op}"),
+            Srl(op) => write!(f, "SRL {op}"),
+            Stop => write!(f, "STOP"),
+            SubA(op) => write!(f, "SUB A,{op}"),
+            SwapHl => write!(f, "SWAP (HL)"),
+            SwapR8(r) => write!(f, "SWAP {r:?}"),
+            XorA(op) => write!(f, "XOR A,{op}"),
+        }
+    }
+}
@@ -52,6 +52,10 @@ impl InterruptHandler {
         InterruptHandler { flag, enable }
     }
 
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
     fn is_active(&self, mask: u8) -> bool {
         (self.enable & self.flag & mask) != 0
     }
@@ -1,30 +1,81 @@
-use crate::joypad::SelectedButtons::{Action, Direction};
 use crate::mmu::MemoryArea;
 
+use std::ops::{BitOr, BitOrAssign};
+
 use serde::{Deserialize, Serialize};
-use winit::keyboard::KeyCode;
-use winit::keyboard::KeyCode::{ArrowDown, ArrowLeft, ArrowRight, ArrowUp, Backspace, Enter, KeyC, KeyZ};
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
-pub enum SelectedButtons {
-    Action = 0x10,
-    Direction = 0x20,
+/// A set of joypad buttons, one bit per button. The low nibble holds the directions and the
+/// high one the actions, in the order the joypad register reports them.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, PartialOrd)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub const NONE: Self = Self(0x00);
+    pub const RIGHT: Self = Self(0x01);
+    pub const LEFT: Self = Self(0x02);
+    pub const UP: Self = Self(0x04);
+    pub const DOWN: Self = Self(0x08);
+    pub const A: Self = Self(0x10);
+    pub const B: Self = Self(0x20);
+    pub const SELECT: Self = Self(0x40);
+    pub const START: Self = Self(0x80);
+
+    /// Names buttons go by in scripts and input recordings.
+    pub const NAMES: [(&'static str, Self); 8] = [
+        ("a", Self::A),
+        ("b", Self::B),
+        ("select", Self::SELECT),
+        ("start", Self::START),
+        ("up", Self::UP),
+        ("down", Self::DOWN),
+        ("left", Self::LEFT),
+        ("right", Self::RIGHT),
+    ];
+
+    pub fn named(name: &str) -> Option<Self> {
+        Self::NAMES.iter().find(|(button, _)| *button == name).map(|&(_, button)| button)
+    }
+
+    pub fn contains(self, buttons: Buttons) -> bool {
+        self.0 & buttons.0 == buttons.0
+    }
+}
+
+impl BitOr for Buttons {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Buttons {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd)]
 pub struct Joypad {
-    selected_buttons: SelectedButtons,
+    /// Select lines as last written, P14 (bit 4) low selects the directions and P15 (bit 5) low
+    /// the actions
+    select: u8,
     action_buttons: u8,
     direction_buttons: u8,
     #[serde(skip)]
-    pub(crate) held_action: Vec<KeyCode>,
+    pub held: Buttons,
+    /// Poll input before running each frame rather than after it
     #[serde(skip)]
-    pub(crate) held_direction: Vec<KeyCode>,
+    pub low_latency: bool,
+    /// Buttons held by a script, on top of the ones held on the keyboard
+    #[serde(skip)]
+    pub scripted: Buttons,
 }
 
 impl MemoryArea for Joypad {
     fn read(&self, address: usize) -> Option<u8> {
-        let value = self.selected_buttons as u8 | self.buttons();
+        // Only the select lines are writable, the unused top bits read as 1
+        let value = 0xC0 | self.select | self.buttons();
         match address {
             0xFF00 => Some(value),
             _ => None,
@@ -33,13 +84,7 @@ impl MemoryArea for Joypad {
 
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
-            0xFF00 => {
-                self.selected_buttons = match value & 0x30 {
-                    0x20 | 0x30 => Direction,
-                    0x10 => Action,
-                    _ => self.selected_buttons,
-                }
-            }
+            0xFF00 => self.select = value & 0x30,
             _ => return false,
         };
         true
@@ -51,35 +96,44 @@ impl Joypad {
         Self {
             action_buttons: 0x0F,
             direction_buttons: 0x0F,
-            selected_buttons: Action,
-            held_direction: vec![],
-            held_action: vec![],
+            select: 0x10,
+            held: Buttons::NONE,
+            low_latency: false,
+            scripted: Buttons::NONE,
         }
     }
 
+    pub fn reset(&mut self) {
+        let low_latency = self.low_latency;
+        *self = Self::new();
+        self.low_latency = low_latency;
+    }
+
+    /// Holds `buttons` from the frontend's input, along with the ones held by a script.
+    pub fn hold(&mut self, buttons: Buttons) {
+        self.held = self.scripted | buttons;
+    }
+
     pub fn machine_cycle(&mut self) -> bool {
         let previous_buttons = self.buttons();
 
-        self.action_buttons = Self::map_buttons([KeyZ, KeyC, Backspace, Enter], &self.held_action);
-        self.direction_buttons = Self::map_buttons([ArrowRight, ArrowLeft, ArrowUp, ArrowDown], &self.held_direction);
+        // Held buttons read as 0
+        self.action_buttons = !(self.held.0 >> 4) & 0x0F;
+        self.direction_buttons = !self.held.0 & 0x0F;
 
         self.buttons() != previous_buttons
     }
 
-    fn map_buttons(buttons: [KeyCode; 4], held: &[KeyCode]) -> u8 {
-        !buttons
-            .iter()
-            .enumerate()
-            .map(|(i, button)| u8::from(held.contains(button)) * 2u8.pow(i as u32))
-            .sum::<u8>()
-            & 0x0F
-    }
-
+    /// The buttons of the selected groups, pressed ones reading as 0. With both groups selected
+    /// a button from either pulls its line low, with neither every line reads high.
     fn buttons(&self) -> u8 {
-        if self.selected_buttons == Action {
-            self.action_buttons
-        } else {
-            self.direction_buttons
+        let mut buttons = 0x0F;
+        if self.select & 0x10 == 0 {
+            buttons &= self.direction_buttons;
+        }
+        if self.select & 0x20 == 0 {
+            buttons &= self.action_buttons;
         }
+        buttons
     }
 }
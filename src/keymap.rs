@@ -0,0 +1,75 @@
+use serde::Deserialize;
+use winit::keyboard::KeyCode;
+use winit_input_helper::WinitInputHelper;
+use winit::keyboard::KeyCode::{ArrowDown, ArrowLeft, ArrowRight, ArrowUp, Backspace, Enter, KeyC, KeyZ};
+
+use iron_boy::Buttons;
+
+/// Keyboard key for every Game Boy button. Loaded from a TOML file with winit key names, buttons
+/// missing from it keep their default key:
+/// ```toml
+/// up = "KeyI"
+/// left = "KeyJ"
+/// down = "KeyK"
+/// right = "KeyL"
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyMap {
+    pub a: KeyCode,
+    pub b: KeyCode,
+    pub select: KeyCode,
+    pub start: KeyCode,
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        Self {
+            a: KeyZ,
+            b: KeyC,
+            select: Backspace,
+            start: Enter,
+            up: ArrowUp,
+            down: ArrowDown,
+            left: ArrowLeft,
+            right: ArrowRight,
+        }
+    }
+}
+
+impl KeyMap {
+    #[cfg(any(unix, windows))]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        toml::from_str(&config).map_err(|e| e.to_string())
+    }
+
+    /// Each key along with the button it holds.
+    pub fn buttons(&self) -> [(KeyCode, Buttons); 8] {
+        [
+            (self.a, Buttons::A),
+            (self.b, Buttons::B),
+            (self.select, Buttons::SELECT),
+            (self.start, Buttons::START),
+            (self.up, Buttons::UP),
+            (self.down, Buttons::DOWN),
+            (self.left, Buttons::LEFT),
+            (self.right, Buttons::RIGHT),
+        ]
+    }
+
+    /// The buttons whose keys are held down.
+    pub fn held(&self, input: &WinitInputHelper) -> Buttons {
+        let mut held = Buttons::NONE;
+        for (key, button) in self.buttons() {
+            if input.key_held(key) {
+                held |= button;
+            }
+        }
+        held
+    }
+}
@@ -0,0 +1,155 @@
+//! The IronBoy Game Boy emulator core. [`Emulator`] runs a ROM headless one frame at a time, the
+//! window, input and audio output of the `iron_boy` binary are behind the `frontend` feature.
+
+use std::io::Read;
+use std::iter;
+
+use clap::ValueEnum;
+use ruzstd::decoding::StreamingDecoder;
+use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+
+use crate::SaveFile::{Bin, BinZstd, Json};
+
+pub use apu::Resampler;
+pub use archive::unpack;
+pub use cartridge::Cartridge;
+pub use cheats::Cheats;
+pub use debugger::{Debugger, StepResult};
+pub use emulator::Emulator;
+pub use gameboy::{CycleResult, Gameboy};
+pub use instruction_fetcher::disassemble;
+pub use joypad::Buttons;
+#[cfg(any(unix, windows))]
+pub use link::LinkPeer;
+pub use logger::{Logger, Progress};
+pub use mmu::{FrameCallback, MemoryManagementUnit, RamInit};
+#[cfg(any(unix, windows))]
+pub use movie::{Playback, Recorder};
+pub use ppu::{Color, Palette, Sprite};
+pub use register::Register;
+pub use renderer::{ColorCorrection, Scaling, VSync};
+#[cfg(any(unix, windows))]
+pub use script::Script;
+pub use trace::TraceRing;
+
+mod archive;
+mod cartridge;
+mod cheats;
+#[cfg(any(unix, windows))]
+pub mod conformance;
+mod debugger;
+mod emulator;
+mod gameboy;
+mod instruction;
+mod instruction_fetcher;
+mod interrupt;
+mod joypad;
+#[cfg(any(unix, windows))]
+mod link;
+mod mbc;
+mod mbc0;
+mod mbc1;
+mod mbc3;
+mod mmu;
+#[cfg(any(unix, windows))]
+mod movie;
+mod ppu;
+mod printer;
+mod register;
+mod renderer;
+#[cfg(any(unix, windows))]
+mod script;
+mod serial;
+mod sgb;
+mod timer;
+mod trace;
+mod apu;
+
+#[cfg(test)]
+mod test;
+mod mbc5;
+mod mbc_camera;
+mod logger;
+mod mbc2;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+
+pub const CYCLES_PER_FRAME: u16 = 17556;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SaveFile {
+    Json,
+    Bin,
+    /// Bincode compressed with zstd, a fraction of the size of plain binary states
+    BinZstd,
+}
+
+impl SaveFile {
+    pub const FORMATS: [Self; 3] = [Json, Bin, BinZstd];
+
+    pub fn extension(&self) -> &str {
+        match self {
+            Json => ".sav.json",
+            Bin => ".sav.bin",
+            BinZstd => ".sav.zst",
+        }
+    }
+
+    pub fn save(&self, gameboy: &Gameboy) -> Vec<u8> {
+        match self {
+            Json => serde_json::to_vec(gameboy).unwrap(),
+            Bin => bincode::serialize(gameboy).unwrap(),
+            BinZstd => compress_to_vec(&Bin.save(gameboy)[..], CompressionLevel::Fastest),
+        }
+    }
+
+    pub fn load(&self, data: &[u8]) -> Result<Gameboy, String> {
+        match self {
+            Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Bin => bincode::deserialize(data).map_err(|e| e.to_string()),
+            BinZstd => {
+                let mut state = vec![];
+                StreamingDecoder::new(data)
+                    .map_err(|e| e.to_string())?
+                    .read_to_end(&mut state)
+                    .map_err(|e| e.to_string())?;
+                Bin.load(&state)
+            }
+        }
+    }
+
+    /// The format a path's extension names, `.json`, `.bin` or `.zst`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Self::FORMATS.into_iter().find(|format| {
+            let extension = format.extension();
+            path.ends_with(&extension[extension.rfind('.').unwrap()..])
+        })
+    }
+
+    /// The ROM a save state at `path` belongs to, `game.gb` for `game.gb.sav.bin` as well as for
+    /// `game.gb.slot2.sav.zst`. Paths without a save extension are returned as they are.
+    pub fn rom_path(path: &str) -> &str {
+        let Some(stem) = Self::FORMATS.iter().find_map(|format| path.strip_suffix(format.extension())) else {
+            return path;
+        };
+        match stem.rsplit_once(".slot") {
+            Some((rom, slot)) if !slot.is_empty() && slot.bytes().all(|b| b.is_ascii_digit()) => rom,
+            _ => stem,
+        }
+    }
+
+    /// Loads a save state in whichever format it turns out to be in, trying the one `path` names
+    /// first, so a renamed file still loads. Returns the format it was in.
+    pub fn detect(path: &str, data: &[u8]) -> Result<(Self, Gameboy), String> {
+        let named = Self::from_path(path).unwrap_or(Bin);
+        let mut errors = vec![];
+        for format in iter::once(named).chain(Self::FORMATS.into_iter().filter(|format| *format != named)) {
+            match format.load(data) {
+                Ok(gameboy) => return Ok((format, gameboy)),
+                Err(e) => errors.push(format!("{format:?}: {e}")),
+            }
+        }
+        Err(format!("not a save state in any format ({})", errors.join(", ")))
+    }
+}
@@ -0,0 +1,95 @@
+use std::io::{ErrorKind, Read, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::logger::Logger;
+
+/// How long the side driving a transfer waits for the peer's byte before reading 0xFF, as if the
+/// cable were unplugged
+const TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Another IronBoy at the other end of the link cable, reached over TCP. Every transfer is one
+/// byte each way: the side using the internal clock sends its byte and polls for the answer, the
+/// side using the external clock answers whenever a byte arrives while it has a transfer armed.
+#[derive(Debug)]
+pub struct LinkPeer {
+    stream: TcpStream,
+    /// When the transfer being driven stops waiting for the peer's byte
+    deadline: Option<Instant>,
+}
+
+/// Progress of a transfer driven with the internal clock
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Reply {
+    Pending,
+    Received(u8),
+    TimedOut,
+}
+
+impl LinkPeer {
+    /// Waits for a peer to connect on `port`.
+    pub fn listen(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        Logger::info(format!("Waiting for a link cable peer on port {port}"));
+        let (stream, address) = listener.accept()?;
+        Logger::info(format!("Link cable connected to {address}"));
+        Self::new(stream)
+    }
+
+    pub fn connect(address: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        Logger::info(format!("Link cable connected to {address}"));
+        Self::new(stream)
+    }
+
+    pub(crate) fn new(stream: TcpStream) -> Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self { stream, deadline: None })
+    }
+
+    /// Drives a transfer with the internal clock, the answer is picked up by polling [`Self::reply`].
+    pub(crate) fn send(&mut self, byte: u8) -> Result<()> {
+        // Answers to earlier transfers that timed out would shift every later one by a byte
+        while self.read()?.is_some() {}
+
+        self.stream.write_all(&[byte])?;
+        self.deadline = Some(Instant::now() + TIMEOUT);
+        Ok(())
+    }
+
+    /// The peer's answer to the transfer started by [`Self::send`], without waiting for it.
+    pub(crate) fn reply(&mut self) -> Result<Reply> {
+        let Some(deadline) = self.deadline else {
+            return Ok(Reply::TimedOut);
+        };
+        if let Some(reply) = self.read()? {
+            self.deadline = None;
+            return Ok(Reply::Received(reply));
+        }
+        if Instant::now() < deadline {
+            return Ok(Reply::Pending);
+        }
+        self.deadline = None;
+        Ok(Reply::TimedOut)
+    }
+
+    /// Answers a transfer driven by the peer with `byte`, if one arrived. Returns the peer's byte.
+    pub(crate) fn receive(&mut self, byte: u8) -> Result<Option<u8>> {
+        let received = self.read()?;
+        if received.is_some() {
+            self.stream.write_all(&[byte])?;
+        }
+        Ok(received)
+    }
+
+    fn read(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte) {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(Some(byte[0])),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
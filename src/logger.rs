@@ -10,6 +10,15 @@ impl Logger {
         println!("{s}");
     }
 
+    pub fn debug<S: Into<String>>(s: S) {
+        let s: String = s.into();
+        #[cfg(target_arch = "wasm32")]
+        web_sys::console::debug_1(&s.into());
+
+        #[cfg(any(unix, windows))]
+        println!("{s}");
+    }
+
     pub fn error<S: Into<String>>(s: S) {
         let s: String = s.into();
 
@@ -19,4 +28,31 @@ impl Logger {
         #[cfg(any(unix, windows))]
         eprintln!("{s}");
     }
-}
\ No newline at end of file
+}
+
+/// Logs the progress of a long running load in 10% steps.
+pub struct Progress {
+    name: String,
+    total: usize,
+    logged: usize,
+}
+
+impl Progress {
+    /// Loads smaller than this finish fast enough that logging them would only be noise.
+    const MIN_SIZE: usize = 1024 * 1024;
+
+    pub fn new<S: Into<String>>(name: S, total: usize) -> Self {
+        Self { name: name.into(), total, logged: 0 }
+    }
+
+    pub fn update(&mut self, loaded: usize) {
+        if self.total < Self::MIN_SIZE {
+            return;
+        }
+        let percentage = loaded * 100 / self.total;
+        if percentage >= self.logged + 10 {
+            self.logged = percentage - percentage % 10;
+            Logger::info(format!("Loading {}: {}%", self.name, percentage));
+        }
+    }
+}
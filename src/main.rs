@@ -2,128 +2,306 @@ extern crate core;
 
 #[cfg(target_arch = "wasm32")]
 use {
-    js_sys::{Array, ArrayBuffer, Uint8Array},
+    js_sys::{Array, ArrayBuffer, Reflect, Uint8Array},
     wasm_bindgen::{JsCast, JsValue},
     wasm_bindgen::closure::Closure,
     wasm_bindgen_futures::JsFuture,
-    web_sys::{console, HtmlInputElement, HtmlAnchorElement, HtmlDivElement, Blob, Request, RequestInit, Response, Url, window},
+    web_sys::{console, HtmlInputElement, HtmlAnchorElement, HtmlDivElement, Blob, ReadableStreamDefaultReader, Request, RequestInit, Response, Url, window},
     std::sync::atomic::Ordering,
     std::sync::Mutex,
-    std::collections::HashMap
+    std::collections::HashMap,
 };
 
 #[cfg(any(unix, windows))]
 use {
-    std::io::{Write},
+    std::io::{Read, Write},
     std::fs::{read, write, File},
     winit::event::Event,
     std::thread,
 };
 
-use gameboy::Gameboy;
-
-use crate::mmu::MemoryManagementUnit;
+use iron_boy::{
+    Buttons, Cartridge, ColorCorrection, Emulator, Gameboy, Logger, MemoryManagementUnit, Palette, Progress,
+    RamInit, Register, Resampler, SaveFile, Scaling, TraceRing, VSync, disassemble, unpack, HEIGHT, WIDTH,
+};
+#[cfg(any(unix, windows))]
+use iron_boy::{conformance, LinkPeer, Playback, Recorder, Script};
 use instant::{Duration, Instant};
+use wasm_timer::{SystemTime, UNIX_EPOCH};
 
 use std::path::Path;
 use std::sync::{Arc};
 use std::sync::atomic::{AtomicBool};
 use std::sync::atomic::Ordering::Relaxed;
 
-use crate::cartridge::Cartridge;
-use crate::register::Register;
-
 use clap::{Parser, ValueEnum};
 use cpal::traits::StreamTrait;
 
 use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
-use pixels::wgpu::PresentMode;
 
 use winit::dpi::LogicalSize;
-use winit::keyboard::KeyCode::{Backspace, Escape, ArrowLeft, ArrowDown, Enter, ArrowRight, ArrowUp, KeyC, KeyF, KeyS, KeyZ, KeyP, KeyM, KeyR};
-
-use winit::event_loop::EventLoop;
 use winit::keyboard::KeyCode;
+use winit::keyboard::KeyCode::{Escape, Space, Tab, Period, Comma, KeyB, KeyF, KeyG, KeyO, KeyS, KeyP, KeyM, KeyN, KeyR, KeyT, KeyW};
+use winit::keyboard::KeyCode::{Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9};
+
+use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::Fullscreen::Borderless;
 use winit::window::{Window, WindowBuilder};
 use winit_input_helper::WinitInputHelper;
-use crate::SaveFile::{Bin, Json};
-use crate::logger::Logger;
-
-mod cartridge;
-mod gameboy;
-mod instruction;
-mod instruction_fetcher;
-mod interrupt;
-mod joypad;
-mod mbc;
-mod mbc0;
-mod mbc1;
-mod mbc3;
-mod mmu;
-mod ppu;
-mod register;
-mod renderer;
-mod serial;
-mod timer;
-mod apu;
-
-#[cfg(test)]
-mod test;
-mod mbc5;
-mod logger;
-mod mbc2;
-
-const WIDTH: usize = 160;
-const HEIGHT: usize = 144;
+use crate::gamepad::Gamepads;
+use crate::keymap::KeyMap;
+use crate::autosave::Autosave;
+use crate::rewind::Rewind;
+use image::imageops::{resize, FilterType};
+use image::{ImageOutputFormat, RgbaImage};
+use std::io::Cursor;
+
+mod autosave;
+mod gamepad;
+mod keymap;
+mod rewind;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// GameBoy ROM file to input
-    rom_file: String,
+    /// GameBoy ROM file to input, zipped or gzipped ones are extracted
+    #[clap(required_unless_present_any = ["conformance", "convert_save"])]
+    rom_file: Option<String>,
+
+    /// Run every ROM in the directory headless and report which test suites passed
+    #[clap(long, value_name = "DIR")]
+    conformance: Option<String>,
+
+    /// Convert a save state to the format named by the output's extension, .json, .bin or .zst, then exit
+    #[clap(long, num_args = 2, value_names = ["IN", "OUT"])]
+    convert_save: Option<Vec<String>>,
 
     /// Boot title screen even when opening save file
     #[clap(long, default_value = "false")]
     cold_boot: bool,
 
+    /// Record the audio output to a WAV file from boot, W starts and stops recordings at runtime
+    #[clap(long, value_name = "PATH")]
+    record_audio: Option<String>,
+
+    /// Master volume, from 0.0 to 1.0
+    #[clap(long, default_value_t = 1.0)]
+    volume: f32,
+
+    /// Ask the output device for this sample rate instead of 44100Hz, falling back if it doesn't
+    /// support it
+    #[clap(long, value_name = "HZ")]
+    sample_rate: Option<u32>,
+
+    /// How the channels' 4 MiHz output is brought down to the output sample rate
+    #[clap(value_enum, long, default_value_t = Resampler::Average)]
+    resampler: Resampler,
+
     /// Wait between frames to attempt to lock framerate to 60 FPS
     #[clap(long, default_value = "false")]
     fast: bool,
 
+    /// Frame limiter speed multiplier, e.g. 2 for double speed or 0.5 for half speed
+    #[clap(long, default_value_t = 1.0, value_parser = positive)]
+    speed: f64,
+
     /// Automatically save state before exiting emulator
     #[clap(long, default_value = "false")]
     save_on_exit: bool,
 
+    /// Write the battery save every N seconds while the game keeps changing it, so a crash loses
+    /// at most that much progress
+    #[clap(long, value_name = "SECONDS")]
+    autosave_interval: Option<u64>,
+
     /// Use specified boot ROM
     #[clap(long)]
     boot_rom: Option<String>,
 
+    /// M-cycles to run before the game starts when booting without a boot ROM
+    #[clap(long, default_value_t = 0)]
+    boot_delay: u64,
+
+    /// Run the boot ROM's logo animation at full speed before showing the game
+    #[clap(long, default_value = "false", requires = "boot_rom")]
+    skip_logo: bool,
+
+    /// What work RAM, VRAM and OAM hold at power on: zeroes, random bytes that are the same on
+    /// every run, or the pattern CGB RAM tends to hold. Exposes games that expect zeroed RAM
+    #[clap(value_enum, long, default_value_t = RamInit::Zero)]
+    ram_init: RamInit,
+
+    /// Advance the cartridge clock with emulated time, so it follows fast-forward
+    #[clap(long, default_value = "false")]
+    emulated_rtc: bool,
+
+    /// Run without opening an audio stream, the APU is still emulated
+    #[clap(long, default_value = "false")]
+    no_audio: bool,
+
+    /// Run without a window, the PPU is still emulated
+    #[clap(long, default_value = "false")]
+    no_video: bool,
+
+    /// Run a number of frames as fast as possible without a window or audio, then exit
+    #[clap(long, default_value = "false", requires = "frames")]
+    headless: bool,
+
+    /// Run the ROM as fast as possible for this many seconds without a window or audio, then
+    /// report the frames, clock cycles and instructions it got through
+    #[clap(long, value_name = "SECONDS", value_parser = positive, conflicts_with_all = ["headless", "no_video"])]
+    bench: Option<f64>,
+
+    /// Frames to run in headless mode
+    #[clap(long, value_name = "N", requires = "headless")]
+    frames: Option<u32>,
+
+    /// Save the screen after the last headless frame as a PNG
+    #[clap(long, value_name = "PATH", requires = "headless")]
+    screenshot: Option<String>,
+
+    /// Ignore the cartridge header and use the specified bank controller
+    #[clap(value_enum, long)]
+    force_mbc: Option<ForcedMbc>,
+
+    /// Refuse to boot ROMs whose header or global checksum doesn't match, which usually means a bad dump
+    #[clap(long, default_value = "false")]
+    strict_checksums: bool,
+
+    /// Emulate the colors of a real handheld screen
+    #[clap(value_enum, long, default_value_t = ColorCorrection::None)]
+    color_correction: ColorCorrection,
+
+    /// How the screen fills the window: integer multiples of its size, the largest size that keeps
+    /// its aspect ratio, or stretched to the whole window
+    #[clap(value_enum, long, default_value_t = Scaling::Integer)]
+    scale: Scaling,
+
+    /// Wait for the display's vertical blank before showing a frame: on, off, or adaptive to only
+    /// wait when the frame isn't already late. The frame limiter still sets the speed, waiting for
+    /// the display counts towards its frame time, and fast-forwarding drops frames instead of waiting
+    #[clap(value_enum, long, default_value_t = VSync::Off)]
+    vsync: VSync,
+
+    /// Blend this much of the previous frame into every new one, from 0 to 1, emulating the blur
+    /// of the DMG screen that games rely on for flicker transparency
+    #[clap(long, value_name = "STRENGTH", default_value = "0")]
+    ghosting: f32,
+
+    /// Shades DMG games are drawn with: green, grayscale, or four comma separated #RRGGBB colors
+    /// from lightest to darkest
+    #[clap(long, default_value = "green")]
+    palette: Palette,
+
+    /// Draw every sprite on a line instead of the hardware limit of 10, removing sprite flicker
+    #[clap(long, default_value = "false")]
+    unlimited_sprites: bool,
+
+    /// Color games with Super Game Boy functions using the palettes they send to the SGB
+    #[clap(long, default_value = "false")]
+    sgb: bool,
+
+    /// Apply a Game Genie (ABC-DEF-GHI) or GameShark (01DDAAAA) code, can be repeated
+    #[clap(long, value_name = "CODE")]
+    cheat: Vec<String>,
+
     /// Use specified file format for saves
     #[clap(value_enum, long, default_value_t = SaveFile::Bin)]
     format: SaveFile,
-}
 
-#[derive(ValueEnum, Clone, Copy, Debug)]
-enum SaveFile {
-    Json,
-    Bin,
-}
+    /// Disable the extra mode 3 cycles caused by sprites, for timing experiments
+    #[clap(long, default_value = "false")]
+    no_sprite_penalties: bool,
 
-impl SaveFile {
-    const FORMATS: [Self; 2] = [Json, Bin];
+    /// Log the length of mode 3 for every scanline
+    #[clap(long, default_value = "false")]
+    log_mode3: bool,
 
-    fn extension(&self) -> &str {
-        match self {
-            Json => ".sav.json",
-            Bin => ".sav.bin"
-        }
+    /// Log every interrupt dispatch
+    #[clap(long, default_value = "false")]
+    log_interrupts: bool,
+
+    /// Apply key presses as they come in instead of on the next frame, at the cost of determinism
+    #[clap(long, default_value = "false")]
+    low_latency_input: bool,
+
+    /// Never read the wall clock from the emulation, so the same ROM and inputs always give the
+    /// same screens and save states. The cartridge clock counts emulated time from a fixed epoch
+    #[clap(long, default_value = "false", conflicts_with_all = ["low_latency_input", "link_listen", "link_connect"])]
+    deterministic: bool,
+
+    /// Keep the last N executed instructions with their registers, printed on a crash
+    #[clap(long, value_name = "N", alias = "trace-ring")]
+    trace: Option<usize>,
+
+    /// Rhai script called after every frame
+    #[clap(long, value_name = "FILE")]
+    script: Option<String>,
+
+    /// Replay the buttons held on every frame from a file written by --record-inputs, live input
+    /// takes over once it runs out
+    #[clap(long, value_name = "FILE")]
+    playback: Option<String>,
+
+    /// Write the buttons held on every frame to a file, combine with --deterministic to replay
+    /// the session exactly
+    #[clap(long, value_name = "FILE")]
+    record_inputs: Option<String>,
+
+    /// TOML file mapping Game Boy buttons to keyboard keys
+    #[clap(long, value_name = "FILE")]
+    keymap: Option<String>,
+
+    /// Print every byte sent over the serial port, where test ROMs like blargg's report results
+    #[clap(long, default_value = "false")]
+    serial_stdout: bool,
+
+    /// Connect a Game Boy Printer to the serial port, printed pages are saved as PNGs
+    #[clap(long, default_value = "false")]
+    printer: bool,
+
+    /// Wait for another IronBoy to connect its link cable on this port
+    #[clap(long, value_name = "PORT", conflicts_with_all = ["link_connect", "printer"])]
+    link_listen: Option<u16>,
+
+    /// Connect the link cable to another IronBoy listening at this address
+    #[clap(long, value_name = "HOST:PORT", conflicts_with = "printer")]
+    link_connect: Option<String>,
+
+    /// Seconds of gameplay kept for rewinding, 0 disables it
+    #[clap(long, value_name = "SECONDS", default_value_t = 10)]
+    rewind_seconds: u32,
+}
+
+/// Parses a number that has to be finite and above 0, like a duration or a speed.
+fn positive(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => Ok(number),
+        Ok(number) => Err(format!("must be a positive number, got {number}")),
+        Err(e) => Err(e.to_string()),
     }
+}
 
-    fn save(&self, gameboy: &Gameboy) -> Vec<u8> {
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ForcedMbc {
+    Mbc0,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Camera,
+}
+
+impl ForcedMbc {
+    /// Header byte for the most capable variant of each controller (RAM and battery if available)
+    fn header(&self) -> u8 {
         match self {
-            Json => serde_json::to_vec(gameboy).unwrap(),
-            Bin => bincode::serialize(gameboy).unwrap()
+            ForcedMbc::Mbc0 => 0x00,
+            ForcedMbc::Mbc1 => 0x03,
+            ForcedMbc::Mbc2 => 0x06,
+            ForcedMbc::Mbc3 => 0x13,
+            ForcedMbc::Mbc5 => 0x1B,
+            ForcedMbc::Camera => 0xFC,
         }
     }
 }
@@ -233,20 +411,26 @@ async fn file_callback(pixels: Pixels, event_loop: EventLoop<()>, file: Option<w
         None => return,
     };
     console::log_2(&"File:".into(), &file.name().into());
-    let array_buffer = JsFuture::from(file.array_buffer()).await.unwrap();
-    let data = Uint8Array::new(&array_buffer).to_vec();
-
-    console::log_2(
-        &"Got data".into(),
-        &String::from_utf8_lossy(&data).into_owned().into(),
-    );
+    let data = read_file(&file).await;
+    console::log_2(&"Got data".into(), &data.len().into());
 
-    let name = file.name().replace(".sav.bin", "").replace(".sav.json", "");
-    let boot_rom = download_file("dmg_boot.gb")
-        .await
-        .ok()
-        .map(|b| Uint8Array::new(&b).to_vec());
-    let gameboy = load_gameboy(pixels, file.name(), false, boot_rom, data);
+    // Saves made from here on are named after the ROM, not after the state they continue from
+    let name = SaveFile::rom_path(&file.name()).to_string();
+    let boot_rom = if SaveFile::from_path(&file.name()).is_some() {
+        Logger::info(format!("Resuming {name} from a save state"));
+        None
+    } else {
+        download_file("dmg_boot.gb")
+            .await
+            .ok()
+            .map(|b| Uint8Array::new(&b).to_vec())
+    };
+    let options = LoadOptions { boot_rom, ..Default::default() };
+    let mut gameboy = match load_gameboy(Some(pixels), file.name(), data, options) {
+        Ok(gameboy) => gameboy,
+        Err(e) => return Logger::error(e),
+    };
+    gameboy.mmu.renderer.set_vsync(VSync::On);
 
     let doc = web_sys::window().unwrap().document().unwrap();
     doc.get_element_by_id("rom-selector")
@@ -266,14 +450,43 @@ async fn file_callback(pixels: Pixels, event_loop: EventLoop<()>, file: Option<w
 
     run_event_loop(
         event_loop,
-        gameboy,
+        Emulator::new(gameboy),
         Arc::new(AtomicBool::new(true)),
         mute,
         name,
         SaveFile::Bin,
+        false,
+        KeyMap::default(),
+        Duration::from_nanos(NANOS_PER_FRAME),
+        None,
+        None,
+        false,
+        None,
     );
 }
 
+/// Streams the file straight into wasm memory, so the ROM is never held in a JS buffer as well.
+#[cfg(target_arch = "wasm32")]
+async fn read_file(file: &web_sys::File) -> Vec<u8> {
+    let size = file.size() as usize;
+    let mut progress = Progress::new(file.name(), size);
+    let reader: ReadableStreamDefaultReader = file.stream().get_reader().dyn_into().unwrap();
+
+    let mut data = Vec::with_capacity(size);
+    loop {
+        let result = JsFuture::from(reader.read()).await.unwrap();
+        let done = Reflect::get(&result, &"done".into()).unwrap().as_bool().unwrap_or(true);
+        if done {
+            break data;
+        }
+        let chunk = Uint8Array::new(&Reflect::get(&result, &"value".into()).unwrap());
+        let offset = data.len();
+        data.resize(offset + chunk.length() as usize, 0);
+        chunk.copy_to(&mut data[offset..]);
+        progress.update(data.len());
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 fn main_wasm() {
     console_error_panic_hook::set_once();
@@ -291,50 +504,256 @@ fn main() {
 #[cfg(any(unix, windows))]
 fn main_desktop() {
     let args = Args::parse();
-    let rom_path = args.rom_file;
+    if let Some(dir) = args.conformance {
+        let passed = conformance::run_dir(Path::new(&dir));
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if let Some([input, output]) = args.convert_save.as_deref() {
+        match convert_save(input, output) {
+            Ok(()) => Logger::info(format!("Converted {input} to {output}")),
+            Err(e) => {
+                Logger::error(format!("Unable to convert {input} to {output}: {e}"));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let rom_path = args.rom_file.unwrap();
+
+    // Headless runs never touch the windowing system, so they work on servers without a display
+    let event_loop = (!args.headless && args.bench.is_none()).then(|| EventLoop::new().unwrap());
+    let window = event_loop
+        .as_ref()
+        .filter(|_| !args.no_video)
+        .map(|event_loop| setup_window(rom_path.clone()).build(event_loop).unwrap());
+    let pixels = window.as_ref().map(|window| setup_pixels(window, args.vsync));
+    let rom = read_rom(&rom_path).expect("Unable to read ROM file");
+    let options = LoadOptions {
+        cold_boot: args.cold_boot,
+        boot_rom: args.boot_rom.map(read).map(|f| f.expect("Boot ROM not found")),
+        boot_delay: args.boot_delay,
+        skip_logo: args.skip_logo,
+        force_mbc: args.force_mbc,
+        strict_checksums: args.strict_checksums,
+        sgb: args.sgb,
+        no_audio: args.no_audio || args.headless || args.bench.is_some(),
+        ram_init: args.ram_init,
+    };
+    let mut gameboy = load_gameboy(pixels, rom_path.clone(), rom, options).unwrap_or_else(|e| {
+        Logger::error(e);
+        std::process::exit(1);
+    });
+    gameboy.mmu.ppu.debug.no_sprite_at_0_penalty = args.no_sprite_penalties;
+    gameboy.mmu.ppu.debug.no_sprite_fetch_penalty = args.no_sprite_penalties;
+    gameboy.mmu.ppu.debug.log_mode3 = args.log_mode3;
+    gameboy.log_interrupts = args.log_interrupts;
+    gameboy.mmu.renderer.set_color_correction(args.color_correction);
+    gameboy.mmu.renderer.set_ghosting(args.ghosting);
+    gameboy.mmu.renderer.set_scaling(args.scale);
+    gameboy.mmu.renderer.set_vsync(args.vsync);
+    gameboy.mmu.ppu.palette = args.palette;
+    gameboy.mmu.ppu.unlimited_sprites = args.unlimited_sprites;
+    for code in &args.cheat {
+        gameboy.mmu.cheats.add(code);
+    }
+    gameboy.mmu.joypad.low_latency = args.low_latency_input;
+    gameboy.trace = args.trace.map(TraceRing::new);
+    gameboy.script = args.script.map(|path| match Script::load(path.into()) {
+        Ok(script) => script,
+        Err(e) => panic!("Unable to load script: {e}"),
+    });
+    gameboy.playback = args.playback.map(|path| match Playback::load(&path) {
+        Ok(playback) => playback,
+        Err(e) => panic!("Unable to load playback {path}: {e}"),
+    });
+    if let Some(path) = args.record_inputs {
+        match Recorder::create(&path) {
+            Ok(recorder) => gameboy.recorder = Some(recorder),
+            Err(e) => Logger::error(format!("Unable to record inputs to {path}: {e}")),
+        }
+    }
+    if let Some(mbc) = gameboy.mmu.mbc_mut() {
+        mbc.set_emulated_clock(args.emulated_rtc);
+        if args.deterministic {
+            mbc.set_deterministic_clock();
+        }
+    }
+    gameboy.mmu.apu.set_master_volume(args.volume);
+    if let Some(sample_rate) = args.sample_rate {
+        gameboy.mmu.apu.set_sample_rate(sample_rate);
+    }
+    gameboy.mmu.apu.set_resampler(args.resampler);
+    if let Some(path) = args.record_audio {
+        match gameboy.mmu.apu.start_recording(&path) {
+            Ok(()) => Logger::info(format!("Recording audio to {path}")),
+            Err(e) => Logger::error(format!("Unable to record audio to {path}: {e}")),
+        }
+    }
+    if args.serial_stdout {
+        gameboy.mmu.capture_serial(true);
+    }
+    if args.printer {
+        gameboy.mmu.connect_printer();
+    }
+    let link = match (args.link_listen, args.link_connect) {
+        (Some(port), _) => Some(LinkPeer::listen(port)),
+        (None, Some(address)) => Some(LinkPeer::connect(&address)),
+        (None, None) => None,
+    };
+    match link {
+        Some(Ok(peer)) => gameboy.mmu.connect_link(peer),
+        Some(Err(e)) => Logger::error(format!("Link cable unavailable, playing unlinked: {e}")),
+        None => {}
+    }
 
-    let event_loop = EventLoop::new().unwrap();
-    let window = setup_window(rom_path.clone()).build(&event_loop).unwrap();
-    let pixels = setup_pixels(&window);
-    let rom = read(rom_path.clone()).expect("Unable to read ROM file");
-    let boot_rom = args.boot_rom.map(read).map(|f| f.expect("Boot ROM not found"));
-    let gameboy = load_gameboy(pixels, rom_path.clone(), args.cold_boot, boot_rom, rom);
+    if let Some(seconds) = args.bench {
+        run_bench(Emulator::new(gameboy), Duration::from_secs_f64(seconds));
+        return;
+    }
+
+    let Some(event_loop) = event_loop else {
+        run_headless(Emulator::new(gameboy), &rom_path, args.format, args.frames.unwrap(), args.screenshot);
+        return;
+    };
+
+    let keymap = args.keymap.map_or_else(KeyMap::default, |path| match KeyMap::load(&path) {
+        Ok(keymap) => keymap,
+        Err(e) => panic!("Unable to load keymap {path}: {e}"),
+    });
+
+    if !(0.0..=1.0).contains(&args.ghosting) {
+        panic!("Ghosting must be between 0 and 1, got {}", args.ghosting);
+    }
+    let frame_time = Duration::from_nanos((NANOS_PER_FRAME as f64 / args.speed) as u64);
+    let autosave = args.autosave_interval.map(|seconds| Autosave::new(battery_ram_path(&rom_path), Duration::from_secs(seconds)));
+
+    run_event_loop(event_loop, Emulator::new(gameboy), Arc::new(AtomicBool::new(!args.fast)), Arc::new(AtomicBool::new(false)), rom_path, args.format, args.save_on_exit, keymap, frame_time, Rewind::new(args.rewind_seconds), autosave, args.deterministic, window.as_ref());
+}
+
+/// Runs `frames` frames back to back, with only scripts driving the joypad, then saves the screen
+/// to `screenshot` if given.
+#[cfg(any(unix, windows))]
+fn run_headless(mut emulator: Emulator, rom_path: &str, format: SaveFile, frames: u32, screenshot: Option<String>) {
+    for _ in 0..frames {
+        emulator.run_frame();
+
+        if let Some(mut script) = emulator.gameboy.script.take() {
+            match script.on_frame(&mut emulator.gameboy) {
+                Ok(save) => {
+                    // Wait for the files, the process may exit right after
+                    if let Some(saving) = save.then(|| save_state(rom_path.to_string(), &mut emulator.gameboy, format, None)).flatten() {
+                        saving.join().unwrap();
+                    }
+                    emulator.gameboy.script = Some(script);
+                }
+                Err(e) => Logger::error(format!("Script stopped: {e}")),
+            }
+            emulator.gameboy.mmu.joypad.hold(Buttons::NONE);
+        }
+    }
+
+    if let Some(path) = screenshot {
+        let mmu = &emulator.gameboy.mmu;
+        match write(&path, png(&mmu.renderer.corrected(&mmu.ppu.screen[..]), 1)) {
+            Ok(()) => Logger::info(format!("Saved screenshot {path}")),
+            Err(e) => Logger::error(format!("Unable to write screenshot {path}: {e}")),
+        }
+    }
+}
 
-    run_event_loop(event_loop, gameboy, Arc::new(AtomicBool::new(!args.fast)), Arc::new(AtomicBool::new(false)), rom_path, args.format);
+/// Runs frames back to back for `duration` without pacing or input, then reports how fast the
+/// emulation went.
+#[cfg(any(unix, windows))]
+fn run_bench(mut emulator: Emulator, duration: Duration) {
+    let no_sleep = Arc::new(AtomicBool::new(false));
+    let start_clock = emulator.gameboy.clock();
+    let start_instructions = emulator.gameboy.instructions();
+    let start = Instant::now();
+    let mut frames = 0_u64;
+    while start.elapsed() < duration {
+        run_frame(&mut emulator, no_sleep.clone(), Buttons::NONE, Duration::ZERO);
+        frames += 1;
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let cycles = emulator.gameboy.clock() - start_clock;
+    let clock_rate = cycles as f64 / elapsed;
+    Logger::info(format!("Ran {frames} frames in {elapsed:.2}s, {:.1} FPS on average", frames as f64 / elapsed));
+    Logger::info(format!(
+        "Ran {cycles} T-cycles, {:.3} MHz or {:.1}x the real 4.194304 MHz",
+        clock_rate / 1e6,
+        clock_rate / CLOCK_RATE
+    ));
+    let instructions = emulator.gameboy.instructions() - start_instructions;
+    Logger::info(format!("Ran {instructions} instructions, {:.3} million per second", instructions as f64 / elapsed / 1e6));
 }
 
+#[cfg(any(unix, windows))]
+fn read_rom(rom_path: &str) -> std::io::Result<Vec<u8>> {
+    const CHUNK_SIZE: u64 = 256 * 1024;
 
+    let mut file = File::open(rom_path)?;
+    let size = file.metadata()?.len() as usize;
+    let mut progress = Progress::new(rom_path, size);
+
+    let mut data = Vec::with_capacity(size);
+    while (&mut file).take(CHUNK_SIZE).read_to_end(&mut data)? != 0 {
+        progress.update(data.len());
+    }
+    Ok(data)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn run_event_loop(
     event_loop: EventLoop<()>,
-    mut gameboy: Gameboy,
+    mut emulator: Emulator,
     sleep: Arc<AtomicBool>,
     muted: Arc<AtomicBool>,
     rom_path: String,
     format: SaveFile,
+    save_on_exit: bool,
+    keymap: KeyMap,
+    frame_time: Duration,
+    mut rewind: Option<Rewind>,
+    mut autosave: Option<Autosave>,
+    deterministic: bool,
+    window: Option<&Window>,
 ) {
     let mut input = WinitInputHelper::new();
+    let mut gamepads = Gamepads::new();
 
     let mut frames = 0.0;
     let start = Instant::now();
+    let start_clock = emulator.gameboy.clock();
 
     let mut slowest_frame = Duration::from_nanos(0);
 
     let mut paused = false;
-    if let (Some(stream), false) = (&gameboy.mmu.apu.stream, muted.load(Relaxed)) {
-        stream.play().unwrap();
+    if let (Some(stream), false) = (&emulator.gameboy.mmu.apu.stream, muted.load(Relaxed)) {
+        stream.play().ok();
     }
 
     let mut last_save = Instant::now();
+    let mut last_audio_retry = Instant::now();
+    let mut audio_lost = false;
+
+    // The palette picked on the command line stays in the cycle, even if it's a custom one
+    let mut palettes = Palette::PRESETS.to_vec();
+    if !palettes.contains(&emulator.gameboy.mmu.ppu.palette) {
+        palettes.push(emulator.gameboy.mmu.ppu.palette);
+    }
 
     #[cfg(target_arch = "aarch64")]
         let mut focus = (Instant::now(), true);
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = deterministic;
 
     #[cfg(target_arch = "wasm32")]
         let mut sleep_time = Duration::from_secs(0);
     #[cfg(target_arch = "wasm32")]
         let mut wait_time = Instant::now();
     #[cfg(target_arch = "wasm32")]
-        let keymap = setup_virtual_pad();
+        let virtual_pad = setup_virtual_pad();
 
     #[cfg(target_arch = "wasm32")] {
         let mut previously_muted = muted.load(Relaxed);
@@ -353,18 +772,58 @@ fn run_event_loop(
         mute_on_unfocus.forget();
     }
 
+    // Games without a readable header name go by their file name
+    let title = emulator.gameboy.title().unwrap_or_else(|| {
+        Path::new(&rom_path).file_name().map_or(rom_path.clone(), |name| name.to_string_lossy().into_owned())
+    });
+    show_title(window, &title);
+    let mut title_update = (Instant::now(), frames);
+
     let mut previously_muted = false;
+    let mut rumbling = false;
+    let mut previously_rewinding = false;
+    #[cfg(any(unix, windows))]
+    let mut next_frame = Instant::now();
     let _ = event_loop.run(move |event, control_flow| {
-        let gameboy = &mut gameboy;
+        let emulator = &mut emulator;
         input.update(&event);
 
-        if let Some(stream) = &gameboy.mmu.apu.stream {
+        // Low latency input holds keys as their events come in, not just when a frame starts
+        #[cfg(any(unix, windows))]
+        if emulator.gameboy.mmu.joypad.low_latency
+            && matches!(event, Event::WindowEvent { event: winit::event::WindowEvent::KeyboardInput { .. }, .. })
+        {
+            emulator.gameboy.mmu.joypad.hold(keymap.held(&input) | gamepads.held());
+        }
+
+        // Without a window there are no events to wake the loop up
+        if emulator.gameboy.mmu.renderer.pixels().is_none() {
+            control_flow.set_control_flow(ControlFlow::Poll);
+        }
+
+        if let Some(stream) = &emulator.gameboy.mmu.apu.stream {
             if muted.load(Relaxed) && !previously_muted {
                 previously_muted = true;
-                stream.pause().unwrap();
+                stream.pause().ok();
             } else if !muted.load(Relaxed) && previously_muted {
                 previously_muted = false;
-                stream.play().unwrap();
+                stream.play().ok();
+            }
+        }
+
+        // Audio dies with its device, so look for one again every second and run muted until then
+        if emulator.gameboy.mmu.apu.audio_lost() && last_audio_retry.elapsed() >= Duration::from_secs(1) {
+            if !audio_lost {
+                Logger::error("Audio output lost, running muted until a device is available");
+                audio_lost = true;
+            }
+            last_audio_retry = Instant::now();
+            if emulator.gameboy.mmu.apu.reinit_audio() {
+                Logger::info("Audio output restored");
+                audio_lost = false;
+                if let (Some(stream), false) = (&emulator.gameboy.mmu.apu.stream, paused || muted.load(Relaxed)) {
+                    stream.play().ok();
+                }
             }
         }
 
@@ -373,8 +832,8 @@ fn run_event_loop(
 
         if input.key_released(KeyP) {
             paused = !paused;
-            if let Some(stream) = &gameboy.mmu.apu.stream {
-                if paused { stream.pause().unwrap(); } else if !muted.load(Relaxed) { stream.play().unwrap(); }
+            if let Some(stream) = &emulator.gameboy.mmu.apu.stream {
+                if paused { stream.pause().ok(); } else if !muted.load(Relaxed) { stream.play().ok(); }
             }
         }
 
@@ -383,13 +842,35 @@ fn run_event_loop(
                 "Finished running at {} FPS average.\nSlowest frame took {:?}.\nSlowest render frame took {:?}.",
                 frames / start.elapsed().as_secs_f64(),
                 slowest_frame,
-                gameboy.mmu.renderer.slowest
+                emulator.gameboy.mmu.renderer.slowest
+            ));
+            let clock_rate = emulator.gameboy.clock().saturating_sub(start_clock) as f64 / start.elapsed().as_secs_f64();
+            Logger::info(format!(
+                "Emulated clock averaged {:.3} MHz, {:.1}% of the real 4.194304 MHz.",
+                clock_rate / 1e6,
+                clock_rate / CLOCK_RATE * 100.0
+            ));
+            let apu = &emulator.gameboy.mmu.apu;
+            Logger::info(format!(
+                "Audio ran dry {} times, {} samples were buffered.",
+                apu.underruns(),
+                apu.buffered_samples()
             ));
+            if let Some(autosave) = &mut autosave {
+                autosave.flush(&mut emulator.gameboy);
+            }
+            if save_on_exit {
+                if let Some(writer) = save_state(rom_path.clone(), &mut emulator.gameboy, format, None) {
+                    writer.join().unwrap();
+                }
+            }
+            #[cfg(any(unix, windows))]
+            emulator.gameboy.mmu.apu.stop_recording();
             control_flow.exit();
         }
 
-        if let (Some(size), Some(p)) = (input.window_resized(), gameboy.mmu.renderer.pixels().as_mut()) {
-            p.resize_surface(size.width, size.height).unwrap();
+        if let Some(size) = input.window_resized() {
+            emulator.gameboy.mmu.renderer.resize(size.width, size.height);
         }
 
         #[cfg(target_arch = "aarch64")]
@@ -399,7 +880,7 @@ fn run_event_loop(
                 rand::Rng,
                 rand::distributions::Uniform
             };
-            if !paused && focus.1 && Instant::now() > focus.0 {
+            if !deterministic && !paused && focus.1 && Instant::now() > focus.0 {
                 // Save temporary dummy file to prevent throttling on Apple Silicon after focus change
                 let dummy_data: Vec<u8> = rand::thread_rng().sample_iter(&Uniform::from(0..255)).take(0xFFFFFF).collect();
 
@@ -415,10 +896,42 @@ fn run_event_loop(
         }
 
         if input.key_released(KeyS) && last_save + Duration::from_secs(1) < Instant::now() {
-            save_state(rom_path.clone(), gameboy, format);
+            if let Some(autosave) = &mut autosave {
+                autosave.wait();
+            }
+            save_state(rom_path.clone(), &mut emulator.gameboy, format, None);
             last_save = Instant::now();
         }
 
+        if input.key_released(KeyT) {
+            screenshot(&rom_path, &emulator.gameboy);
+        }
+
+        #[cfg(any(unix, windows))]
+        if input.key_released(KeyW) {
+            toggle_recording(&rom_path, &mut emulator.gameboy);
+        }
+
+        for (slot, key) in SLOT_KEYS.into_iter().enumerate() {
+            if !input.key_released(key) {
+                continue;
+            }
+            if input.held_control() {
+                if let 1..=4 = slot {
+                    let muted = emulator.gameboy.mmu.apu.toggle_channel(slot);
+                    Logger::info(format!("Channel {slot} {}", if muted { "muted" } else { "unmuted" }));
+                }
+            } else if input.held_shift() {
+                if let Some(autosave) = &mut autosave {
+                    autosave.wait();
+                }
+                save_state(rom_path.clone(), &mut emulator.gameboy, format, Some(slot as u8));
+            } else {
+                #[cfg(any(unix, windows))]
+                load_slot(rom_path.clone(), &mut emulator.gameboy, format, slot as u8);
+            }
+        }
+
         if input.key_released(KeyF) {
             sleep.store(!sleep.load(Relaxed), Relaxed);
         }
@@ -427,13 +940,38 @@ fn run_event_loop(
             muted.store(!muted.load(Relaxed), Relaxed);
         }
 
+        if input.key_released(KeyG) {
+            let ppu = &mut emulator.gameboy.mmu.ppu;
+            let current = palettes.iter().position(|palette| *palette == ppu.palette).unwrap_or(0);
+            ppu.palette = palettes[(current + 1) % palettes.len()];
+            Logger::info(format!("Palette: {:?}", ppu.palette));
+        }
+
+        if input.key_released(KeyO) {
+            let ppu = &mut emulator.gameboy.mmu.ppu;
+            ppu.unlimited_sprites = !ppu.unlimited_sprites;
+            Logger::info(format!("Sprites per line: {}", if ppu.unlimited_sprites { "unlimited" } else { "10" }));
+        }
+
         if input.key_released(KeyR) {
-            gameboy.reset();
+            emulator.gameboy.reset();
         }
 
+        if input.key_released(KeyB) {
+            let renderer = &mut emulator.gameboy.mmu.renderer;
+            renderer.set_beam_overlay(!renderer.beam_overlay());
+            Logger::info(format!("Beam overlay {}", if renderer.beam_overlay() { "on" } else { "off" }));
+            if paused {
+                emulator.gameboy.mmu.redraw();
+            }
+        }
+
+        // Everything the player holds, the core only ever sees these buttons
+        #[cfg_attr(not(target_arch = "wasm32"), allow(unused_mut))]
+        let mut buttons = keymap.held(&input) | gamepads.held();
+
         #[cfg(target_arch = "wasm32")] {
-            let keymap = keymap.clone();
-            check_buttons(rom_path.clone(), format, gameboy, muted.clone(), sleep.clone(), &mut paused, keymap);
+            buttons |= check_buttons(rom_path.clone(), format, &mut emulator.gameboy, muted.clone(), sleep.clone(), &mut paused, virtual_pad.clone());
             if paused != previously_paused {
                 let class = "title fa fa-".to_owned() + if paused { "play" } else { "pause" };
                 window()
@@ -444,17 +982,46 @@ fn run_event_loop(
         }
 
         if paused {
-            if let Some(stream) = &gameboy.mmu.apu.stream {
-                stream.pause().unwrap();
+            if let Some(stream) = &emulator.gameboy.mmu.apu.stream {
+                stream.pause().ok();
+            }
+            // Frame and instruction advance, the stream stays paused so the frame doesn't blip
+            if input.key_released(KeyN) || input.key_released(Period) {
+                run_frame(emulator, Arc::new(AtomicBool::new(false)), buttons, frame_time);
+            }
+            if input.key_released(Comma) {
+                step_instruction(&mut emulator.gameboy);
+            }
+            // Steps rarely end on a finished frame, the overlay shows how far the PPU got
+            if emulator.gameboy.mmu.renderer.beam_overlay() && [KeyN, Period, Comma].into_iter().any(|key| input.key_released(key)) {
+                emulator.gameboy.mmu.redraw();
             }
             return;
         }
 
+        // Low latency input waits for the next frame in the event loop instead of asleep in this
+        // handler, so the key events that come in meanwhile reach the joypad before it starts
+        #[cfg(any(unix, windows))]
+        if emulator.gameboy.mmu.joypad.low_latency && Instant::now() < next_frame {
+            control_flow.set_control_flow(ControlFlow::WaitUntil(next_frame));
+            return;
+        }
+
+        // Rewinding steps back a snapshot every frame, without sound since it would play forwards
+        let rewinding = input.key_held(Tab) && rewind.as_mut().is_some_and(|rewind| rewind.step_back(&mut emulator.gameboy));
+        if let (Some(stream), true) = (&emulator.gameboy.mmu.apu.stream, rewinding != previously_rewinding) {
+            if rewinding { stream.pause().ok(); } else if !muted.load(Relaxed) { stream.play().ok(); }
+        }
+        previously_rewinding = rewinding;
+
+        // Turbo runs uncapped while held, pacing picks up again from the release
+        let sleep = if input.key_held(Space) { Arc::new(AtomicBool::new(false)) } else { sleep.clone() };
+
         #[cfg(target_arch = "wasm32")]
         if wait_time.elapsed() < sleep_time {
             return;
         } else {
-            let run = run_frame(gameboy, sleep.clone(), Some(&input));
+            let run = run_frame(emulator, sleep, buttons, frame_time);
             sleep_time = run.1;
             if slowest_frame < run.0 {
                 slowest_frame = run.0;
@@ -463,36 +1030,123 @@ fn run_event_loop(
         }
 
         #[cfg(any(unix, windows))] {
-            let (current_frame, sleep_time) = run_frame(
-                gameboy,
-                sleep.clone(),
-                Some(&input));
-            thread::sleep(sleep_time);
+            let (current_frame, sleep_time) = run_frame(emulator, sleep, buttons, frame_time);
+            if emulator.gameboy.mmu.joypad.low_latency {
+                next_frame = Instant::now() + sleep_time;
+                control_flow.set_control_flow(ControlFlow::WaitUntil(next_frame));
+            } else {
+                thread::sleep(sleep_time);
+            }
             if slowest_frame < current_frame {
                 slowest_frame = current_frame;
             }
+
+            if let Some(mut script) = emulator.gameboy.script.take() {
+                match script.on_frame(&mut emulator.gameboy) {
+                    Ok(save) => {
+                        if save {
+                            if let Some(autosave) = &mut autosave {
+                                autosave.wait();
+                            }
+                            save_state(rom_path.clone(), &mut emulator.gameboy, format, None);
+                        }
+                        emulator.gameboy.script = Some(script);
+                    }
+                    Err(e) => Logger::error(format!("Script stopped: {e}")),
+                }
+                // Apply the buttons pressed by the script to the next frame
+                emulator.gameboy.mmu.joypad.hold(buttons);
+            }
+        }
+
+        if let (Some(rewind), false) = (&mut rewind, rewinding) {
+            rewind.record(&emulator.gameboy);
+        }
+
+        if let Some(autosave) = &mut autosave {
+            autosave.tick(&mut emulator.gameboy);
+        }
+
+        for page in emulator.gameboy.mmu.take_prints() {
+            save_print(&rom_path, page);
+        }
+
+        if emulator.gameboy.mmu.rumble() != rumbling {
+            rumbling = !rumbling;
+            set_rumble(&mut gamepads, rumbling);
         }
 
         frames += 1.0;
+
+        let (since, frames_then) = title_update;
+        if since.elapsed() >= Duration::from_secs(1) {
+            let fps = (frames - frames_then) / since.elapsed().as_secs_f64();
+            show_title(window, &format!("{title} — {fps:.1} FPS"));
+            title_update = (Instant::now(), frames);
+        }
     });
 }
 
+/// Names the window, or the page on the web where the canvas has no title bar of its own.
+fn show_title(window: Option<&Window>, title: &str) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = window;
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            document.set_title(title);
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(window) = window {
+        window.set_title(title);
+    }
+}
+
+/// Runs the instruction at PC and logs it, the screen only catches up once the frame is done.
+fn step_instruction(gameboy: &mut Gameboy) {
+    let pc = gameboy.registers().pc.value();
+    let bytes: Vec<u8> = (0..3).map(|offset| gameboy.peek(pc.wrapping_add(offset))).collect();
+    let (_, instruction) = disassemble(&bytes, 0, 1).remove(0);
+    let cycles = gameboy.step();
+    Logger::info(format!("{pc:#06X}: {instruction} ({cycles} M-cycles)"));
+}
+
+/// Drives the host's vibration from the cartridge's rumble motor. Browsers vibrate the device
+/// where supported, desktop builds rumble the gamepads that have force feedback.
+fn set_rumble(gamepads: &mut Gamepads, on: bool) {
+    #[cfg(target_arch = "wasm32")]
+    if let Some(window) = window() {
+        window.navigator().vibrate_with_duration(if on { 60_000 } else { 0 });
+    }
+
+    gamepads.set_rumble(on);
+}
+
+/// Handles the virtual pad's menu buttons and returns the Game Boy buttons held on it.
 #[cfg(target_arch = "wasm32")]
-fn check_buttons(rom_path: String, format: SaveFile, gameboy: &mut Gameboy, muted: Arc<AtomicBool>, sleep: Arc<AtomicBool>, paused: &mut bool, keymap: Arc<Mutex<HashMap<&str, AtomicBool>>>) {
+fn check_buttons(rom_path: String, format: SaveFile, gameboy: &mut Gameboy, muted: Arc<AtomicBool>, sleep: Arc<AtomicBool>, paused: &mut bool, keymap: Arc<Mutex<HashMap<&str, AtomicBool>>>) -> Buttons {
     let previously_paused = *paused;
+    let mut held = Buttons::NONE;
     for (key, value) in keymap.lock().unwrap().iter() {
         if !value.load(Relaxed) {
             continue;
         }
+        let button = match *key {
+            "a" => Some(Buttons::A),
+            "b" => Some(Buttons::B),
+            "select" => Some(Buttons::SELECT),
+            "start" => Some(Buttons::START),
+            "up" => Some(Buttons::UP),
+            "left" => Some(Buttons::LEFT),
+            "right" => Some(Buttons::RIGHT),
+            "down" => Some(Buttons::DOWN),
+            _ => None,
+        };
+        if let Some(button) = button {
+            held |= button;
+            continue;
+        }
         let code = match *key {
-            "a" => KeyZ,
-            "b" => KeyC,
-            "select" => Backspace,
-            "start" => Enter,
-            "up" => ArrowUp,
-            "left" => ArrowLeft,
-            "right" => ArrowRight,
-            "down" => ArrowDown,
             "speaker" => KeyM,
             "power" => KeyR,
             "pause" => KeyP,
@@ -500,11 +1154,7 @@ fn check_buttons(rom_path: String, format: SaveFile, gameboy: &mut Gameboy, mute
             "save" => KeyS,
             _ => unreachable!()
         };
-        if ACTION.contains(&code) && !gameboy.mmu.joypad.held_action.contains(&code) {
-            gameboy.mmu.joypad.held_action.push(code);
-        } else if DIRECTION.contains(&code) && !gameboy.mmu.joypad.held_direction.contains(&code) {
-            gameboy.mmu.joypad.held_direction.push(code);
-        } else if code == KeyM {
+        if code == KeyM {
             muted.store(!muted.load(Relaxed), Relaxed);
             value.store(false, Relaxed);
         } else if code == KeyR {
@@ -519,7 +1169,7 @@ fn check_buttons(rom_path: String, format: SaveFile, gameboy: &mut Gameboy, mute
             sleep.store(!sleep.load(Relaxed), Relaxed);
             value.store(false, Relaxed);
         } else if code == KeyS {
-            save_state(rom_path.clone(), gameboy, format);
+            save_state(rom_path.clone(), gameboy, format, None);
             value.store(false, Relaxed);
         }
     }
@@ -535,48 +1185,37 @@ fn check_buttons(rom_path: String, format: SaveFile, gameboy: &mut Gameboy, mute
             }
         }
     }
+    held
 }
 
-const ACTION: [KeyCode; 4] = [KeyZ, KeyC, Backspace, Enter];
-const DIRECTION: [KeyCode; 4] = [ArrowUp, ArrowDown, ArrowLeft, ArrowRight];
-
-fn run_frame(gameboy: &mut Gameboy, sleep: Arc<AtomicBool>, input: Option<&WinitInputHelper>) -> (Duration, Duration) {
-    let mut elapsed_cycles = 0;
+/// Runs a frame and returns how long it took, along with how long to sleep to keep one frame
+/// every `frame_time`. The `buttons` held apply to this frame with low latency input, otherwise
+/// to the next one, ~16.7ms later.
+fn run_frame(emulator: &mut Emulator, sleep: Arc<AtomicBool>, buttons: Buttons, frame_time: Duration) -> (Duration, Duration) {
     let start = Instant::now();
-    let pin = if let Some(pin) = gameboy.pin {
+    let pin = if let Some(pin) = emulator.gameboy.pin {
         (pin.0 + 1, pin.1)
     } else {
         (1, Instant::now())
     };
 
-    while elapsed_cycles < CYCLES_PER_FRAME {
-        let previously_halted = gameboy.halted;
-        let cycles = gameboy.cycle() as u16;
-        elapsed_cycles += cycles;
-        let mem_cycles = cycles - gameboy.mmu.cycles;
-        if mem_cycles != 0 && !previously_halted && !gameboy.halted {
-            panic!("Cycle count after considering reads/writes: mem_cycles {} | cycles: {} | micro_ops: {}", mem_cycles, cycles, gameboy.mmu.cycles)
-        }
-        (0..mem_cycles).for_each(|_| gameboy.mmu.cycle(4));
-        gameboy.mmu.cycles = 0;
+    emulator.gameboy.mmu.renderer.set_fast_forward(!sleep.load(Relaxed));
+    if emulator.gameboy.mmu.joypad.low_latency {
+        emulator.gameboy.mmu.joypad.hold(buttons);
     }
 
-    let map_held = |buttons: [KeyCode; 4]| -> Vec<KeyCode> {
-        buttons
-            .iter()
-            .filter(|&&b| input.map_or(false, |input| input.key_held(b)))
-            .copied()
-            .collect()
-    };
+    emulator.run_frame();
 
-    gameboy.mmu.joypad.held_action = map_held(ACTION);
-    gameboy.mmu.joypad.held_direction = map_held(DIRECTION);
+    let gameboy = &mut emulator.gameboy;
+    if !gameboy.mmu.joypad.low_latency {
+        gameboy.mmu.joypad.hold(buttons);
+    }
 
     if !sleep.load(Relaxed) {
         return (start.elapsed(), Duration::from_secs(0));
     }
 
-    let expected = pin.1 + Duration::from_nanos(pin.0 * NANOS_PER_FRAME);
+    let expected = pin.1 + Duration::from_nanos(pin.0 * frame_time.as_nanos() as u64);
 
     let now = Instant::now();
     gameboy.pin = if now < expected {
@@ -663,105 +1302,297 @@ fn setup_virtual_pad() -> Arc<Mutex<HashMap<&'static str, AtomicBool>>> {
     keymap
 }
 
-fn save_state(rom_path: String, gameboy: &mut Gameboy, format: SaveFile) {
+/// Battery save next to the ROM, e.g. `game.srm` for `game.gb` or its `game.gb.sav.bin` state.
+fn battery_ram_path(rom_path: &str) -> String {
+    Path::new(SaveFile::rom_path(rom_path)).with_extension("srm").to_string_lossy().into_owned()
+}
+
+/// Saves the screen as a PNG next to the ROM, named after the time it was taken.
+fn screenshot(rom_path: &str, gameboy: &Gameboy) {
+    export_png(rom_path, "", "screenshot", gameboy.mmu.renderer.corrected(&gameboy.mmu.ppu.screen[..]));
+}
+
+/// Saves a page from the Game Boy Printer as a PNG next to the ROM, named after the time it was
+/// printed.
+fn save_print(rom_path: &str, page: Vec<u8>) {
+    export_png(rom_path, "-print", "print", page);
+}
+
+/// Starts recording the audio to a WAV file next to the ROM, named after the time the recording
+/// started, or finishes the recording in progress.
+#[cfg(any(unix, windows))]
+fn toggle_recording(rom_path: &str, gameboy: &mut Gameboy) {
+    if gameboy.mmu.apu.stop_recording() {
+        Logger::info("Stopped audio recording");
+        return;
+    }
+
+    let path = timestamped_path(rom_path, "", "wav");
+    match gameboy.mmu.apu.start_recording(&path) {
+        Ok(()) => Logger::info(format!("Recording audio to {path}")),
+        Err(e) => Logger::error(format!("Unable to record audio to {path}: {e}")),
+    }
+}
+
+/// Path next to the ROM named `<rom><suffix>-<unix millis>.<extension>`.
+fn timestamped_path(rom_path: &str, suffix: &str, extension: &str) -> String {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+    let rom_path = Path::new(SaveFile::rom_path(rom_path)).with_extension("");
+    format!("{}{suffix}-{timestamp}.{extension}", rom_path.to_string_lossy())
+}
+
+/// Writes an RGBA image as `<rom><suffix>-<unix millis>.png`. The encoding runs on its own
+/// thread on desktop.
+fn export_png(rom_path: &str, suffix: &str, description: &'static str, image: Vec<u8>) {
+    let path = timestamped_path(rom_path, suffix, "png");
+
+    #[cfg(any(unix, windows))]
+    thread::spawn(move || match write(&path, png(&image, 1)) {
+        Ok(()) => Logger::info(format!("Saved {description} {path}")),
+        Err(e) => Logger::error(format!("Unable to write {description} {path}: {e}")),
+    });
+
+    #[cfg(target_arch = "wasm32")]
+    download(&path, &png(&image, 1));
+}
+
+/// Saves the full state, along with the battery-backed RAM for other emulators. Returns the
+/// thread writing the files on desktop.
+fn save_state(rom_path: String, gameboy: &mut Gameboy, format: SaveFile, slot: Option<u8>) -> Option<std::thread::JoinHandle<()>> {
     Logger::info("Saving state.");
 
-    let rom_path = SaveFile::FORMATS
-        .iter()
-        .map(SaveFile::extension)
-        .fold(rom_path, |path, extension| path.replace(extension, ""))
-        + format.extension();
+    let ram_path = battery_ram_path(&rom_path);
+    // Taking the unsaved RAM marks it as saved, so autosaves don't write it again
+    let ram = gameboy.mmu.unsaved_battery_ram().or_else(|| gameboy.mmu.battery_ram());
+    let rom_path = state_path(rom_path, slot, format);
 
     gameboy.mmu.save();
 
     let now = Instant::now();
     let save = format.save(gameboy);
-    Logger::info(format!("Serialization took {}ms", now.elapsed().as_millis()));
+    let size = match format {
+        SaveFile::BinZstd => format!("{} KiB, {} KiB uncompressed", save.len() / 1024, bincode::serialized_size(gameboy).unwrap() / 1024),
+        _ => format!("{} KiB", save.len() / 1024),
+    };
+    Logger::info(format!("Serialization took {}ms ({size})", now.elapsed().as_millis()));
+
+    // Preview of the save, so slots can be told apart without loading them
+    let thumbnail_path = format!("{rom_path}.png");
+    let thumbnail = png(&gameboy.mmu.renderer.corrected(&gameboy.mmu.ppu.screen[..]), 2);
 
     #[cfg(any(unix, windows))]
-    thread::spawn(move || {
+    return Some(thread::spawn(move || {
         let now = Instant::now();
 
         let mut save_file = File::create(&rom_path).unwrap();
         save_file.write_all(save.as_slice()).unwrap();
+        if let Err(e) = write(&thumbnail_path, thumbnail) {
+            Logger::error(format!("Unable to write thumbnail {thumbnail_path}: {e}"));
+        }
+        if let Some(Err(e)) = ram.map(|ram| write(&ram_path, ram)) {
+            Logger::error(format!("Unable to write battery save {ram_path}: {e}"));
+        }
 
         Logger::info(format!("Save file {} successfully generated in {}ms.", rom_path, now.elapsed().as_millis()));
-    });
+    }));
 
     #[cfg(target_arch = "wasm32")]
     {
-        window()
-            .and_then(|w| w.document())
-            .and_then(|d| d.create_element("a").ok())
-            .and_then(|a| a.dyn_into::<HtmlAnchorElement>().ok())
-            .and_then(|a| {
-                let array = Array::new();
-                let uarray = Uint8Array::new_with_length(save.len() as u32);
-                uarray.copy_from(&save);
-                array.push(&uarray);
-                let blob = Blob::new_with_u8_array_sequence(&array);
-                let object_url = Url::create_object_url_with_blob(&blob.unwrap());
-                a.set_href(&object_url.unwrap());
-                a.set_attribute("download", &rom_path.clone()).unwrap();
-                a.click();
-                Some(())
-            });
+        download(&rom_path, &save);
+        download(&thumbnail_path, &thumbnail);
+        if let Some(ram) = ram {
+            download(&ram_path, &ram);
+        }
+        None
     }
 }
 
-fn load_gameboy(
-    pixels: Pixels,
-    rom_path: String,
+/// Where the state of a ROM is saved, either the default save or `<rom>.slotN` for numbered slots.
+fn state_path(rom_path: String, slot: Option<u8>, format: SaveFile) -> String {
+    let path = SaveFile::rom_path(&rom_path);
+    match slot {
+        Some(slot) => format!("{path}.slot{slot}{}", format.extension()),
+        None => format!("{path}{}", format.extension()),
+    }
+}
+
+/// Rewrites the save state at `input` in the format `output`'s extension names.
+#[cfg(any(unix, windows))]
+fn convert_save(input: &str, output: &str) -> Result<(), String> {
+    let format = SaveFile::from_path(output).ok_or("the output needs a .json, .bin or .zst extension")?;
+    let data = read(input).map_err(|e| e.to_string())?;
+    let (_, gameboy) = SaveFile::detect(input, &data)?;
+    write(output, format.save(&gameboy)).map_err(|e| e.to_string())
+}
+
+/// Loads a numbered save state into the running Game Boy, keeping its window and audio stream.
+#[cfg(any(unix, windows))]
+fn load_slot(rom_path: String, gameboy: &mut Gameboy, format: SaveFile, slot: u8) {
+    let path = state_path(rom_path, Some(slot), format);
+    match read(&path).map_err(|e| e.to_string()).and_then(|data| SaveFile::detect(&path, &data)) {
+        Ok((_, state)) => {
+            gameboy.load_state(state);
+            Logger::info(format!("Loaded slot {slot} from {path}"));
+        }
+        Err(e) => Logger::error(format!("Unable to load slot {slot} from {path}: {e}")),
+    }
+}
+
+/// Encodes an RGBA image as wide as the screen as a PNG, scaled with nearest-neighbour filtering.
+fn png(image: &[u8], scale: u32) -> Vec<u8> {
+    let height = (image.len() / 4 / WIDTH) as u32;
+    let image = RgbaImage::from_raw(WIDTH as u32, height, image.to_vec()).unwrap();
+    let image = resize(&image, WIDTH as u32 * scale, height * scale, FilterType::Nearest);
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+    png.into_inner()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn download(name: &str, data: &[u8]) {
+    window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.create_element("a").ok())
+        .and_then(|a| a.dyn_into::<HtmlAnchorElement>().ok())
+        .and_then(|a| {
+            let array = Array::new();
+            let uarray = Uint8Array::new_with_length(data.len() as u32);
+            uarray.copy_from(data);
+            array.push(&uarray);
+            let blob = Blob::new_with_u8_array_sequence(&array);
+            let object_url = Url::create_object_url_with_blob(&blob.unwrap());
+            a.set_href(&object_url.unwrap());
+            a.set_attribute("download", name).unwrap();
+            a.click();
+            Some(())
+        });
+}
+
+/// How a ROM or save state is turned into a running Game Boy.
+#[derive(Default)]
+struct LoadOptions {
     cold_boot: bool,
     boot_rom: Option<Vec<u8>>,
-    mut data: Vec<u8>,
-) -> Gameboy {
+    boot_delay: u64,
+    /// Fast-forward through the boot ROM
+    skip_logo: bool,
+    force_mbc: Option<ForcedMbc>,
+    /// Refuse to boot ROMs that fail `Cartridge::verify`
+    strict_checksums: bool,
+    /// Run games with Super Game Boy functions as if on one
+    sgb: bool,
+    /// Emulate the APU without opening an audio stream
+    no_audio: bool,
+    /// Power-on contents of work RAM, VRAM and OAM, save states keep theirs
+    ram_init: RamInit,
+}
+
+/// Boot ROMs are the DMG's 256 bytes, anything else boots with the registers set up as the boot
+/// ROM would leave them. The CRC32 tells apart the DMG (59C8598E) and MGB (E6920754) dumps.
+fn check_boot_rom(boot_rom: Vec<u8>) -> Option<Vec<u8>> {
+    match boot_rom.len() {
+        0x100 => {
+            Logger::info(format!("Loaded boot ROM, CRC32 {:08X}", crc32(&boot_rom)));
+            return Some(boot_rom);
+        }
+        0x900 => Logger::error("CGB boot ROMs aren't supported, booting without one"),
+        len => Logger::error(format!("The boot ROM is {len} bytes instead of 256, booting without one")),
+    }
+    None
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 })
+    })
+}
+
+fn load_gameboy(
+    pixels: Option<Pixels>,
+    rom_path: String,
+    data: Vec<u8>,
+    options: LoadOptions,
+) -> Result<Gameboy, String> {
+    let (rom_path, data) = unpack(&rom_path, data).map_err(|e| format!("Unable to open {rom_path}: {e}"))?;
     let mut gameboy = if rom_path.ends_with(".gb") || rom_path.ends_with(".gbc") {
-        let cartridge = Cartridge::new(&data);
-        let mem = MemoryManagementUnit::new(data, cartridge, boot_rom, Path::new(&rom_path));
-        Gameboy::new(mem)
+        let mut cartridge = Cartridge::new(&data).map_err(|e| format!("Unable to load {rom_path}: {e}"))?;
+        if !cartridge.verify(&data) && options.strict_checksums {
+            return Err(format!("Refusing to boot {rom_path}, its checksums don't match"));
+        }
+        if let Some(mbc) = options.force_mbc {
+            Logger::info(format!(
+                "Forcing {mbc:?} over header MBC ID {:#04X}, this may break games that rely on their \
+                 real mapper",
+                cartridge.mbc
+            ));
+            cartridge.mbc = mbc.header();
+        }
+        let sgb = options.sgb && cartridge.supports_sgb();
+        if options.sgb && !sgb {
+            Logger::info("The cartridge has no Super Game Boy functions, ignoring --sgb");
+        }
+        let mut mem = MemoryManagementUnit::new(
+            data,
+            cartridge,
+            options.boot_rom.and_then(check_boot_rom),
+            Path::new(&rom_path),
+            !options.no_audio,
+        );
+        mem.set_ram_init(options.ram_init);
+        let mut gb = Gameboy::new(mem);
+        #[cfg(any(unix, windows))]
+        if let Ok(ram) = read(battery_ram_path(&rom_path)) {
+            Logger::info(format!("Loaded battery save {}", battery_ram_path(&rom_path)));
+            gb.mmu.load_battery_ram(&ram);
+        }
+        if sgb && !gb.mmu.ppu.enable_sgb() {
+            Logger::info("CGB games use their own colors, ignoring --sgb");
+        }
+        if gb.mmu.boot_rom.is_none() {
+            gb.warm_up(options.boot_delay);
+        } else if options.skip_logo && !gb.skip_boot_rom() {
+            Logger::error("The boot ROM didn't hand over to the game within 10 seconds, stopped skipping it");
+        }
+        gb
     } else {
-        let format = if rom_path.ends_with(".json") {
-            Json
-        } else if rom_path.ends_with(".bin") {
-            Bin
-        } else {
-            panic!("Unexpected file format for ROM save file: {}", rom_path);
-        };
-
-        let mut gb: Gameboy = match format {
-            Json => serde_json::from_slice(data.as_mut()).unwrap(),
-            Bin => bincode::deserialize(data.as_mut()).unwrap()
-        };
-        gb.init();
+        let (format, mut gb) =
+            SaveFile::detect(&rom_path, &data).map_err(|e| format!("Unable to load save file {rom_path}: {e}"))?;
+        if SaveFile::from_path(&rom_path).is_some_and(|named| named != format) {
+            Logger::info(format!("{rom_path} is a {format:?} save state despite its extension"));
+        }
+        if !options.no_audio {
+            gb.init();
+        }
         gb
     };
 
-    if cold_boot {
-        gameboy.reg = Register::new(gameboy.mmu.boot_rom.is_some())
+    if options.cold_boot {
+        gameboy.reg = Register::new(gameboy.mmu.boot_rom.is_some(), gameboy.mmu.ppu.cgb)
     }
 
-    gameboy.mmu.renderer.set_pixels(pixels);
+    if let Some(pixels) = pixels {
+        gameboy.mmu.renderer.set_pixels(pixels);
+    }
     gameboy.mmu.start();
 
-    gameboy
+    Ok(gameboy)
 }
 
 #[cfg(target_arch = "wasm32")]
 async fn setup_pixels(window: &Window) -> Pixels {
     let (width, height) = (WIDTH as u32, HEIGHT as u32);
     PixelsBuilder::new(width, height, SurfaceTexture::new(width, height, window))
-        .present_mode(PresentMode::Fifo)
+        .present_mode(VSync::On.present_mode())
         .build_async()
         .await
         .unwrap()
 }
 
 #[cfg(any(unix, windows))]
-fn setup_pixels(window: &Window) -> Pixels {
+fn setup_pixels(window: &Window, vsync: VSync) -> Pixels {
     let (width, height) = (WIDTH as u32, HEIGHT as u32);
     PixelsBuilder::new(width, height, SurfaceTexture::new(width, height, window))
-        .present_mode(PresentMode::AutoNoVsync)
+        .present_mode(vsync.present_mode())
         .build()
         .unwrap()
 }
@@ -776,5 +1607,10 @@ fn setup_window(rom_path: String) -> WindowBuilder {
         .with_fullscreen(Some(Borderless(None)))
 }
 
-const CYCLES_PER_FRAME: u16 = 17556;
 const NANOS_PER_FRAME: u64 = 16742706;
+
+/// Base clock of the Game Boy in Hz
+const CLOCK_RATE: f64 = 4194304.0;
+
+/// Number keys selecting save state slots 0-9
+const SLOT_KEYS: [KeyCode; 10] = [Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9];
@@ -4,4 +4,76 @@ pub trait MemoryBankController: MemoryArea {
     fn start(&mut self) {}
 
     fn save(&mut self) {}
+
+    /// ROM bank currently mapped to 4000-7FFF, if the controller supports banking.
+    fn rom_bank(&self) -> Option<u16> {
+        None
+    }
+
+    /// RAM (or RTC register) bank currently mapped to A000-BFFF.
+    fn ram_bank(&self) -> Option<u8> {
+        None
+    }
+
+    fn ram_enabled(&self) -> Option<bool> {
+        None
+    }
+
+    fn expansion_mode(&self) -> Option<u8> {
+        None
+    }
+
+    /// Forces a ROM bank, recomputing the mapping the same way a bank-select write would.
+    fn set_rom_bank(&mut self, _bank: u16) {}
+
+    fn set_ram_bank(&mut self, _bank: u8) {}
+
+    fn set_ram_enabled(&mut self, _enabled: bool) {}
+
+    fn set_expansion_mode(&mut self, _mode: u8) {}
+
+    /// Makes any real time clock follow emulated time instead of the wall clock, so it speeds up
+    /// and slows down along with the emulation.
+    fn set_emulated_clock(&mut self, _emulated: bool) {}
+
+    /// Makes any real time clock follow emulated time from a fixed epoch, so that it never reads
+    /// the wall clock and runs can be reproduced exactly.
+    fn set_deterministic_clock(&mut self) {}
+
+    /// Battery-backed RAM, sized to the cartridge header, if the cartridge has a battery.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Restores battery-backed RAM, ignoring anything past the RAM size.
+    fn load_battery_ram(&mut self, _ram: &[u8]) {}
+
+    /// Whether the game stored a byte in cartridge RAM since the last call. Writes while RAM is
+    /// disabled, or to RTC and camera registers mapped in its place, don't count.
+    fn take_ram_written(&mut self) -> bool {
+        false
+    }
+
+    /// Whether the cartridge's rumble motor is running.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    /// Human-readable summary of the banking registers, e.g. for a debugger overlay.
+    fn bank_info(&self) -> String {
+        let mut info = vec![];
+        if let Some(bank) = self.rom_bank() {
+            info.push(format!("ROM bank: {bank}"));
+        }
+        if let Some(bank) = self.ram_bank() {
+            info.push(format!("RAM bank: {bank}"));
+        }
+        if let Some(enabled) = self.ram_enabled() {
+            info.push(format!("RAM enabled: {enabled}"));
+        }
+        if let Some(mode) = self.expansion_mode() {
+            info.push(format!("Mode: {mode}"));
+        }
+        info.join(", ")
+    }
 }
@@ -5,7 +5,9 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct MBC0 {
+    #[serde(with = "serde_bytes")]
     pub rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
     pub ram: Vec<u8>,
 }
 
@@ -20,7 +22,8 @@ impl MBC0 {
 impl MemoryArea for MBC0 {
     fn read(&self, address: usize) -> Option<u8> {
         Some(match address {
-            0x0000..=0x7FFF => self.rom[address],
+            // Homebrew and test ROMs can be shorter than 32 KiB, past their end the bus floats
+            0x0000..=0x7FFF => self.rom.get(address).copied().unwrap_or(0xFF),
             0xA000..=0xBFFF => self.ram[address - 0xA000],
             _ => return None,
         })
@@ -8,36 +8,119 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct MBC1 {
     cartridge: Cartridge,
+    #[serde(with = "serde_bytes")]
     rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
     ram: Vec<u8>,
+    /// BANK1 in bits 0-4 and BANK2 in bits 5-6, the bank mapped to 4000-7FFF
     rom_bank: u8,
+    /// BANK2, the upper ROM bank bits which also pick the RAM bank in mode 1
     ram_bank: u8,
+    /// Bank mapped to 0000-3FFF, only moved by BANK2 in mode 1
+    zero_bank_offset: usize,
     rom_offset: usize,
     ram_offset: usize,
     ram_enabled: bool,
     expansion_mode: u8,
+    /// Set when the game stores a byte in RAM, until [`MemoryBankController::take_ram_written`]
+    #[serde(skip)]
+    ram_written: bool,
 }
 
 impl MBC1 {
     pub fn new(cartridge: Cartridge, rom: Vec<u8>) -> Self {
         Self {
+            ram: vec![0; cartridge.ram_bytes()],
             cartridge,
             rom,
-            ram: vec![0; 1024 * 1024 * 2],
+            rom_bank: 1,
             rom_offset: 0x4000,
             ..Default::default()
         }
     }
+
+    fn select_rom_bank(&mut self, bank: u8) {
+        self.rom_bank = bank;
+        self.update_offsets();
+    }
+
+    fn select_ram_bank(&mut self, bank: u8) {
+        self.ram_bank = bank;
+        self.rom_bank = (self.rom_bank & 0x1F) | bank << 5;
+        self.update_offsets();
+    }
+
+    /// BANK2 always feeds ROM bank bits 5-6 for 4000-7FFF. In mode 1 it also maps banks
+    /// 0x20/0x40/0x60 to 0000-3FFF and selects the RAM bank, in mode 0 both stay at bank 0. Banks
+    /// past the end of the ROM or RAM wrap around, as the chip just drops the unconnected lines.
+    fn update_offsets(&mut self) {
+        self.rom_offset = (self.rom_bank as usize * 0x4000) % self.rom.len();
+        (self.zero_bank_offset, self.ram_offset) = match self.expansion_mode {
+            0 => (0, 0),
+            _ => (
+                ((self.ram_bank as usize) << 5) * 0x4000 % self.rom.len(),
+                self.cartridge.ram_bank_offset(self.ram_bank),
+            ),
+        };
+    }
 }
 
-impl MemoryBankController for MBC1 {}
+impl MemoryBankController for MBC1 {
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.cartridge.has_battery().then_some(self.ram.as_slice())
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        let size = ram.len().min(self.ram.len());
+        self.ram[..size].copy_from_slice(&ram[..size]);
+    }
+
+    fn take_ram_written(&mut self) -> bool {
+        std::mem::take(&mut self.ram_written)
+    }
+
+    fn rom_bank(&self) -> Option<u16> {
+        Some(self.rom_bank as u16)
+    }
+
+    fn ram_bank(&self) -> Option<u8> {
+        Some(self.ram_bank)
+    }
+
+    fn ram_enabled(&self) -> Option<bool> {
+        Some(self.ram_enabled)
+    }
+
+    fn expansion_mode(&self) -> Option<u8> {
+        Some(self.expansion_mode)
+    }
+
+    fn set_rom_bank(&mut self, bank: u16) {
+        let bank = bank as u8 & 0x7F;
+        self.ram_bank = bank >> 5;
+        self.select_rom_bank((bank & 0x60) | max(1, bank & 0x1F))
+    }
+
+    fn set_ram_bank(&mut self, bank: u8) {
+        self.select_ram_bank(bank & 3)
+    }
+
+    fn set_ram_enabled(&mut self, enabled: bool) {
+        self.ram_enabled = enabled
+    }
+
+    fn set_expansion_mode(&mut self, mode: u8) {
+        self.expansion_mode = mode & 1;
+        self.update_offsets();
+    }
+}
 
 impl MemoryArea for MBC1 {
     fn read(&self, address: usize) -> Option<u8> {
         Some(match address {
-            0x0000..=0x3FFF => self.rom[address],
+            0x0000..=0x3FFF => self.rom[self.zero_bank_offset + address],
             0x4000..=0x7FFF => self.rom[self.rom_offset + (address & 0x3FFF)],
-            0xA000..=0xBFFF if self.ram_enabled => self.ram[self.ram_offset + (address & 0x1FFF)],
+            0xA000..=0xBFFF if self.ram_enabled => *self.ram.get(self.ram_offset + (address & 0x1FFF)).unwrap_or(&0xFF),
             0xA000..=0xBFFF => 0xFF,
             _ => return None,
         })
@@ -45,34 +128,15 @@ impl MemoryArea for MBC1 {
 
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
-            0x0000..=0x1FFF => match self.cartridge.mbc {
-                2 | 3 => self.ram_enabled = value & 0x0F == 0x0A,
-                _ => (),
-            },
-            0x2000..=0x3FFF => match self.cartridge.mbc {
-                1 | 2 | 3 => {
-                    self.rom_bank = (self.rom_bank & 0x60) + max(1, value & 0x1F);
-                    self.rom_offset = self.rom_bank as usize * 0x4000;
-                }
-                _ => (),
-            },
-            0x4000..=0x5FFF => match self.cartridge.mbc {
-                1 | 2 | 3 if self.expansion_mode != 0 => {
-                    self.ram_bank = value & 3;
-                    self.ram_offset = self.ram_bank as usize * 0x2000;
-                }
-                1 | 2 | 3 => {
-                    self.rom_bank = (self.rom_bank & 0x1F) + ((value & 3) << 5);
-                    self.rom_offset = self.rom_bank as usize * 0x4000;
-                }
-                _ => (),
-            },
-            0x6000..=0x7FFF => match self.cartridge.mbc {
-                2 | 3 => self.expansion_mode = value & 1,
-                _ => (),
-            },
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.select_rom_bank((self.rom_bank & 0x60) | max(1, value & 0x1F)),
+            0x4000..=0x5FFF => self.select_ram_bank(value & 3),
+            0x6000..=0x7FFF => self.set_expansion_mode(value),
             0xA000..=0xBFFF if self.ram_enabled => {
-                self.ram[self.ram_offset + (address & 0x1FFF)] = value
+                if let Some(byte) = self.ram.get_mut(self.ram_offset + (address & 0x1FFF)) {
+                    *byte = value;
+                    self.ram_written = true;
+                }
             }
             0xA000..=0xBFFF => (),
             _ => return false,
@@ -8,11 +8,16 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct MBC2 {
     cartridge: Cartridge,
+    #[serde(with = "serde_bytes")]
     rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
     ram: Vec<u8>,
     rom_bank: u8,
     rom_offset: usize,
     ram_enabled: bool,
+    /// Set when the game stores a byte in RAM, until [`MemoryBankController::take_ram_written`]
+    #[serde(skip)]
+    ram_written: bool,
 }
 
 impl MBC2 {
@@ -25,9 +30,44 @@ impl MBC2 {
             ..Default::default()
         }
     }
+
+    fn select_rom_bank(&mut self, bank: u8) {
+        self.rom_bank = max(1, bank & 0x0F);
+        self.rom_offset = self.rom_bank as usize * 0x4000;
+    }
 }
 
-impl MemoryBankController for MBC2 {}
+impl MemoryBankController for MBC2 {
+    /// The built-in RAM is always 512 half-bytes, no matter what the header says.
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.cartridge.has_battery().then_some(self.ram.as_slice())
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        let size = ram.len().min(self.ram.len());
+        self.ram[..size].copy_from_slice(&ram[..size]);
+    }
+
+    fn take_ram_written(&mut self) -> bool {
+        std::mem::take(&mut self.ram_written)
+    }
+
+    fn rom_bank(&self) -> Option<u16> {
+        Some(self.rom_bank as u16)
+    }
+
+    fn ram_enabled(&self) -> Option<bool> {
+        Some(self.ram_enabled)
+    }
+
+    fn set_rom_bank(&mut self, bank: u16) {
+        self.select_rom_bank(bank as u8)
+    }
+
+    fn set_ram_enabled(&mut self, enabled: bool) {
+        self.ram_enabled = enabled
+    }
+}
 
 impl MemoryArea for MBC2 {
     fn read(&self, address: usize) -> Option<u8> {
@@ -49,11 +89,13 @@ impl MemoryArea for MBC2 {
             }
             0x2000..=0x3FFF => {
                 if (address & 0x0100) != 0 {
-                    self.rom_bank = max(1, value & 0x0F);
-                    self.rom_offset = self.rom_bank as usize * 0x4000;
+                    self.select_rom_bank(value);
                 }
             }
-            0xA000..=0xA1FF if self.ram_enabled => self.ram[address & 0x01FF] = value & 0x0F,
+            0xA000..=0xA1FF if self.ram_enabled => {
+                self.ram[address & 0x01FF] = value & 0x0F;
+                self.ram_written = true;
+            }
             0xA000..=0xA1FF => (),
             _ => return false,
         }
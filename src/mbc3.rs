@@ -13,7 +13,9 @@ use crate::mmu::MemoryArea;
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MBC3 {
     cartridge: Cartridge,
+    #[serde(with = "serde_bytes")]
     rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
     ram: Vec<u8>,
     rom_bank: u8,
     ram_rtc_bank: u8,
@@ -23,14 +25,29 @@ pub struct MBC3 {
     expansion_mode: u8,
     rtc: RealTimeClock,
     rtc_enabled: bool,
+    /// Set when the game stores a byte in RAM, until [`MemoryBankController::take_ram_written`]
+    #[serde(skip)]
+    ram_written: bool,
 }
 
+/// M-cycles per second of emulated time
+const CYCLES_PER_SECOND: u64 = 1 << 20;
+
+/// Unix time a deterministic clock always reads
+const FIXED_EPOCH: u64 = 0;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct RealTimeClock {
     #[serde(skip)]
     clock: PausableClock,
     #[serde(skip)]
     additional_secs: u64,
+    /// When set, the clock counts emulated M-cycles instead of following `clock`.
+    #[serde(skip)]
+    emulated: Option<EmulatedClock>,
+    /// Read `FIXED_EPOCH` instead of the wall clock for the Unix time
+    #[serde(skip)]
+    fixed_epoch: bool,
     seconds: u8,
     minutes: u8,
     hours: u8,
@@ -38,10 +55,53 @@ struct RealTimeClock {
     halted: bool,
     latched: bool,
     day_carry_bit: bool,
+    /// Unix time of the last save
     timestamp: u64,
+    /// Seconds the running counter was at on the last save
+    counter: u64,
+}
+
+#[derive(Default, Debug)]
+struct EmulatedClock {
+    /// M-cycles counted while the clock wasn't halted
+    elapsed: u64,
+    /// Last M-cycle count seen, to compute the time elapsed since
+    last_sync: Option<u64>,
 }
 
 impl RealTimeClock {
+    fn unix_secs(&self) -> u64 {
+        if self.fixed_epoch {
+            return FIXED_EPOCH;
+        }
+        SystemTime::now().duration_since(wasm_timer::UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    /// Seconds counted since the last rebase, which a halted clock doesn't add to.
+    fn elapsed_secs(&self) -> u64 {
+        if self.halted {
+            return 0;
+        }
+        match &self.emulated {
+            Some(emulated) => emulated.elapsed / CYCLES_PER_SECOND,
+            None => self.clock.now().elapsed_millis() / 1000,
+        }
+    }
+
+    fn sync(&mut self, cycles: u64) {
+        if let Some(emulated) = &mut self.emulated {
+            // The cycle count restarts after a reset or when loading a save state
+            let delta = match emulated.last_sync {
+                Some(last_sync) => cycles.checked_sub(last_sync).unwrap_or(cycles),
+                None => 0,
+            };
+            if !self.halted {
+                emulated.elapsed += delta;
+            }
+            emulated.last_sync = Some(cycles);
+        }
+    }
+
     fn latch(&mut self, value: u8) {
         match value {
             0 => {
@@ -50,12 +110,12 @@ impl RealTimeClock {
             1 => {
                 if self.latched {
                     self.latched = false;
-                    let secs = self.clock.now().elapsed_millis() / 1000 + self.additional_secs;
+                    let secs = self.counter_secs();
                     self.seconds = (secs % 60) as u8;
                     self.minutes = ((secs / 60) % 60) as u8;
                     self.hours = ((secs / 3600) % 24) as u8;
-                    let days = (secs / (3600 * 24)) as u16;
-                    self.days = days % 0x1FF;
+                    let days = secs / (3600 * 24);
+                    self.days = (days & 0x1FF) as u16;
                     self.day_carry_bit |= days > 0x1FF; // Day carry bit is not reset
                 }
             }
@@ -79,45 +139,54 @@ impl RealTimeClock {
     }
 
     fn write(&mut self, register: u8, value: u8) {
-        if (0x08..=0x0A).contains(&value) {
-            if self.halted {
-                return;
-            }
-
-            let total = self.seconds as u64
-                + self.minutes as u64 * 60
-                + self.hours as u64 * 3600
-                + self.days as u64 * 24 * 3600;
-
-            self.clock = PausableClock::new(Duration::from_secs(total), self.clock.is_paused());
-        }
-
         match register {
             0x08 => self.seconds = value,
             0x09 => self.minutes = value,
             0x0A => self.hours = value,
             0x0B => self.days = (self.days & 0x100) | value as u16,
             0x0C => {
-                self.days = value as u16 | if value & 1 == 0 { value as u16 } else { 0x100 };
+                self.days = (self.days & 0xFF) | (value as u16 & 1) << 8;
                 self.day_carry_bit = value & 0x80 != 0;
                 self.halted = value & 0x40 != 0;
-                if self.halted {
-                    self.clock.pause();
-                } else {
-                    self.clock.resume();
-                }
             }
-            _ => (),
-        };
+            _ => return,
+        }
+        self.rebase(self.register_secs());
+    }
+
+    /// Seconds the running counter is at.
+    fn counter_secs(&self) -> u64 {
+        self.elapsed_secs() + self.additional_secs
+    }
+
+    /// Seconds the latched registers add up to.
+    fn register_secs(&self) -> u64 {
+        self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600 + self.days as u64 * 24 * 3600
+    }
+
+    /// Restarts the running counter from `secs`.
+    fn rebase(&mut self, secs: u64) {
+        self.additional_secs = secs;
+        self.clock = PausableClock::new(Duration::ZERO, self.halted);
+        if let Some(emulated) = &mut self.emulated {
+            emulated.elapsed = 0;
+        }
     }
 }
 
 impl MBC3 {
+    /// Keeps time the way `running`'s clock was set to, which save states don't store. The clock
+    /// is rebased once the state starts.
+    pub fn keep_clock_mode(&mut self, running: &MBC3) {
+        self.rtc.fixed_epoch = running.rtc.fixed_epoch;
+        self.rtc.emulated = running.rtc.emulated.as_ref().map(|_| EmulatedClock::default());
+    }
+
     pub fn new(cartridge: Cartridge, rom: Vec<u8>) -> Self {
         Self {
+            ram: vec![0; cartridge.ram_bytes()],
             cartridge,
             rom,
-            ram: vec![0; 1024 * 1024 * 2],
             rom_bank: 0,
             ram_rtc_bank: 0,
             rom_offset: 0x4000,
@@ -127,6 +196,8 @@ impl MBC3 {
             rtc: RealTimeClock {
                 clock: Default::default(),
                 additional_secs: 0,
+                emulated: None,
+                fixed_epoch: false,
                 seconds: 0,
                 minutes: 0,
                 hours: 0,
@@ -135,10 +206,28 @@ impl MBC3 {
                 latched: false,
                 day_carry_bit: false,
                 timestamp: SystemTime::now().duration_since(wasm_timer::UNIX_EPOCH).unwrap().as_secs(),
+                counter: 0,
             },
             rtc_enabled: false,
+            ram_written: false,
         }
     }
+
+    fn select_rom_bank(&mut self, bank: u8) {
+        self.rom_bank = bank;
+        self.rom_offset = self.rom_bank as usize * 0x4000;
+    }
+
+    /// Brings an emulated RTC up to date with the given M-cycle count.
+    pub fn sync_clock(&mut self, cycles: u64) {
+        self.rtc.sync(cycles);
+    }
+
+    fn select_ram_rtc_bank(&mut self, bank: u8) {
+        self.ram_rtc_bank = bank;
+        self.rtc_enabled = self.ram_rtc_bank > 0x03;
+        self.ram_offset = self.cartridge.ram_bank_offset(self.ram_rtc_bank);
+    }
 }
 
 impl MemoryArea for MBC3 {
@@ -147,7 +236,7 @@ impl MemoryArea for MBC3 {
             0x0000..=0x3FFF => self.rom[address],
             0x4000..=0x7FFF => self.rom[self.rom_offset + (address & 0x3FFF)],
             0xA000..=0xBFFF if self.ram_enabled && !self.rtc_enabled => {
-                self.ram[self.ram_offset + (address & 0x1FFF)]
+                *self.ram.get(self.ram_offset + (address & 0x1FFF)).unwrap_or(&0xFF)
             }
             0xA000..=0xBFFF if self.ram_enabled => self.rtc.read(self.ram_rtc_bank),
             0xA000..=0xBFFF => 0xFF,
@@ -158,18 +247,12 @@ impl MemoryArea for MBC3 {
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
             0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
-            0x2000..=0x3FFF => {
-                self.rom_bank = max(1, value) & 0x7F;
-                self.rom_offset = self.rom_bank as usize * 0x4000;
-            }
+            0x2000..=0x3FFF => self.select_rom_bank(max(1, value) & 0x7F),
             0x4000..=0x5FFF => {
                 if self.expansion_mode != 0 {
-                    self.ram_rtc_bank = value;
-                    self.rtc_enabled = self.ram_rtc_bank > 0x03;
-                    self.ram_offset = self.ram_rtc_bank as usize * 0x2000;
+                    self.select_ram_rtc_bank(value);
                 } else {
-                    self.rom_bank = (self.rom_bank & 0x1F) + ((value & 3) << 5);
-                    self.rom_offset = self.rom_bank as usize * 0x4000;
+                    self.select_rom_bank((self.rom_bank & 0x1F) + ((value & 3) << 5));
                 }
             }
             0x6000..=0x7FFF => {
@@ -180,7 +263,10 @@ impl MemoryArea for MBC3 {
                 }
             }
             0xA000..=0xBFFF if self.ram_enabled && !self.rtc_enabled => {
-                self.ram[self.ram_offset + (address & 0x1FFF)] = value
+                if let Some(byte) = self.ram.get_mut(self.ram_offset + (address & 0x1FFF)) {
+                    *byte = value;
+                    self.ram_written = true;
+                }
             }
             0xA000..=0xBFFF if self.ram_enabled => self.rtc.write(self.ram_rtc_bank, value),
             0xA000..=0xBFFF => (),
@@ -191,21 +277,76 @@ impl MemoryArea for MBC3 {
 }
 
 impl MemoryBankController for MBC3 {
-    fn start(&mut self) {
-        let total_secs = self.rtc.seconds as u64
-            + self.rtc.minutes as u64 * 60
-            + self.rtc.hours as u64 * 3600
-            + self.rtc.days as u64 * 24 * 3600;
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.cartridge.has_battery().then_some(self.ram.as_slice())
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        let size = ram.len().min(self.ram.len());
+        self.ram[..size].copy_from_slice(&ram[..size]);
+    }
+
+    fn take_ram_written(&mut self) -> bool {
+        std::mem::take(&mut self.ram_written)
+    }
+
+    fn rom_bank(&self) -> Option<u16> {
+        Some(self.rom_bank as u16)
+    }
+
+    fn ram_bank(&self) -> Option<u8> {
+        Some(self.ram_rtc_bank)
+    }
 
-        self.rtc.additional_secs = SystemTime::now()
-            .duration_since(wasm_timer::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            - self.rtc.timestamp
-            + total_secs;
+    fn ram_enabled(&self) -> Option<bool> {
+        Some(self.ram_enabled)
+    }
+
+    fn expansion_mode(&self) -> Option<u8> {
+        Some(self.expansion_mode)
+    }
+
+    fn set_rom_bank(&mut self, bank: u16) {
+        self.select_rom_bank(max(1, bank as u8 & 0x7F))
+    }
+
+    fn set_ram_bank(&mut self, bank: u8) {
+        self.select_ram_rtc_bank(bank)
+    }
+
+    fn set_ram_enabled(&mut self, enabled: bool) {
+        self.ram_enabled = enabled
+    }
+
+    fn set_expansion_mode(&mut self, mode: u8) {
+        self.expansion_mode = mode & 1
+    }
+
+    fn set_emulated_clock(&mut self, emulated: bool) {
+        self.rtc.emulated = emulated.then(|| EmulatedClock {
+            elapsed: self.rtc.clock.now().elapsed_millis() * CYCLES_PER_SECOND / 1000,
+            last_sync: None,
+        });
+    }
+
+    fn set_deterministic_clock(&mut self) {
+        self.rtc.fixed_epoch = true;
+        self.rtc.emulated = Some(EmulatedClock::default());
+        self.start();
+    }
+
+    fn start(&mut self) {
+        // A fixed epoch can be behind a save made with the wall clock, no time passed since then
+        let offline_secs = if self.rtc.halted {
+            0
+        } else {
+            self.rtc.unix_secs().saturating_sub(self.rtc.timestamp)
+        };
+        self.rtc.rebase(self.rtc.counter + offline_secs);
     }
 
     fn save(&mut self) {
-        self.rtc.timestamp = SystemTime::now().duration_since(wasm_timer::UNIX_EPOCH).unwrap().as_secs();
+        self.rtc.counter = self.rtc.counter_secs();
+        self.rtc.timestamp = self.rtc.unix_secs();
     }
 }
@@ -7,35 +7,103 @@ use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct MBC5 {
     cartridge: Cartridge,
+    #[serde(with = "serde_bytes")]
     rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
     ram: Vec<u8>,
     rom_bank: u16,
     ram_bank: u8,
     rom_offset: usize,
     ram_offset: usize,
     ram_enabled: bool,
+    /// Motor state on rumble cartridges, driven by bit 3 of the RAM bank register
+    #[serde(skip)]
+    rumble: bool,
+    /// Set when the game stores a byte in RAM, until [`MemoryBankController::take_ram_written`]
+    #[serde(skip)]
+    ram_written: bool,
 }
 
 impl MBC5 {
     pub fn new(cartridge: Cartridge, rom: Vec<u8>) -> Self {
         Self {
+            ram: vec![0; cartridge.ram_bytes()],
             cartridge,
             rom,
-            ram: vec![0; 1024 * 1024 * 2],
             rom_offset: 0x4000,
             ..Default::default()
         }
     }
+
+    fn select_rom_bank(&mut self, bank: u16) {
+        self.rom_bank = bank & 0x1FF;
+        self.rom_offset = self.rom_bank as usize * 0x4000;
+    }
+
+    fn has_rumble(&self) -> bool {
+        matches!(self.cartridge.mbc, 0x1C..=0x1E)
+    }
+
+    fn select_ram_bank(&mut self, bank: u8) {
+        if self.has_rumble() {
+            self.rumble = bank & 0x08 != 0;
+            self.ram_bank = bank & 0x07;
+        } else {
+            self.ram_bank = bank & 0x0F;
+        }
+        self.ram_offset = self.cartridge.ram_bank_offset(self.ram_bank);
+    }
 }
 
-impl MemoryBankController for MBC5 {}
+impl MemoryBankController for MBC5 {
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn battery_ram(&self) -> Option<&[u8]> {
+        self.cartridge.has_battery().then_some(self.ram.as_slice())
+    }
+
+    fn load_battery_ram(&mut self, ram: &[u8]) {
+        let size = ram.len().min(self.ram.len());
+        self.ram[..size].copy_from_slice(&ram[..size]);
+    }
+
+    fn take_ram_written(&mut self) -> bool {
+        std::mem::take(&mut self.ram_written)
+    }
+
+    fn rom_bank(&self) -> Option<u16> {
+        Some(self.rom_bank)
+    }
+
+    fn ram_bank(&self) -> Option<u8> {
+        Some(self.ram_bank)
+    }
+
+    fn ram_enabled(&self) -> Option<bool> {
+        Some(self.ram_enabled)
+    }
+
+    fn set_rom_bank(&mut self, bank: u16) {
+        self.select_rom_bank(bank)
+    }
+
+    fn set_ram_bank(&mut self, bank: u8) {
+        self.select_ram_bank(bank)
+    }
+
+    fn set_ram_enabled(&mut self, enabled: bool) {
+        self.ram_enabled = enabled
+    }
+}
 
 impl MemoryArea for MBC5 {
     fn read(&self, address: usize) -> Option<u8> {
         Some(match address {
             0x0000..=0x3FFF => self.rom[address],
             0x4000..=0x7FFF => self.rom[self.rom_offset + (address & 0x3FFF)],
-            0xA000..=0xBFFF if self.ram_enabled => self.ram[self.ram_offset + (address & 0x1FFF)],
+            0xA000..=0xBFFF if self.ram_enabled => *self.ram.get(self.ram_offset + (address & 0x1FFF)).unwrap_or(&0xFF),
             0xA000..=0xBFFF => 0xFF,
             _ => return None,
         })
@@ -44,20 +112,16 @@ impl MemoryArea for MBC5 {
     fn write(&mut self, address: usize, value: u8) -> bool {
         match address {
             0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
-            0x2000..=0x2FFF => {
-                self.rom_bank = (self.rom_bank & 0x100) | u16::from(value);
-                self.rom_offset = self.rom_bank as usize * 0x4000;
-            }
+            0x2000..=0x2FFF => self.select_rom_bank((self.rom_bank & 0x100) | u16::from(value)),
             0x3000..=0x3FFF => {
-                self.rom_bank = (self.rom_bank & 0xFF) | ((u16::from(value) & 0x01) << 8);
-                self.rom_offset = self.rom_bank as usize * 0x4000;
-            }
-            0x4000..=0x5FFF => {
-                self.ram_bank = value & 0x0F;
-                self.ram_offset = self.ram_bank as usize * 0x2000;
+                self.select_rom_bank((self.rom_bank & 0xFF) | ((u16::from(value) & 0x01) << 8))
             }
+            0x4000..=0x5FFF => self.select_ram_bank(value),
             0xA000..=0xBFFF if self.ram_enabled => {
-                self.ram[self.ram_offset + (address & 0x1FFF)] = value
+                if let Some(byte) = self.ram.get_mut(self.ram_offset + (address & 0x1FFF)) {
+                    *byte = value;
+                    self.ram_written = true;
+                }
             }
             0x6000..=0x7FFF | 0xA000..=0xBFFF => (),
             _ => return false,
@@ -0,0 +1,171 @@
+use crate::cartridge::Cartridge;
+use crate::mbc::MemoryBankController;
+use crate::mmu::MemoryArea;
+
+use serde::{Deserialize, Serialize};
+
+/// Mapper used by the Game Boy Camera (cartridge type 0xFC, "POCKET CAMERA").
+///
+/// Implemented:
+/// - 0000-1FFF: RAM write enable (0x0A). RAM can be read even while disabled.
+/// - 2000-3FFF: ROM bank select (6 bits, bank 0 is not remapped to 1).
+/// - 4000-5FFF: RAM bank select (4 bits). Setting bit 4 maps the camera registers into
+///   A000-BFFF instead of RAM.
+/// - A000: capture trigger and status. Bit 0 starts a capture and reads back as 0 once it's done.
+/// - A006-A035: 4x4 dithering matrix, three thresholds per pixel, used by captures.
+///
+/// The register block is mirrored every 0x80 bytes, and every register other than A000 reads
+/// back as 0x00.
+///
+/// Stubbed, since there is no real sensor:
+/// - A000 bits 1-2 (capture flags) are kept but ignored.
+/// - A001 (gain and edge mode), A002-A003 (exposure time), A004 (edge strength and inversion)
+///   and A005 (sensor voltage offset) are stored and ignored.
+/// - Captures finish instantly, and dither a static gradient through the matrix into the image
+///   area of RAM bank 0 (A100-AEFF).
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct PocketCamera {
+    cartridge: Cartridge,
+    #[serde(with = "serde_bytes")]
+    rom: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    ram: Vec<u8>,
+    rom_bank: u8,
+    ram_bank: u8,
+    rom_offset: usize,
+    ram_offset: usize,
+    ram_enabled: bool,
+    registers_mapped: bool,
+    registers: Vec<u8>,
+}
+
+impl PocketCamera {
+    const REGISTER_COUNT: usize = 0x36;
+    const MATRIX_START: usize = 0x06;
+    const IMAGE_START: usize = 0x100;
+    const IMAGE_WIDTH: usize = 128;
+    const IMAGE_HEIGHT: usize = 112;
+
+    pub fn new(cartridge: Cartridge, rom: Vec<u8>) -> Self {
+        Self {
+            cartridge,
+            rom,
+            ram: vec![0; 0x2000 * 16],
+            rom_bank: 1,
+            rom_offset: 0x4000,
+            registers: vec![0; Self::REGISTER_COUNT],
+            ..Default::default()
+        }
+    }
+
+    fn select_rom_bank(&mut self, bank: u8) {
+        self.rom_bank = bank & 0x3F;
+        self.rom_offset = self.rom_bank as usize * 0x4000;
+    }
+
+    fn select_ram_bank(&mut self, bank: u8) {
+        self.registers_mapped = bank & 0x10 != 0;
+        self.ram_bank = bank & 0x0F;
+        self.ram_offset = self.ram_bank as usize * 0x2000;
+    }
+
+    fn read_register(&self, address: usize) -> u8 {
+        match address & 0x7F {
+            0x00 => self.registers[0] & 0x07,
+            _ => 0x00,
+        }
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) {
+        match address & 0x7F {
+            0x00 => {
+                self.registers[0] = value & 0x07;
+                if value & 0x01 != 0 {
+                    self.capture();
+                }
+            }
+            register if register < Self::REGISTER_COUNT => self.registers[register] = value,
+            _ => (),
+        }
+    }
+
+    /// Dithers the static sensor image into 2bpp tiles, finishing the capture immediately.
+    fn capture(&mut self) {
+        for y in 0..Self::IMAGE_HEIGHT {
+            for x in 0..Self::IMAGE_WIDTH {
+                // Horizontal gradient, from black on the left to white on the right
+                let sensor = (x * 0xFF / (Self::IMAGE_WIDTH - 1)) as u8;
+
+                let threshold = Self::MATRIX_START + ((y % 4) * 4 + x % 4) * 3;
+                let thresholds = &self.registers[threshold..threshold + 3];
+                let color = match sensor {
+                    v if v < thresholds[0] => 3,
+                    v if v < thresholds[1] => 2,
+                    v if v < thresholds[2] => 1,
+                    _ => 0,
+                };
+
+                let tile = (y / 8) * (Self::IMAGE_WIDTH / 8) + x / 8;
+                let row = Self::IMAGE_START + tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                for (plane, byte) in self.ram[row..row + 2].iter_mut().enumerate() {
+                    *byte = (*byte & !(1 << bit)) | (((color >> plane) & 1) << bit);
+                }
+            }
+        }
+        self.registers[0] &= !0x01;
+    }
+}
+
+impl MemoryBankController for PocketCamera {
+    fn rom_bank(&self) -> Option<u16> {
+        Some(self.rom_bank as u16)
+    }
+
+    fn ram_bank(&self) -> Option<u8> {
+        Some(self.ram_bank)
+    }
+
+    fn ram_enabled(&self) -> Option<bool> {
+        Some(self.ram_enabled)
+    }
+
+    fn set_rom_bank(&mut self, bank: u16) {
+        self.select_rom_bank(bank as u8)
+    }
+
+    fn set_ram_bank(&mut self, bank: u8) {
+        self.select_ram_bank(bank)
+    }
+
+    fn set_ram_enabled(&mut self, enabled: bool) {
+        self.ram_enabled = enabled
+    }
+}
+
+impl MemoryArea for PocketCamera {
+    fn read(&self, address: usize) -> Option<u8> {
+        Some(match address {
+            0x0000..=0x3FFF => self.rom[address],
+            0x4000..=0x7FFF => self.rom[self.rom_offset + (address & 0x3FFF)],
+            0xA000..=0xBFFF if self.registers_mapped => self.read_register(address),
+            0xA000..=0xBFFF => self.ram[self.ram_offset + (address & 0x1FFF)],
+            _ => return None,
+        })
+    }
+
+    fn write(&mut self, address: usize, value: u8) -> bool {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.select_rom_bank(value),
+            0x4000..=0x5FFF => self.select_ram_bank(value),
+            0xA000..=0xBFFF if self.registers_mapped => self.write_register(address, value),
+            0xA000..=0xBFFF if self.ram_enabled => {
+                self.ram[self.ram_offset + (address & 0x1FFF)] = value
+            }
+            0x6000..=0x7FFF | 0xA000..=0xBFFF => (),
+            _ => return false,
+        }
+        true
+    }
+}
@@ -1,4 +1,5 @@
 use crate::cartridge::Cartridge;
+use crate::cheats::Cheats;
 use crate::interrupt::InterruptHandler;
 use crate::interrupt::InterruptId::{Input, Serial, Stat, Timing, VBlank};
 use crate::joypad::Joypad;
@@ -7,8 +8,10 @@ use crate::ppu::PixelProcessingUnit;
 use crate::timer::Timer;
 use std::any::{Any, TypeId};
 
+use std::mem::take;
 use std::path::Path;
 
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 use crate::mbc::MemoryBankController;
@@ -16,8 +19,10 @@ use crate::mbc0::MBC0;
 use crate::mbc1::MBC1;
 
 use crate::renderer::Renderer;
-use cpal::traits::StreamTrait;
 
+#[cfg(any(unix, windows))]
+use crate::link::LinkPeer;
+use crate::printer::Printer;
 use crate::serial::LinkCable;
 
 use crate::apu::AudioProcessingUnit;
@@ -25,7 +30,11 @@ use crate::logger::Logger;
 use crate::mbc2::MBC2;
 use crate::mbc3::MBC3;
 use crate::mbc5::MBC5;
-use crate::mmu::Mbc::{Five, One, Three, Two, Zero};
+use crate::mbc_camera::PocketCamera;
+use crate::mmu::Mbc::{Camera, Five, One, Three, Two, Zero};
+
+/// Receives every completed frame, 160x144 pixels in 0xAARRGGBB format.
+pub type FrameCallback = Box<dyn FnMut(&[u32])>;
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
 pub enum OamCorruptionCause {
@@ -35,48 +44,163 @@ pub enum OamCorruptionCause {
     ReadWrite,
 }
 
+/// The buses OAM DMA can read from, the CPU reads whatever DMA is moving from the one it's using.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Bus {
+    /// The cartridge, and on the DMG the work RAM too
+    External,
+    Video,
+    /// The CGB's work RAM, on a bus of its own
+    WorkRam,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MemoryManagementUnit {
     #[serde(skip)]
-    pub(crate) renderer: Renderer,
+    pub renderer: Renderer,
     pub boot_rom: Option<Vec<u8>>,
+    /// The boot ROM as loaded, mapped in again on reset after the game has unmapped it.
+    #[serde(skip)]
+    pub(crate) loaded_boot_rom: Option<Vec<u8>>,
     mbc0: Option<MBC0>,
     mbc1: Option<MBC1>,
     mbc2: Option<MBC2>,
     mbc3: Option<MBC3>,
     mbc5: Option<MBC5>,
+    camera: Option<PocketCamera>,
+    /// C000-DFFF: Work RAM, 8 banks of 0x1000 bytes in CGB mode and the 2 the DMG has otherwise,
+    /// which keeps DMG save states small
+    #[serde(with = "serde_bytes")]
     work_ram: Vec<u8>,
+    /// FF70: SVBK, work RAM bank mapped at D000-DFFF (CGB only)
+    wram_bank: u8,
+    /// FF4D: KEY1, bit 7 is set in double speed mode and bit 0 arms a switch on the next STOP
+    /// (CGB only)
+    key1: u8,
+    /// FF00-FF7F: I/O registers no device handles, which keep whatever was written to them
+    unmapped_io: Vec<u8>,
+    /// FF80-FFFE: High RAM
     high_ram: Vec<u8>,
     pub interrupt_handler: InterruptHandler,
     pub ppu: PixelProcessingUnit,
-    serial: LinkCable,
-    timer: Timer,
-    pub(crate) joypad: Joypad,
+    pub(crate) serial: LinkCable,
+    pub(crate) timer: Timer,
+    pub joypad: Joypad,
     pub cycles: u16,
+    /// M-cycles elapsed since power-on, including any warm-up before the CPU starts.
+    #[serde(skip)]
+    pub(crate) total_cycles: u64,
+    /// Base clock T-cycles elapsed since reset, 4 per M-cycle or 2 in double speed mode. Unlike
+    /// `total_cycles` it's saved, so it keeps counting across save states.
+    pub(crate) clock: u64,
     pub dma: u8,
     pub apu: AudioProcessingUnit,
+    /// Set by STOP, the system clock is off until a button is pressed
+    pub(crate) stopped: bool,
+    /// Called with the screen every time the PPU finishes a frame, see `set_frame_callback`
+    #[serde(skip)]
+    pub(crate) on_frame: Option<FrameCallback>,
+    /// What work RAM, VRAM and OAM hold at power on, see `set_ram_init`
+    #[serde(skip)]
+    pub(crate) ram_init: RamInit,
+    #[serde(skip)]
+    pub cheats: Cheats,
+    /// Whether cartridge RAM was written since the battery save was last written out
+    #[serde(skip)]
+    battery_ram_written: bool,
+}
+
+/// Contents of work RAM, VRAM and OAM at power on. Real hardware leaves them in a semi-random
+/// state, which some homebrew checks for and some games accidentally rely on.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RamInit {
+    /// All zeroes
+    #[default]
+    Zero,
+    /// Pseudo-random bytes, the same ones on every run
+    Random,
+    /// Runs of 8 0x00 bytes alternating with runs of 8 0xFF bytes, like CGB RAM tends to hold
+    Cgb,
+}
+
+impl RamInit {
+    /// Fills `ram` with the pattern, memories given different `seed`s get different random bytes.
+    fn fill(&self, ram: &mut [u8], seed: u64) {
+        match self {
+            RamInit::Zero => ram.fill(0),
+            RamInit::Random => {
+                // xorshift64*, fixed here so the contents don't change with a dependency's version
+                let mut state = 0x9E37_79B9_7F4A_7C15_u64 ^ seed.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                for byte in ram {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *byte = (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8;
+                }
+            }
+            RamInit::Cgb => {
+                for (index, byte) in ram.iter_mut().enumerate() {
+                    *byte = if index & 0x08 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+        }
+    }
 }
 
 impl MemoryManagementUnit {
 
+    /// Powers the system back on. The cartridge keeps its RAM and clock, but its banking goes back
+    /// to power-on, and the boot ROM runs again if there is one.
     pub(crate) fn reset(&mut self) {
-        self.interrupt_handler = InterruptHandler::new();
-        self.ppu = PixelProcessingUnit::new();
-        let size = self.renderer.pixels().as_ref().unwrap().frame().len();
-        self.renderer.render(&vec![0; size]);
-        self.serial = LinkCable::new();
-        self.timer = Timer::new(self.boot_rom.is_some());
-        self.joypad = Joypad::new();
+        self.boot_rom = self.loaded_boot_rom.clone();
+        self.interrupt_handler.reset();
+        self.ppu.reset();
+        self.renderer.render(&self.ppu.screen[..], None);
+        self.serial.reset();
+        self.timer.reset(self.boot_rom.is_some());
+        self.joypad.reset();
         self.cycles = 0;
+        self.total_cycles = 0;
+        self.clock = 0;
         self.dma = 0xFF;
-        self.apu = AudioProcessingUnit::new();
-        if let Some(stream) = &self.apu.stream {
-            stream.play().unwrap();
+        self.apu.reset();
+        self.stopped = false;
+        self.init_ram();
+        self.wram_bank = 0;
+        self.key1 = 0;
+        self.unmapped_io.fill(0);
+        self.high_ram.fill(0);
+        if let Some(mbc) = self.mbc_mut() {
+            mbc.set_expansion_mode(0);
+            mbc.set_ram_bank(0);
+            mbc.set_rom_bank(1);
+            mbc.set_ram_enabled(false);
         }
         MemoryManagementUnit::init_memory(self);
     }
 
-    pub(crate) fn save(&mut self) {
+    /// Fills work RAM, VRAM and OAM with `ram_init` now and whenever the system is reset, instead
+    /// of the zeroes they start with. Meant to be called before anything runs.
+    pub fn set_ram_init(&mut self, ram_init: RamInit) {
+        self.ram_init = ram_init;
+        self.init_ram();
+    }
+
+    fn init_ram(&mut self) {
+        self.ram_init.fill(&mut self.work_ram, 0);
+        self.ram_init.fill(&mut self.ppu.vram, 1);
+        self.ram_init.fill(&mut self.ppu.oam, 2);
+    }
+
+    /// Carries the host's choice of clock for the cartridge's RTC over from `running`, a save
+    /// state loaded in its place doesn't store it.
+    pub(crate) fn keep_clock_mode(&mut self, running: &MemoryManagementUnit) {
+        if let (Some(mbc), Some(running)) = (&mut self.mbc3, &running.mbc3) {
+            mbc.keep_clock_mode(running);
+        }
+    }
+
+    pub fn save(&mut self) {
         if let Some(mbc) = &mut self.mbc0 {
             mbc.save()
         } else if let Some(mbc) = &mut self.mbc1 {
@@ -88,7 +212,57 @@ impl MemoryManagementUnit {
         }
     }
 
-    pub(crate) fn start(&mut self) {
+    /// Battery-backed cartridge RAM, in the `.srm` layout other emulators use.
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.mbc().and_then(|mbc| mbc.battery_ram()).map(<[u8]>::to_vec)
+    }
+
+    /// Battery-backed cartridge RAM if the game wrote to it since the last call, which marks it as
+    /// saved. Cheap to poll, it's only copied when it changed.
+    pub fn unsaved_battery_ram(&mut self) -> Option<Vec<u8>> {
+        take(&mut self.battery_ram_written).then(|| self.battery_ram()).flatten()
+    }
+
+    pub fn load_battery_ram(&mut self, ram: &[u8]) {
+        if let Some(mbc) = self.mbc_mut() {
+            mbc.load_battery_ram(ram);
+        }
+    }
+
+    /// Whether the cartridge's rumble motor is running, polled once per frame.
+    pub fn rumble(&self) -> bool {
+        self.mbc().is_some_and(|mbc| mbc.rumble())
+    }
+
+    /// Connects a Game Boy Printer to the serial port.
+    pub fn connect_printer(&mut self) {
+        self.serial.printer = Some(Printer::new());
+    }
+
+    /// Links the serial port to another Game Boy.
+    #[cfg(any(unix, windows))]
+    pub fn connect_link(&mut self, peer: LinkPeer) {
+        self.serial.peer = Some(peer);
+    }
+
+    /// Starts capturing every byte sent over the serial port, printing each one to stdout as it's
+    /// sent if `echo` is set.
+    pub fn capture_serial(&mut self, echo: bool) {
+        self.serial.output.get_or_insert_with(Vec::new);
+        self.serial.echo = echo;
+    }
+
+    /// Bytes sent over the serial port since `capture_serial`, see [`LinkCable::serial_log`].
+    pub fn serial_log(&self) -> String {
+        self.serial.serial_log()
+    }
+
+    /// Pages printed since the last call, as RGBA pixels 160 pixels wide.
+    pub fn take_prints(&mut self) -> Vec<Vec<u8>> {
+        self.serial.printer.as_mut().map(|printer| take(&mut printer.pages)).unwrap_or_default()
+    }
+
+    pub fn start(&mut self) {
         if let Some(mbc) = &mut self.mbc0 {
             mbc.start()
         } else if let Some(mbc) = &mut self.mbc1 {
@@ -109,7 +283,8 @@ enum Mbc {
     One(MBC1),
     Two(MBC2),
     Three(MBC3),
-    Five(MBC5)
+    Five(MBC5),
+    Camera(PocketCamera),
 }
 
 impl MemoryManagementUnit {
@@ -118,50 +293,145 @@ impl MemoryManagementUnit {
         cartridge: Cartridge,
         boot_rom: Option<Vec<u8>>,
         rom_path: &Path,
+        audio: bool,
     ) -> MemoryManagementUnit {
-        let (mbc0, mbc1, mbc2, mbc3, mbc5) = match Self::load_mbc(cartridge, rom, rom_path) {
-            Zero(mbc) => (Some(mbc), None, None, None, None),
-            One(mbc) => (None, Some(mbc), None, None, None),
-            Two(mbc) => (None, None, Some(mbc), None, None),
-            Three(mbc) => (None, None, None, Some(mbc), None),
-            Five(mbc) => (None, None, None, None, Some(mbc)),
+        let cgb = cartridge.cgb && boot_rom.is_none();
+        let (mbc0, mbc1, mbc2, mbc3, mbc5, camera) = match Self::load_mbc(cartridge, rom, rom_path)
+        {
+            Zero(mbc) => (Some(mbc), None, None, None, None, None),
+            One(mbc) => (None, Some(mbc), None, None, None, None),
+            Two(mbc) => (None, None, Some(mbc), None, None, None),
+            Three(mbc) => (None, None, None, Some(mbc), None, None),
+            Five(mbc) => (None, None, None, None, Some(mbc), None),
+            Camera(mbc) => (None, None, None, None, None, Some(mbc)),
         };
 
         let mut mem = MemoryManagementUnit {
             renderer: Renderer::new(),
-            high_ram: vec![0; 0x10000 - 0xFEA0],
+            unmapped_io: vec![0; 0x80],
+            high_ram: vec![0; 0x7F],
             dma: 0xFF,
+            ram_init: RamInit::Zero,
             joypad: Joypad::new(),
             ppu: PixelProcessingUnit::new(),
             interrupt_handler: InterruptHandler::new(),
             timer: Timer::new(boot_rom.is_some()),
-            work_ram: vec![0; 0xE000 - 0xC000],
+            work_ram: vec![0; if cgb { 0x8000 } else { 0x2000 }],
+            wram_bank: 0,
+            key1: 0,
             cycles: 0,
+            total_cycles: 0,
+            clock: 0,
             serial: LinkCable::new(),
+            loaded_boot_rom: boot_rom.clone(),
             boot_rom,
-            apu: AudioProcessingUnit::new(),
+            apu: if audio { AudioProcessingUnit::new() } else { AudioProcessingUnit::silent() },
+            stopped: false,
+            on_frame: None,
+            cheats: Cheats::default(),
+            battery_ram_written: false,
             mbc0,
             mbc1,
             mbc2,
             mbc3,
-            mbc5
+            mbc5,
+            camera,
         };
 
+        if cgb {
+            Logger::info("Running in Game Boy Color mode");
+            mem.ppu.cgb = true;
+        }
         MemoryManagementUnit::init_memory(&mut mem);
         mem
     }
 
+    /// Whether the CPU runs at twice the DMG clock, which the PPU doesn't follow.
+    pub(crate) fn double_speed(&self) -> bool {
+        self.key1 & 0x80 != 0
+    }
+
+    /// Called on STOP. Switches the CPU speed if KEY1 asked for it and returns true, otherwise
+    /// stops the system clock with the LCD blanked until a button is pressed. Either way the
+    /// divider restarts from 0.
+    pub(crate) fn stop(&mut self) -> bool {
+        self.timer.write(0xFF04, 0);
+        if self.ppu.cgb && self.key1 & 0x01 != 0 {
+            self.key1 = !self.key1 & 0x80;
+            return true;
+        }
+
+        self.stopped = true;
+        self.renderer.render(&vec![0xFF; self.ppu.screen.len()], None);
+        false
+    }
+
+    /// Shows the frame as drawn so far, with the renderer's beam overlay on the current line.
+    /// For looking at the screen while paused mid-frame.
+    pub fn redraw(&mut self) {
+        self.renderer.render(self.ppu.drawing(), Some(self.ppu.scanline()));
+    }
+
+    /// Calls `callback` with the screen, 160x144 pixels in 0xAARRGGBB format, once per frame the
+    /// PPU completes. Lets embedders push frames to their own pipeline instead of polling.
+    pub fn set_frame_callback(&mut self, callback: FrameCallback) {
+        self.on_frame = Some(callback);
+    }
+
+    /// Hands a completed frame to the renderer and the frame callback, and applies GameShark codes
+    /// like the real device does on VBlank.
+    fn finish_frame(&mut self) {
+        let writes = take(&mut self.cheats.writes);
+        for write in &writes {
+            self.internal_write(write.address as usize, write.value);
+        }
+        self.cheats.writes = writes;
+
+        self.renderer.render(&self.ppu.screen[..], None);
+        if let Some(on_frame) = &mut self.on_frame {
+            let frame: Vec<u32> = self
+                .ppu
+                .screen
+                .chunks_exact(4)
+                .map(|rgba| u32::from_be_bytes([rgba[3], rgba[0], rgba[1], rgba[2]]))
+                .collect();
+            on_frame(&frame);
+        }
+    }
+
+    /// PPU dots elapsed in every CPU M-cycle.
+    pub(crate) fn dots_per_cycle(&self) -> u16 {
+        if self.double_speed() {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Index in `work_ram` of an address in C000-FDFF, accounting for the echo and the bank
+    /// mapped at D000-DFFF.
+    fn work_ram_index(&self, address: usize) -> usize {
+        let offset = (address - 0xC000) & 0x1FFF;
+        if offset < 0x1000 {
+            offset
+        } else {
+            (self.wram_bank as usize & 0x07).max(1) * 0x1000 + offset - 0x1000
+        }
+    }
+
     fn load_mbc(
         cartridge: Cartridge,
         rom: Vec<u8>,
         rom_path: &Path,
     ) -> Mbc {
+        Logger::info(format!("Using MBC ID {:#04X}", cartridge.mbc));
         match cartridge.mbc {
             0x00 => Zero(MBC0::new(rom, vec![0; 32 * 1024])),
             0x01..=0x03 => One(MBC1::new(cartridge, rom)),
             0x05 | 0x06 => Two(MBC2::new(cartridge, rom)),
             0x0F..=0x13 => Three(MBC3::new(cartridge, rom)),
             0x19..=0x1E => Five(MBC5::new(cartridge, rom)),
+            0xFC => Camera(PocketCamera::new(cartridge, rom)),
             _ => {
                 Logger::error(format!(
                     "MBC ID {} not implemented, defaulting to MBC0 - {}",
@@ -210,12 +480,36 @@ impl MemoryManagementUnit {
             _ => None,
         };
 
-        let value = self.internal_read(translated_address);
+        let value = self.dma_conflict(translated_address).unwrap_or_else(|| self.internal_read(translated_address));
 
         self.cycle(4);
         value
     }
 
+    /// The bus an address is on, None for OAM, the IO registers and HRAM, which the CPU keeps
+    /// access to during OAM DMA.
+    fn bus(&self, address: usize) -> Option<Bus> {
+        match address {
+            0x8000..=0x9FFF => Some(Bus::Video),
+            0xC000..=0xFDFF if self.ppu.cgb => Some(Bus::WorkRam),
+            0x0000..=0xFDFF => Some(Bus::External),
+            _ => None,
+        }
+    }
+
+    /// The byte OAM DMA is moving, if it's using the bus the CPU is reading from.
+    fn dma_conflict(&self, address: usize) -> Option<u8> {
+        if !self.ppu.dma_running || !self.ppu.dma_block_oam {
+            return None;
+        }
+        let source = self.dma_source();
+        if self.bus(address).is_none() || self.bus(address) != self.bus(source) {
+            return None;
+        }
+        let elapsed = self.ppu.ticks.wrapping_sub(self.ppu.dma_started).saturating_sub(8);
+        Some(self.dma_read(source + (elapsed / 4).min(Self::WIDTH - 1)))
+    }
+
     pub fn write<Address: 'static + Into<usize> + Copy, Value: Into<u8> + Copy>(
         &mut self,
         address: Address,
@@ -250,13 +544,55 @@ impl MemoryManagementUnit {
 
     fn internal_ram_read(&self, address: usize) -> u8 {
         match address as u16 {
-            0xC000..=0xDFFF => self.work_ram[address - 0xC000],
-            0xE000..=0xFDFF => self.work_ram[address - 0x2000 - 0xC000],
-            0xFEA0..=0xFFFF => self.high_ram[address - 0xFEA0],
+            0xC000..=0xFDFF => self.work_ram[self.work_ram_index(address)],
+            0xFF4D if self.ppu.cgb => self.key1 | 0x7E,
+            0xFF70 if self.ppu.cgb => self.wram_bank | 0xF8,
+            // Prohibited area, reads as 0 on the DMG unless the PPU is blocking OAM
+            0xFEA0..=0xFEFF if self.ppu.dma_block_oam || self.ppu.oam_read_block => 0xFF,
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.unmapped_io[address - 0xFF00],
+            0xFF80..=0xFFFE => self.high_ram[address - 0xFF80],
             _ => panic!("Unhandled address for read: {}", address),
         }
     }
 
+    /// The cartridge's bank controller, exposed so debuggers can inspect or force its banks.
+    pub fn mbc(&self) -> Option<&dyn MemoryBankController> {
+        if let Some(mbc) = &self.mbc0 {
+            Some(mbc)
+        } else if let Some(mbc) = &self.mbc1 {
+            Some(mbc)
+        } else if let Some(mbc) = &self.mbc2 {
+            Some(mbc)
+        } else if let Some(mbc) = &self.mbc3 {
+            Some(mbc)
+        } else if let Some(mbc) = &self.mbc5 {
+            Some(mbc)
+        } else if let Some(mbc) = &self.camera {
+            Some(mbc)
+        } else {
+            None
+        }
+    }
+
+    pub fn mbc_mut(&mut self) -> Option<&mut dyn MemoryBankController> {
+        if let Some(mbc) = &mut self.mbc0 {
+            Some(mbc)
+        } else if let Some(mbc) = &mut self.mbc1 {
+            Some(mbc)
+        } else if let Some(mbc) = &mut self.mbc2 {
+            Some(mbc)
+        } else if let Some(mbc) = &mut self.mbc3 {
+            Some(mbc)
+        } else if let Some(mbc) = &mut self.mbc5 {
+            Some(mbc)
+        } else if let Some(mbc) = &mut self.camera {
+            Some(mbc)
+        } else {
+            None
+        }
+    }
+
     fn mbc_read(&self, translated_address: usize) -> Option<u8> {
         if let Some(mbc) = &self.mbc0 {
             mbc.read(translated_address)
@@ -268,6 +604,8 @@ impl MemoryManagementUnit {
             mbc.read(translated_address)
         } else if let Some(mbc) = &self.mbc5 {
             mbc.read(translated_address)
+        } else if let Some(mbc) = &self.camera {
+            mbc.read(translated_address)
         } else {
             None
         }
@@ -281,9 +619,12 @@ impl MemoryManagementUnit {
         } else if let Some(mbc) = &mut self.mbc2 {
             mbc.write(translated_address, value)
         } else if let Some(mbc) = &mut self.mbc3 {
+            mbc.sync_clock(self.total_cycles);
             mbc.write(translated_address, value)
         } else if let Some(mbc) = &mut self.mbc5 {
             mbc.write(translated_address, value)
+        } else if let Some(mbc) = &mut self.camera {
+            mbc.write(translated_address, value)
         } else {
             false
         }
@@ -291,25 +632,38 @@ impl MemoryManagementUnit {
 
     fn internal_ram_write(&mut self, address: usize, value: u8) {
         match address as u16 {
-            0xC000..=0xDFFF => self.work_ram[address - 0xC000] = value,
-            0xE000..=0xFDFF => self.work_ram[address - 0x2000 - 0xC000] = value,
-            0xFEA0..=0xFFFF => self.high_ram[address - 0xFEA0] = value,
+            0xC000..=0xFDFF => {
+                let index = self.work_ram_index(address);
+                self.work_ram[index] = value
+            }
+            0xFF4D if self.ppu.cgb => self.key1 = (self.key1 & 0x80) | (value & 0x01),
+            0xFF70 if self.ppu.cgb => self.wram_bank = value & 0x07,
+            0xFEA0..=0xFEFF => (),
+            0xFF00..=0xFF7F => self.unmapped_io[address - 0xFF00] = value,
+            0xFF80..=0xFFFE => self.high_ram[address - 0xFF80] = value,
             _ => panic!("Unhandled address for write: {}", address),
         }
     }
 
     pub fn internal_read(&self, translated_address: usize) -> u8 {
-        self.mbc_read(translated_address)
+        let value = self
+            .mbc_read(translated_address)
             .or_else(|| self.ppu.read(translated_address))
             .or_else(|| self.interrupt_handler.read(translated_address))
             .or_else(|| self.timer.read(translated_address))
+            .or_else(|| self.ppu.sgb.as_ref().and_then(|sgb| sgb.read(translated_address)))
             .or_else(|| self.joypad.read(translated_address))
             .or_else(|| self.serial.read(translated_address))
             .or_else(|| self.apu.read(translated_address))
-            .unwrap_or_else(|| self.internal_ram_read(translated_address))
+            .unwrap_or_else(|| self.internal_ram_read(translated_address));
+        self.cheats.patch(translated_address, value)
     }
 
-    fn internal_write(&mut self, translated_address: usize, value: u8) {
+    pub(crate) fn internal_write(&mut self, translated_address: usize, value: u8) {
+        // A Super Game Boy listens in on the joypad lines for command packets
+        if translated_address == 0xFF00 {
+            self.ppu.sgb_write(value);
+        }
         if !(self.mbc_write(translated_address, value)
             || self.ppu.write(translated_address, value)
             || self.interrupt_handler.write(translated_address, value)
@@ -320,10 +674,15 @@ impl MemoryManagementUnit {
         {
             self.internal_ram_write(translated_address, value);
         }
+        if (0xA000..=0xBFFF).contains(&translated_address) && self.mbc_mut().is_some_and(|mbc| mbc.take_ram_written()) {
+            self.battery_ram_written = true;
+        }
     }
 
     pub fn cycle(&mut self, ticks: usize) {
         self.cycles += 1;
+        self.total_cycles += 1;
+        self.clock += self.dots_per_cycle() as u64;
         self.dma_transfer();
         self.machine_cycle(ticks);
     }
@@ -334,6 +693,12 @@ impl MemoryManagementUnit {
         if !self.ppu.dma_running {
             return;
         }
+        let ticks = self.ppu.ticks;
+        if self.ppu.dma_replaced.is_some_and(|started| ticks.wrapping_sub(started) >= 8) {
+            self.ppu.dma_block_oam = true;
+            self.ppu.dma_replaced = None;
+        }
+
         let elapsed = self.ppu.ticks.wrapping_sub(self.ppu.dma_started);
         if elapsed < 8 {
             return;
@@ -351,41 +716,62 @@ impl MemoryManagementUnit {
         self.ppu.dma_running = false;
 
         // Copy memory
-        let start = if self.ppu.dma >= 0xFE {
-            self.ppu.dma - 0x20
-        } else {
-            self.ppu.dma
-        } as usize
-            * 0x100;
-
+        let start = self.dma_source();
         for (index, address) in (start..start + Self::WIDTH).enumerate() {
-            self.ppu.oam[index] = match address {
-                0x8000..=0x9FFF => self.ppu.vram[address - 0x8000],
-                _ => self.internal_read(address),
-            };
+            self.ppu.oam[index] = self.dma_read(address);
+        }
+    }
+
+    /// Where OAM DMA copies from. Sources past DFFF read the echo of work RAM, FE and FF included,
+    /// since DMA doesn't see OAM and the IO registers.
+    fn dma_source(&self) -> usize {
+        let page = match self.ppu.dma {
+            0xE0..=0xFF => self.ppu.dma - 0x20,
+            dma => dma,
+        };
+        page as usize * 0x100
+    }
+
+    /// Reads a DMA source byte, VRAM included even while the PPU keeps the CPU out of it.
+    fn dma_read(&self, address: usize) -> u8 {
+        match address {
+            0x8000..=0x9FFF => self.ppu.vram[self.ppu.vram_bank_offset() + address - 0x8000],
+            _ => self.internal_read(address),
         }
     }
 
     fn machine_cycle(&mut self, ticks: usize) {
-        match self.ppu.machine_cycle(ticks) {
+        if self.stopped {
+            // Only the joypad keeps going, and any press starts the clock again
+            if self.joypad.machine_cycle() {
+                self.stopped = false;
+                self.interrupt_handler.set(Input)
+            }
+            return;
+        }
+
+        let dots = ticks * self.dots_per_cycle() as usize / 4;
+        match self.ppu.machine_cycle(dots) {
             (true, true) => {
-                self.renderer.render(&self.ppu.screen);
+                self.finish_frame();
                 self.interrupt_handler.set(VBlank);
                 self.interrupt_handler.set(Stat);
             }
             (true, false) => {
-                self.renderer.render(&self.ppu.screen);
+                self.finish_frame();
                 self.interrupt_handler.set(VBlank)
             }
             (false, true) => self.interrupt_handler.set(Stat),
             (false, false) => (),
         };
 
+        self.apu.machine_cycle(dots as u16);
+
         if self.timer.machine_cycle(ticks as u16) {
             self.interrupt_handler.set(Timing)
         };
 
-        if self.serial.machine_cycle() {
+        if self.serial.machine_cycle(self.ppu.cgb) {
             self.interrupt_handler.set(Serial)
         };
 
@@ -408,6 +794,8 @@ impl MemoryManagementUnit {
             0xFF05: 0x0,
             0xFF06: 0x0,
             0xFF07: 0x0,
+            // The APU ignores writes to its other registers until powered on
+            0xFF26: 0xF1,
             0xFF10: 0x80,
             0xFF11: 0xBF,
             0xFF12: 0xF3,
@@ -419,14 +807,13 @@ impl MemoryManagementUnit {
             0xFF1A: 0x7F,
             0xFF1B: 0xFF,
             0xFF1C: 0x9F,
-            0xFF1E: 0xFF,
+            0xFF1E: 0xBF,
             0xFF20: 0xFF,
             0xFF21: 0x0,
             0xFF22: 0x0,
             0xFF23: 0xBF,
             0xFF24: 0x77,
             0xFF25: 0xF3,
-            0xFF26: 0xF1,
             0xFF40: 0x91,
             0xFF42: 0x0,
             0xFF43: 0x0,
@@ -436,7 +823,7 @@ impl MemoryManagementUnit {
             0xFF49: 0xFF,
             0xFF4A: 0x0,
             0xFF4B: 0x0,
-            0xFF00: 0xFF,
+            0xFF00: 0xCF,
         }
     }
 }
\ No newline at end of file
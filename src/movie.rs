@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+
+use crate::joypad::Buttons;
+
+/// Buttons to hold on each frame, replayed in place of live input. Recordings have a line per
+/// frame listing the held buttons by name, e.g. `a right`, with blank lines holding nothing.
+pub struct Playback {
+    frames: VecDeque<Buttons>,
+}
+
+impl Playback {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let frames = text
+            .lines()
+            .enumerate()
+            .map(|(line, names)| {
+                names.split_whitespace().try_fold(Buttons::NONE, |held, name| match Buttons::named(name) {
+                    Some(button) => Ok(held | button),
+                    None => Err(format!("Unknown button {name} on line {}", line + 1)),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { frames })
+    }
+
+    /// The buttons held on the next frame, none once the recording runs out.
+    pub fn next_frame(&mut self) -> Option<Buttons> {
+        self.frames.pop_front()
+    }
+}
+
+/// Writes the buttons held on every frame in the format [`Playback`] reads.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        Ok(Self { file: File::create(path)? })
+    }
+
+    pub fn record(&mut self, held: Buttons) -> std::io::Result<()> {
+        let names: Vec<_> = Buttons::NAMES.iter().filter(|(_, button)| held.contains(*button)).map(|(name, _)| *name).collect();
+        writeln!(self.file, "{}", names.join(" "))
+    }
+}
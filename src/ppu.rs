@@ -1,10 +1,13 @@
 use crate::{
+    logger::Logger,
     mmu::{MemoryArea, OamCorruptionCause},
+    sgb::SuperGameBoy,
     HEIGHT, WIDTH,
 };
 use OamCorruptionCause::{IncDec, Read, ReadWrite, Write};
 
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 use HorizontalBlankPhase::*;
 use OamSearchPhase::*;
@@ -12,17 +15,20 @@ use PixelTransferPhase::*;
 use PpuState::*;
 use VerticalBlankPhase::*;
 
-fn init_screen() -> [u8; 0x5A00 * 4] {
-    [0; 0x5A00 * 4]
+/// Boxed so the PPU, and everything holding it, stays cheap to move and deserialize.
+fn init_screen() -> Box<[u8; 0x5A00 * 4]> {
+    Box::new([0; 0x5A00 * 4])
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct PixelProcessingUnit {
     oam_start_clock_count: usize,
     pub(crate) oam_corruption: Option<OamCorruptionCause>,
-    /// 8000-9FFF: Video RAM
+    /// 8000-9FFF: Video RAM, two banks of 0x2000 bytes. The second one is only used in CGB mode.
+    #[serde(with = "serde_bytes")]
     pub vram: Vec<u8>,
     /// FE00-FE9F: Sprite Attribute table
+    #[serde(with = "serde_bytes")]
     pub oam: Vec<u8>,
     pub dma: u8,
     /// The cycle in which the last DMA transfer was requested.
@@ -31,16 +37,23 @@ pub struct PixelProcessingUnit {
     pub(crate) dma_running: bool,
     /// Oam read is blocked
     pub(crate) dma_block_oam: bool,
+    /// The cycle in which a DMA transfer replaced by a restart was requested, if it hadn't
+    /// started blocking OAM yet.
+    #[serde(skip)]
+    pub(crate) dma_replaced: Option<usize>,
 
     pub(crate) oam_read_block: bool,
     pub(crate) oam_write_block: bool,
     vram_read_block: bool,
     vram_write_block: bool,
 
-    /// The current screen been render.
-    /// Each pixel is a shade of gray, from 0 to 3
+    /// The last finished frame as RGBA pixels, only updated at the start of VBlank so it never
+    /// shows a frame half drawn.
     #[serde(skip, default = "init_screen")]
-    pub screen: [u8; 0x5A00 * 4],
+    pub screen: Box<[u8; 0x5A00 * 4]>,
+    /// The frame being drawn during mode 3, copied to `screen` once it's done.
+    #[serde(skip, default = "init_screen")]
+    back_buffer: Box<[u8; 0x5A00 * 4]>,
     /// sprites that will be rendered in the next mode 3 scanline
     pub sprite_buffer: Vec<Sprite>,
     /// the length of the `sprite_buffer`
@@ -77,6 +90,19 @@ pub struct PixelProcessingUnit {
     /// FF4B: Window X Position
     pub wx: u8,
 
+    /// Game Boy Color mode, for cartridges that support it and run without a boot ROM.
+    pub cgb: bool,
+    /// FF4F: VRAM bank mapped at 8000-9FFF (CGB only)
+    vram_bank: u8,
+    /// FF68: Background palette index. Bit 7 increments it after every write to FF69.
+    bcps: u8,
+    /// FF69: Background palette RAM, 8 palettes of 4 little-endian RGB555 colors
+    bg_palettes: Vec<u8>,
+    /// FF6A: Sprite palette index
+    ocps: u8,
+    /// FF6B: Sprite palette RAM
+    obj_palettes: Vec<u8>,
+
     pub state: PpuState,
     /// When making the LY==LYC comparison, uses this value instead of ly to control the comparison
     /// timing. This is 0xFF if this will not update the stat.
@@ -87,6 +113,10 @@ pub struct PixelProcessingUnit {
     /// use this value instead of the current stat mode when controlling the stat interrupt signal,
     /// to control the timing. 0xff means that this will not trigger a interrupt.
     stat_mode_for_interrupt: u8,
+    /// The dot of the last machine cycle an interrupt was first raised on, a halted CPU only
+    /// notices interrupts raised in the first half of a machine cycle before the next one.
+    #[serde(skip)]
+    pub(crate) interrupt_dot: Option<usize>,
     /// Current clock cycle
     pub(crate) ticks: usize,
     /// Next clock cycle where the PPU will be updated
@@ -102,6 +132,8 @@ pub struct PixelProcessingUnit {
     /// the tile x position that the pixel fetcher is in
     fetcher_x: u8,
     fetch_tile_number: u8,
+    /// Tile attributes from VRAM bank 1, always 0 outside CGB mode
+    fetch_tile_attributes: u8,
     fetch_tile_data_low: u8,
     fetch_tile_data_high: u8,
 
@@ -114,6 +146,8 @@ pub struct PixelProcessingUnit {
 
     /// Sprites at 0 cause a extra delay in the sprite fetching.
     sprite_at_0_penalty: u8,
+    /// Sprites fetched in the current scanline
+    sprites_fetched: u8,
 
     /// The x position of the next screen pixel to be draw in the current scanline
     pub screen_x: u8,
@@ -121,6 +155,30 @@ pub struct PixelProcessingUnit {
     /// (represented by positives between 241 and 255) are use for detecting sprites that starts
     /// to the left of the screen, and for discarding pixels for scrolling.
     scanline_x: u8,
+
+    #[serde(skip)]
+    pub debug: PpuDebug,
+    #[serde(skip)]
+    pub palette: Palette,
+    /// Draw every sprite on a line instead of the first 10 like the hardware, which removes the
+    /// flicker games use to get around the limit. Not saved, like `palette`.
+    #[serde(skip)]
+    pub unlimited_sprites: bool,
+    /// Palettes a Super Game Boy enhanced game picked, when running it as one
+    pub(crate) sgb: Option<SuperGameBoy>,
+}
+
+/// Switches for isolating the effect of sprite penalties on mode 3 timing. These are research
+/// tools and not part of the emulated state, so they aren't saved and survive a reset.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub struct PpuDebug {
+    /// Skip the extra cycles taken by a sprite at x=0 while the background is scrolled.
+    pub no_sprite_at_0_penalty: bool,
+    /// Skip the fixed cycles spent fetching the tile data of every sprite.
+    pub no_sprite_fetch_penalty: bool,
+    /// Log the length of mode 3 for every visible scanline.
+    pub log_mode3: bool,
+    mode3_start: usize,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Default, Clone, Copy, Debug)]
@@ -131,12 +189,91 @@ pub struct Sprite {
     pub flags: u8,
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
-    a: u8,
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 255 }
+    }
+
+    /// Expands a color with 5 bits per channel, red in the lowest bits, as CGB and SGB palettes
+    /// store them.
+    pub(crate) fn from_rgb555(rgb555: u16) -> Self {
+        let [r, g, b] = [0, 5, 10].map(|shift| {
+            let channel = (rgb555 >> shift) as u8 & 0x1F;
+            (channel << 3) | (channel >> 2)
+        });
+        Self::rgb(r, g, b)
+    }
+}
+
+/// The four shades DMG games are drawn with, from lightest to darkest. Like [`PpuDebug`] it's a
+/// display preference, so it isn't saved and survives resets and state loads. CGB games pick
+/// their own colors and ignore it.
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub enum Palette {
+    /// The pea green of the original screen
+    #[default]
+    GreenDmg,
+    /// Neutral grays
+    Grayscale,
+    Custom([Color; 4]),
+}
+
+impl Palette {
+    /// The palettes that can be picked by name.
+    pub const PRESETS: [Palette; 2] = [Palette::GreenDmg, Palette::Grayscale];
+
+    pub fn colors(&self) -> [Color; 4] {
+        match self {
+            Palette::GreenDmg => [
+                Color::rgb(224, 248, 208),
+                Color::rgb(136, 192, 112),
+                Color::rgb(39, 80, 70),
+                Color::rgb(8, 24, 32),
+            ],
+            Palette::Grayscale => [
+                Color::rgb(255, 255, 255),
+                Color::rgb(170, 170, 170),
+                Color::rgb(85, 85, 85),
+                Color::rgb(0, 0, 0),
+            ],
+            Palette::Custom(colors) => *colors,
+        }
+    }
+}
+
+/// Parses `green`, `grayscale`, or four comma separated `#RRGGBB` colors from lightest to darkest.
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "green" => return Ok(Palette::GreenDmg),
+            "grayscale" => return Ok(Palette::Grayscale),
+            _ => {}
+        }
+
+        let colors = s
+            .split(',')
+            .map(|color| {
+                let hex = color.trim().strip_prefix('#').filter(|hex| hex.len() == 6);
+                let rgb = hex.and_then(|hex| u32::from_str_radix(hex, 16).ok());
+                let [_, r, g, b] = rgb.ok_or(format!("Invalid color {color}, expected #RRGGBB"))?.to_be_bytes();
+                Ok(Color::rgb(r, g, b))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let colors = colors
+            .try_into()
+            .map_err(|colors: Vec<_>| format!("Expected 4 colors, got {}", colors.len()))?;
+        Ok(Palette::Custom(colors))
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug, Ord, PartialOrd, Eq)]
@@ -228,11 +365,12 @@ impl PixelFifo {
         self.tail = 0;
     }
 
-    fn push_background(&mut self, tile_low: u8, tile_high: u8) {
+    /// Background pixels keep the CGB palette in bits 2-4 and the BG-to-OAM priority in bit 7.
+    fn push_background(&mut self, tile_low: u8, tile_high: u8, attributes: u8) {
         for i in (0..8).rev() {
             let color = (((tile_high >> i) & 0x01) << 1) | ((tile_low >> i) & 0x01);
             debug_assert!(color < 4);
-            let pixel = color;
+            let pixel = color | ((attributes & 0x07) << 2) | (attributes & 0x80);
             self.queue[self.head as usize] = pixel;
             self.head = (self.head + 1) % self.queue.len() as u8;
             debug_assert_ne!(self.head, self.tail);
@@ -245,12 +383,13 @@ impl PixelFifo {
         tile_high: u8,
         palette: bool,
         background_priority: bool,
+        cgb_palette: u8,
     ) {
         let pixel = |x| {
             let color: u8 = (((tile_high >> x) & 0x01) << 1) | ((tile_low >> x) & 0x01);
             debug_assert!(color < 4);
 
-            color | ((background_priority as u8) << 3) | ((palette as u8) << 4)
+            color | ((background_priority as u8) << 3) | ((palette as u8) << 4) | (cgb_palette << 5)
         };
 
         let mut cursor = self.tail;
@@ -282,12 +421,18 @@ impl PixelFifo {
     }
 }
 
+impl Default for PixelProcessingUnit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MemoryArea for PixelProcessingUnit {
     fn read(&self, address: usize) -> Option<u8> {
         let value = match address {
             0x8000..=0x9FFF if self.vram_read_block => 0xFF,
             0xFE00..=0xFE9F if self.dma_block_oam || self.oam_read_block => 0xFF,
-            0x8000..=0x9FFF => self.vram[address - 0x8000],
+            0x8000..=0x9FFF => self.vram[self.vram_bank_offset() + address - 0x8000],
             0xFE00..=0xFE9F => self.oam[address - 0xFE00],
             0xFF40 => self.lcdc,
             0xFF41 => self.stat | 0x80,
@@ -301,6 +446,11 @@ impl MemoryArea for PixelProcessingUnit {
             0xFF49 => self.obp1,
             0xFF4A => self.wy,
             0xFF4B => self.wx,
+            0xFF4F if self.cgb => 0xFE | self.vram_bank,
+            0xFF68 if self.cgb => self.bcps | 0x40,
+            0xFF69 if self.cgb => self.bg_palettes[self.bcps as usize & 0x3F],
+            0xFF6A if self.cgb => self.ocps | 0x40,
+            0xFF6B if self.cgb => self.obj_palettes[self.ocps as usize & 0x3F],
             _ => return None,
         };
         Some(value)
@@ -310,7 +460,10 @@ impl MemoryArea for PixelProcessingUnit {
         match address {
             0x8000..=0x9FFF if self.vram_write_block => (),
             0xFE00..=0xFE9F if self.oam_write_block => (),
-            0x8000..=0x9FFF => self.vram[address - 0x8000] = value,
+            0x8000..=0x9FFF => {
+                let index = self.vram_bank_offset() + address - 0x8000;
+                self.vram[index] = value
+            }
             0xFE00..=0xFE9F => self.oam[address - 0xFE00] = value,
             0xFF46 => self.start_dma(value),
             0xFF40 => {
@@ -342,6 +495,11 @@ impl MemoryArea for PixelProcessingUnit {
             0xFF49 => self.obp1 = value,
             0xFF4A => self.wy = value,
             0xFF4B => self.wx = value,
+            0xFF4F if self.cgb => self.vram_bank = value & 0x01,
+            0xFF68 if self.cgb => self.bcps = value & 0xBF,
+            0xFF69 if self.cgb => Self::write_palette(&mut self.bg_palettes, &mut self.bcps, value),
+            0xFF6A if self.cgb => self.ocps = value & 0xBF,
+            0xFF6B if self.cgb => Self::write_palette(&mut self.obj_palettes, &mut self.ocps, value),
             _ => return false,
         }
         true
@@ -353,17 +511,19 @@ impl PixelProcessingUnit {
         Self {
             oam_start_clock_count: 0,
             oam_corruption: None,
-            vram: vec![0; 0x2000],
+            vram: vec![0; 0x4000],
             oam: vec![0; 0xA0],
             dma: 0xFF,
             dma_started: 0,
             dma_running: false,
             dma_block_oam: false,
+            dma_replaced: None,
             oam_read_block: false,
             oam_write_block: false,
             vram_read_block: false,
             vram_write_block: false,
-            screen: [0; 0x5A00 * 4],
+            screen: init_screen(),
+            back_buffer: init_screen(),
             sprite_buffer: vec![Sprite::default(); 10],
             sprite_buffer_len: 0,
             wyc: 0,
@@ -378,6 +538,12 @@ impl PixelProcessingUnit {
             obp1: 0,
             wy: 0,
             wx: 0,
+            cgb: false,
+            vram_bank: 0,
+            bcps: 0,
+            bg_palettes: vec![0xFF; 0x40],
+            ocps: 0,
+            obj_palettes: vec![0xFF; 0x40],
             state: VerticalBlank(EndVBlank),
             ly_for_compare: 0,
 
@@ -391,6 +557,7 @@ impl PixelProcessingUnit {
             fetcher_step: 0x03,
             fetcher_x: 0x14,
             fetch_tile_number: 0,
+            fetch_tile_attributes: 0,
             fetch_tile_data_low: 0,
             fetch_tile_data_high: 0,
 
@@ -403,16 +570,187 @@ impl PixelProcessingUnit {
             stat_signal: false,
             ly_compare_signal: false,
             stat_mode_for_interrupt: 1,
+            interrupt_dot: None,
 
             sprite_at_0_penalty: 0,
+            sprites_fetched: 0,
 
             screen_x: 0xa0,
             scanline_x: 0x00,
+
+            debug: PpuDebug::default(),
+            palette: Palette::default(),
+            unlimited_sprites: false,
+            sgb: None,
+        }
+    }
+
+    /// Returns the PPU to the state it has right after the boot ROM hands over control.
+    pub fn reset(&mut self) {
+        let (debug, palette, cgb) = (self.debug, self.palette, self.cgb);
+        let (unlimited_sprites, sgb) = (self.unlimited_sprites, self.sgb.is_some());
+        *self = Self::new();
+        self.debug = debug;
+        self.palette = palette;
+        self.unlimited_sprites = unlimited_sprites;
+        self.sgb = sgb.then(SuperGameBoy::new);
+        self.cgb = cgb;
+    }
+
+    /// Runs the game as if on a Super Game Boy, coloring it with the palettes it sends. CGB games
+    /// use their own colors instead, so this returns false for them.
+    pub fn enable_sgb(&mut self) -> bool {
+        if !self.cgb {
+            self.sgb = Some(SuperGameBoy::new());
+        }
+        self.sgb.is_some()
+    }
+
+    /// Passes a joypad register write on to the Super Game Boy, if there is one.
+    pub(crate) fn sgb_write(&mut self, value: u8) {
+        if let Some(sgb) = &mut self.sgb {
+            sgb.write(value, &self.vram, self.lcdc);
+        }
+    }
+
+    /// Writes to the palette RAM entry selected by `index`, advancing it if bit 7 is set.
+    fn write_palette(palettes: &mut [u8], index: &mut u8, value: u8) {
+        palettes[*index as usize & 0x3F] = value;
+        if *index & 0x80 != 0 {
+            *index = 0x80 | (index.wrapping_add(1) & 0x3F);
+        }
+    }
+
+    /// Offset of the VRAM bank the CPU currently sees, for OAM DMA.
+    pub(crate) fn vram_bank_offset(&self) -> usize {
+        self.vram_bank as usize * 0x2000
+    }
+
+    /// The mode STAT reports: 0 for HBlank, 1 for VBlank, 2 for the OAM scan and 3 for drawing.
+    pub fn current_mode(&self) -> u8 {
+        self.stat & 0b11
+    }
+
+    /// The line the PPU is on, as LY reads.
+    pub fn scanline(&self) -> u8 {
+        self.ly
+    }
+
+    /// Dots since the current line started, up to 455.
+    pub fn dot(&self) -> usize {
+        self.ticks.saturating_sub(self.line_start_ticks)
+    }
+
+    /// The frame being drawn, lines past the current one still show the previous frame.
+    pub fn drawing(&self) -> &[u8] {
+        &self.back_buffer[..]
+    }
+
+    /// The 384 tiles of the first VRAM bank as a 128x192 RGBA image, 16 tiles per row, shaded
+    /// through `palette` the way BGP shades the background. Meant for debugging, the emulated
+    /// state isn't touched.
+    pub fn tiles_rgba(&self, palette: u8) -> Vec<u8> {
+        const TILES_PER_ROW: usize = 16;
+        const WIDTH: usize = TILES_PER_ROW * 8;
+
+        let mut image = vec![0; WIDTH * 192 * 4];
+        for tile in 0..384 {
+            let position = (tile % TILES_PER_ROW * 8, tile / TILES_PER_ROW * 8);
+            self.draw_tile(&mut image, WIDTH, tile, position, palette);
+        }
+        image
+    }
+
+    /// The 32x32 tile background map at 9C00 if `map` is set, or 9800 otherwise, as a 256x256 RGBA
+    /// image shaded through BGP. Tiles are looked up with the addressing mode LCDC bit 4 selects.
+    pub fn tilemap_rgba(&self, map: bool) -> Vec<u8> {
+        const WIDTH: usize = 256;
+
+        let map = if map { 0x1C00 } else { 0x1800 };
+        let mut image = vec![0; WIDTH * WIDTH * 4];
+        for (index, tile) in self.vram[map..map + 0x400].iter().enumerate() {
+            let tile = if self.lcdc & 0x10 != 0 {
+                *tile as usize
+            } else {
+                // 8800 addressing, tile numbers are signed and centered on 9000
+                (0x100 + *tile as i8 as i16) as usize
+            };
+            self.draw_tile(&mut image, WIDTH, tile, (index % 32 * 8, index / 32 * 8), self.bgp);
+        }
+        image
+    }
+
+    /// The 40 OAM entries, with positions as stored, 16 below and 8 right of the screen's.
+    pub fn sprites(&self) -> Vec<Sprite> {
+        self.oam[..160]
+            .chunks_exact(4)
+            .map(|data| Sprite { sy: data[0], sx: data[1], tile: data[2], flags: data[3] })
+            .collect()
+    }
+
+    /// OAM entry `index` as an RGBA image 8 pixels wide, and 16 tall instead of 8 while LCDC bit 2
+    /// is set, flipped and colored like the PPU would draw it. Color 0 is left transparent.
+    pub fn sprite_rgba(&self, index: usize) -> Vec<u8> {
+        let sprite = self.sprites()[index];
+        let tall = self.lcdc & 0x04 != 0;
+        let height = if tall { 16 } else { 8 };
+        let tile = if tall { sprite.tile & !1 } else { sprite.tile } as usize;
+        let bank = if self.cgb && sprite.flags & 0x08 != 0 { 0x2000 } else { 0 };
+
+        let mut image = vec![0; 8 * height * 4];
+        for row in 0..height {
+            let py = if sprite.flags & 0x40 != 0 { height - 1 - row } else { row };
+            let [low, high] = [0, 1].map(|byte| self.vram[bank + tile * 0x10 + py * 2 + byte]);
+            for column in 0..8 {
+                let bit = if sprite.flags & 0x20 != 0 { column } else { 7 - column };
+                let color = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                if color == 0 {
+                    continue;
+                }
+                let Color { r, g, b, a } = if self.cgb {
+                    let index = (sprite.flags & 0x07) as usize * 8 + color as usize * 2;
+                    Color::from_rgb555(u16::from_le_bytes([self.obj_palettes[index], self.obj_palettes[index + 1]]))
+                } else {
+                    let palette = [self.obp0, self.obp1][(sprite.flags >> 4) as usize & 1];
+                    self.palette.colors()[(palette >> (color * 2)) as usize & 0b11]
+                };
+                let i = (row * 8 + column) * 4;
+                image[i..i + 4].copy_from_slice(&[r, g, b, a]);
+            }
+        }
+        image
+    }
+
+    fn draw_tile(&self, image: &mut [u8], width: usize, tile: usize, (x, y): (usize, usize), palette: u8) {
+        let colors = self.palette.colors();
+        for row in 0..8 {
+            let [low, high] = [0, 1].map(|byte| self.vram[tile * 0x10 + row * 2 + byte]);
+            for column in 0..8 {
+                let bit = 7 - column;
+                let color = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                let Color { r, g, b, a } = colors[(palette >> (color * 2)) as usize & 0b11];
+                let i = ((y + row) * width + x + column) * 4;
+                image[i..i + 4].copy_from_slice(&[r, g, b, a]);
+            }
+        }
+    }
+
+    fn sprite_fetch_cycles(&self, cycles: usize) -> usize {
+        // Sprites past the hardware limit take no time, or mode 3 could outlast the scanline
+        if self.debug.no_sprite_fetch_penalty || self.sprites_fetched >= 10 {
+            0
+        } else {
+            cycles
         }
     }
 
     fn search_objects(&mut self) {
         self.sprite_buffer_len = 0;
+        self.sprites_fetched = 0;
+        let limit = if self.unlimited_sprites { 40 } else { 10 };
+        if self.sprite_buffer.len() < limit {
+            self.sprite_buffer.resize(limit, Sprite::default());
+        }
         let sprite_height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
         for i in 0..40 {
             let i = i as usize * 4;
@@ -431,7 +769,7 @@ impl PixelProcessingUnit {
                 };
                 self.sprite_buffer_len += 1;
             }
-            if self.sprite_buffer_len == 10 {
+            if self.sprite_buffer_len as usize == limit {
                 break;
             }
         }
@@ -443,19 +781,19 @@ impl PixelProcessingUnit {
 
     pub fn start_dma(&mut self, value: u8) {
         self.dma = value;
-        self.dma_started = self.ticks - 4;
-        if self.dma_running {
-            // HACK: if a DMA requested was make right before this one, this dma_started
-            // rewritten would cancel the oam_block of that DMA. To fix this, I will hackly
-            // block the oam here, but this will block the oam 4 cycles early, but I think
-            // this will not be observable.
-            self.dma_block_oam = true;
+        if self.dma_running && !self.dma_block_oam {
+            // The transfer being replaced keeps going until the new one starts, so it still
+            // blocks OAM once its own initial delay is over
+            self.dma_replaced = Some(self.dma_started);
         }
+        self.dma_started = self.ticks - 4;
         self.dma_running = true;
     }
 
     pub fn machine_cycle(&mut self, ticks: usize) -> (bool, bool) {
+        let start = self.ticks;
         self.ticks += ticks;
+        self.interrupt_dot = None;
 
         // Most of the ppu behaviour is based on the LIJI32/SameBoy including all of the timing,
         // and most of the implementation.
@@ -469,10 +807,17 @@ impl PixelProcessingUnit {
         let mut vblank_interrupt = false;
 
         self.update_stat(&mut stat_interrupt);
+        if stat_interrupt {
+            self.interrupt_dot = Some(0);
+        }
 
         while self.next_ticks < self.ticks {
+            let raised = vblank_interrupt || stat_interrupt;
             let (clocks, state) =
                 self.handle_state_transition(&mut vblank_interrupt, &mut stat_interrupt);
+            if !raised && (vblank_interrupt || stat_interrupt) {
+                self.interrupt_dot = Some(self.next_ticks.saturating_sub(start));
+            }
             self.next_ticks += clocks;
             self.state = state;
         }
@@ -596,6 +941,8 @@ impl PixelProcessingUnit {
                 self.vram_read_block = true;
                 self.vram_write_block = true;
 
+                self.debug.mode3_start = self.next_ticks;
+
                 (5, PixelTransfer(ClearQueue))
             }
 
@@ -604,7 +951,7 @@ impl PixelProcessingUnit {
                 self.sprite_fifo.clear();
 
                 // Fill background FIFO with 8 dummy pixels
-                self.background_fifo.push_background(0x00, 0x00);
+                self.background_fifo.push_background(0x00, 0x00, 0x00);
 
                 self.fetcher_step = 0;
                 self.fetcher_x = 0;
@@ -704,6 +1051,7 @@ impl PixelProcessingUnit {
                 // TODO: handle extra penalty sprite at 0
                 let mut penalty = 0;
                 if self.sprite_at_0_penalty != 0
+                    && !self.debug.no_sprite_at_0_penalty
                     && self.sprite_buffer[self.sprite_buffer_len as usize - 1].sx == 0
                 {
                     penalty = self.sprite_at_0_penalty as usize;
@@ -715,7 +1063,7 @@ impl PixelProcessingUnit {
             PixelTransfer(FirstPixelFetching) => {
                 self.tick_pixel_fetcher(self.ly);
 
-                (1, PixelTransfer(SecondPixelFetching))
+                (self.sprite_fetch_cycles(1), PixelTransfer(SecondPixelFetching))
             }
             PixelTransfer(SecondPixelFetching) => {
                 self.tick_pixel_fetcher(self.ly);
@@ -731,20 +1079,21 @@ impl PixelProcessingUnit {
                     }
 
                     let tile = if tall { sprite.tile & !1 } else { sprite.tile };
-                    tile as u16 * 0x10 + py as u16 * 2
+                    let bank = if self.cgb && sprite.flags & 0x08 != 0 { 0x2000 } else { 0 };
+                    bank + tile as u16 * 0x10 + py as u16 * 2
                 };
 
-                (2, PixelTransfer(LowSpriteDataSetting))
+                (self.sprite_fetch_cycles(2), PixelTransfer(LowSpriteDataSetting))
             }
             PixelTransfer(LowSpriteDataSetting) => {
                 self.sprite_tile_data_low = self.vram[self.sprite_tile_address as usize];
 
-                (2, PixelTransfer(HighSpriteDataSetting))
+                (self.sprite_fetch_cycles(2), PixelTransfer(HighSpriteDataSetting))
             }
             PixelTransfer(HighSpriteDataSetting) => {
                 self.sprite_tile_data_high = self.vram[self.sprite_tile_address as usize + 1];
 
-                (1, PixelTransfer(SpritePushing))
+                (self.sprite_fetch_cycles(1), PixelTransfer(SpritePushing))
             }
             PixelTransfer(SpritePushing) => {
                 let flags = self.sprite_buffer[self.sprite_buffer_len as usize - 1].flags;
@@ -757,8 +1106,10 @@ impl PixelProcessingUnit {
                     tile_high,
                     flags & 0x10 != 0,
                     flags & 0x80 != 0,
+                    if self.cgb { flags & 0x07 } else { 0 },
                 );
                 self.sprite_buffer_len -= 1;
+                self.sprites_fetched += 1;
 
                 // loop again
                 (0, PixelTransfer(SpriteFetching))
@@ -784,13 +1135,21 @@ impl PixelProcessingUnit {
                 self.stat_mode_for_interrupt = 0;
                 self.update_stat(stat_interrupt);
 
+                if self.debug.log_mode3 {
+                    Logger::info(format!(
+                        "LY {:3}: mode 3 took {} dots",
+                        self.ly,
+                        self.next_ticks - self.debug.mode3_start
+                    ));
+                }
+
                 (1, HorizontalBlank(StartHBlankDelay))
             }
             HorizontalBlank(StartHBlankDelay) => (2, HorizontalBlank(ElapsedTickCalculation)),
             HorizontalBlank(ElapsedTickCalculation) => {
                 let elapsed = self.next_ticks - self.line_start_ticks;
 
-                (454 - elapsed, HorizontalBlank(ReachWindow))
+                (454_usize.saturating_sub(elapsed), HorizontalBlank(ReachWindow))
             }
             HorizontalBlank(ReachWindow) => {
                 if self.lcdc & 0x20 != 0 && self.wy == self.ly {
@@ -833,6 +1192,9 @@ impl PixelProcessingUnit {
             }
             VerticalBlank(FirstLineCheck) => {
                 if self.ly == HEIGHT as u8 {
+                    // Copied rather than swapped, pixels the PPU skips like those of a frozen SGB
+                    // screen have to keep their color from the frame before
+                    self.screen.copy_from_slice(&self.back_buffer[..]);
                     self.set_stat_mode(1);
                     *vblank_interrupt = true;
                     if !self.stat_signal && self.stat & 0x20 != 0 {
@@ -921,10 +1283,10 @@ impl PixelProcessingUnit {
                 rows.next().unwrap(),
             );
 
-            let a = u16::from_le_bytes(second_row[0..2].as_ref().try_into().unwrap());
-            let b = u16::from_le_bytes(first_row[0..2].as_ref().try_into().unwrap());
-            let c = u16::from_le_bytes(current_row[0..2].as_ref().try_into().unwrap());
-            let d = u16::from_le_bytes(first_row[4..6].as_ref().try_into().unwrap());
+            let a = u16::from_le_bytes([second_row[0], second_row[1]]);
+            let b = u16::from_le_bytes([first_row[0], first_row[1]]);
+            let c = u16::from_le_bytes([current_row[0], current_row[1]]);
+            let d = u16::from_le_bytes([first_row[4], first_row[5]]);
 
             let pattern = ((b & (a | c | d)) | (a & c & d)).to_le_bytes();
             first_row[0..2].clone_from_slice(pattern.as_slice());
@@ -950,9 +1312,9 @@ impl PixelProcessingUnit {
 
         let (previous_row, current_row) = (rows.nth(row - 1).unwrap(), rows.next().unwrap());
 
-        let a = u16::from_le_bytes(current_row[0..2].as_ref().try_into().unwrap());
-        let b = u16::from_le_bytes(previous_row[0..2].as_ref().try_into().unwrap());
-        let c = u16::from_le_bytes(previous_row[4..6].as_ref().try_into().unwrap());
+        let a = u16::from_le_bytes([current_row[0], current_row[1]]);
+        let b = u16::from_le_bytes([previous_row[0], previous_row[1]]);
+        let c = u16::from_le_bytes([previous_row[4], previous_row[5]]);
 
         let pattern = pattern(a, b, c).to_le_bytes();
         current_row[0..2].clone_from_slice(pattern.as_slice());
@@ -1002,8 +1364,9 @@ impl PixelProcessingUnit {
     fn tick_pixel_fetcher(&mut self, ly: u8) {
         let is_in_window = self.is_in_window;
 
+        // Index in VRAM of the low byte of the current tile row
         let fetch_tile_address =
-            |ppu: &mut PixelProcessingUnit, is_in_window: bool, ly: u8| -> u16 {
+            |ppu: &mut PixelProcessingUnit, is_in_window: bool, ly: u8| -> usize {
                 let mut tile = ppu.fetch_tile_number as u16;
                 if ppu.lcdc & 0x10 == 0 {
                     tile += 0x100;
@@ -1011,21 +1374,25 @@ impl PixelProcessingUnit {
                         tile -= 0x100;
                     }
                 }
-                let address = tile * 0x10 + 0x8000;
-                let offset = if is_in_window {
-                    2 * (ppu.wyc as u16 % 8)
+                let bank = if ppu.fetch_tile_attributes & 0x08 != 0 { 0x2000 } else { 0 };
+                let mut row = if is_in_window {
+                    ppu.wyc as u16 % 8
                 } else {
-                    2 * (ly.wrapping_add(ppu.scy) % 8) as u16
+                    (ly.wrapping_add(ppu.scy) % 8) as u16
                 };
+                if ppu.fetch_tile_attributes & 0x40 != 0 {
+                    row = 7 - row;
+                }
 
-                address + offset
+                (bank + tile * 0x10 + 2 * row) as usize
             };
 
         let push_to_fifo = |ppu: &mut PixelProcessingUnit| {
             if ppu.background_fifo.is_empty() {
-                let low = ppu.fetch_tile_data_low;
-                let high = ppu.fetch_tile_data_high;
-                ppu.background_fifo.push_background(low, high);
+                let attributes = ppu.fetch_tile_attributes;
+                let [low, high] = [ppu.fetch_tile_data_low, ppu.fetch_tile_data_high]
+                    .map(|t| if attributes & 0x20 != 0 { t.reverse_bits() } else { t });
+                ppu.background_fifo.push_background(low, high, attributes);
                 ppu.fetcher_step = 0;
             }
         };
@@ -1058,19 +1425,21 @@ impl PixelProcessingUnit {
                 };
 
                 let offset = (32 * ty as u16 + tx as u16) & 0x03ff;
-                self.fetch_tile_number = self.vram[(tile_map + offset) as usize - 0x8000];
+                let map_address = (tile_map + offset) as usize - 0x8000;
+                self.fetch_tile_number = self.vram[map_address];
+                self.fetch_tile_attributes = if self.cgb { self.vram[0x2000 + map_address] } else { 0 };
             }
             2 => {}
             // fetch tile data (low)
             3 => {
                 let fetch_tile_address = fetch_tile_address(self, is_in_window, ly);
-                self.fetch_tile_data_low = self.vram[fetch_tile_address as usize - 0x8000];
+                self.fetch_tile_data_low = self.vram[fetch_tile_address];
             }
             4 => {}
             // fetch tile data (high)
             5 => {
                 let fetch_tile_address = fetch_tile_address(self, is_in_window, ly);
-                self.fetch_tile_data_high = self.vram[fetch_tile_address as usize + 1 - 0x8000];
+                self.fetch_tile_data_high = self.vram[fetch_tile_address + 1];
                 if self.is_in_window {
                     self.fetcher_x += 1;
                 }
@@ -1104,40 +1473,69 @@ impl PixelProcessingUnit {
             }
 
             let i = (self.ly as usize) * WIDTH + self.screen_x as usize;
-            let background_enable = self.lcdc & 0x01 != 0;
-            let bcolor = if background_enable { pixel & 0b11 } else { 0 };
-
-            // background color, with pallete applied
-            let palette = self.bgp;
-            let mut color = (palette >> (bcolor * 2)) & 0b11;
-
-            if let Some(sprite_pixel) = sprite_pixel {
-                let scolor = sprite_pixel & 0b11;
-                let background_priority = (sprite_pixel >> 3) & 0x01 != 0;
-                if scolor == 0 || background_priority && bcolor != 0 {
-                    // use background color
-                } else {
-                    // use sprite color
-                    let palette = (sprite_pixel >> 4) & 0x1;
-                    let palette = [self.obp0, self.obp1][palette as usize];
-                    color = (palette >> (scolor * 2)) & 0b11;
-                }
-            }
-            let Color { a, r, g, b } = match color {
-                0 => WHITE,
-                1 => LIGHT_GRAY,
-                2 => DARK_GRAY,
-                3 => BLACK,
-                _ => unreachable!(),
+            let color = if self.cgb {
+                Some(self.cgb_color(pixel, sprite_pixel))
+            } else {
+                self.dmg_color(pixel, sprite_pixel)
             };
-            self.screen[i * 4] = r;
-            self.screen[(i * 4) + 1] = g;
-            self.screen[(i * 4) + 2] = b;
-            self.screen[(i * 4) + 3] = a;
+            if let Some(Color { a, r, g, b }) = color {
+                self.back_buffer[i * 4] = r;
+                self.back_buffer[(i * 4) + 1] = g;
+                self.back_buffer[(i * 4) + 2] = b;
+                self.back_buffer[(i * 4) + 3] = a;
+            }
             self.screen_x += 1;
             self.scanline_x += 1;
         }
     }
+
+    /// None while a Super Game Boy freezes the screen.
+    fn dmg_color(&self, pixel: u8, sprite_pixel: Option<u8>) -> Option<Color> {
+        let background_enable = self.lcdc & 0x01 != 0;
+        let bcolor = if background_enable { pixel & 0b11 } else { 0 };
+
+        // background color, with pallete applied
+        let palette = self.bgp;
+        let mut color = (palette >> (bcolor * 2)) & 0b11;
+
+        if let Some(sprite_pixel) = sprite_pixel {
+            let scolor = sprite_pixel & 0b11;
+            let background_priority = (sprite_pixel >> 3) & 0x01 != 0;
+            if scolor == 0 || background_priority && bcolor != 0 {
+                // use background color
+            } else {
+                // use sprite color
+                let palette = (sprite_pixel >> 4) & 0x1;
+                let palette = [self.obp0, self.obp1][palette as usize];
+                color = (palette >> (scolor * 2)) & 0b11;
+            }
+        }
+        match &self.sgb {
+            Some(sgb) => sgb.color(self.screen_x as usize, self.ly as usize, color),
+            None => Some(self.palette.colors()[color as usize]),
+        }
+    }
+
+    /// In CGB mode LCDC bit 0 no longer disables the background, it makes every sprite draw over
+    /// it instead. Otherwise the background wins over a sprite when either of them asks for it.
+    fn cgb_color(&self, pixel: u8, sprite_pixel: Option<u8>) -> Color {
+        let bcolor = pixel & 0b11;
+        let master_priority = self.lcdc & 0x01 != 0;
+        let (palettes, palette, color) = match sprite_pixel {
+            Some(sprite_pixel)
+                if sprite_pixel & 0b11 != 0
+                    && !(master_priority
+                        && bcolor != 0
+                        && (pixel & 0x80 != 0 || sprite_pixel & 0x08 != 0)) =>
+            {
+                (&self.obj_palettes, sprite_pixel >> 5, sprite_pixel & 0b11)
+            }
+            _ => (&self.bg_palettes, (pixel >> 2) & 0x07, bcolor),
+        };
+
+        let index = palette as usize * 8 + color as usize * 2;
+        Color::from_rgb555(u16::from_le_bytes([palettes[index], palettes[index + 1]]))
+    }
 }
 
 impl From<Color> for u32 {
@@ -1146,28 +1544,3 @@ impl From<Color> for u32 {
         u32::from_be_bytes([a, r, g, b])
     }
 }
-
-const WHITE: Color = Color {
-    r: 224,
-    g: 248,
-    b: 208,
-    a: 255,
-};
-const LIGHT_GRAY: Color = Color {
-    r: 136,
-    g: 192,
-    b: 112,
-    a: 255,
-};
-const DARK_GRAY: Color = Color {
-    r: 39,
-    g: 80,
-    b: 70,
-    a: 255,
-};
-const BLACK: Color = Color {
-    r: 8,
-    g: 24,
-    b: 32,
-    a: 255,
-};
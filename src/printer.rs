@@ -0,0 +1,196 @@
+use crate::printer::Stage::{
+    Alive, ChecksumHigh, ChecksumLow, Command, Compression, Data, LengthHigh, LengthLow, MagicHigh, MagicLow, Status,
+};
+use crate::WIDTH;
+
+const INITIALIZE: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const UNPROCESSED_DATA: u8 = 0x08;
+
+/// Status polls a print keeps the printer busy for, so the game's progress bar has time to fill
+const PRINT_POLLS: u8 = 16;
+
+/// Shades of the thermal paper for each of the 4 colors
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// Byte of a packet the printer expects next. Every packet is `88 33`, command, compression flag,
+/// 16 bit data length, data, 16 bit checksum and two bytes the printer answers with its alive
+/// marker and status.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
+enum Stage {
+    MagicLow,
+    MagicHigh,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// Game Boy Printer connected to the serial port. It buffers the tile data the game sends and
+/// turns it into a page once told to print.
+#[derive(Clone, Debug, Eq, PartialEq, PartialOrd)]
+pub(crate) struct Printer {
+    stage: Stage,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    /// Bands of 2 rows of 20 tiles each, in the same 2bpp format as VRAM
+    tiles: Vec<u8>,
+    status: u8,
+    busy_polls: u8,
+    /// Printed pages as RGBA pixels, 160 pixels wide
+    pub(crate) pages: Vec<Vec<u8>>,
+}
+
+impl Printer {
+    pub(crate) fn new() -> Self {
+        Self {
+            stage: MagicLow,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: vec![],
+            checksum: 0,
+            tiles: vec![],
+            status: 0,
+            busy_polls: 0,
+            pages: vec![],
+        }
+    }
+
+    /// Receives a byte from the Game Boy, returning the one sent back at the same time.
+    pub(crate) fn exchange(&mut self, byte: u8) -> u8 {
+        let reply = match self.stage {
+            Alive => 0x81,
+            Status => self.status,
+            _ => 0x00,
+        };
+
+        self.stage = match self.stage {
+            MagicLow if byte == 0x88 => MagicHigh,
+            MagicLow => MagicLow,
+            MagicHigh if byte == 0x33 => Command,
+            MagicHigh => MagicLow,
+            Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                Compression
+            }
+            Compression => {
+                self.compressed = byte & 1 == 1;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                LengthLow
+            }
+            LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                LengthHigh
+            }
+            LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length == 0 { ChecksumLow } else { Data }
+            }
+            Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize { ChecksumLow } else { Data }
+            }
+            ChecksumLow => {
+                self.checksum ^= byte as u16;
+                ChecksumHigh
+            }
+            ChecksumHigh => {
+                self.checksum ^= (byte as u16) << 8;
+                if self.checksum == 0 {
+                    self.status &= !CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= CHECKSUM_ERROR;
+                }
+                Alive
+            }
+            Alive => Status,
+            Status => MagicLow,
+        };
+        reply
+    }
+
+    fn run_command(&mut self) {
+        match self.command {
+            INITIALIZE => {
+                self.tiles.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            DATA => {
+                let data = if self.compressed { decompress(&self.packet) } else { self.packet.clone() };
+                self.tiles.extend(data);
+                if !self.tiles.is_empty() {
+                    self.status |= UNPROCESSED_DATA;
+                }
+            }
+            PRINT => {
+                // Data is sheets, margins, palette and exposure, only the palette affects the page
+                let palette = self.packet.get(2).copied().filter(|&p| p != 0).unwrap_or(0xE4);
+                self.pages.push(self.page(palette));
+                self.tiles.clear();
+                self.status = (self.status & !UNPROCESSED_DATA) | PRINTING;
+                self.busy_polls = PRINT_POLLS;
+            }
+            // Anything else, mostly the status command, just reports progress
+            _ => {
+                self.busy_polls = self.busy_polls.saturating_sub(1);
+                if self.busy_polls == 0 {
+                    self.status &= !PRINTING;
+                }
+            }
+        }
+    }
+
+    /// Renders the buffered tiles, 20 to a row, with the 2 bit shades given by `palette`.
+    fn page(&self, palette: u8) -> Vec<u8> {
+        let rows = self.tiles.len().div_ceil(20 * 16);
+        let mut page = vec![0; WIDTH * rows * 8 * 4];
+        for (index, tile) in self.tiles.chunks_exact(16).enumerate() {
+            let (tile_x, tile_y) = (index % 20, index / 20);
+            for (y, line) in tile.chunks_exact(2).enumerate() {
+                for x in 0..8 {
+                    let color = (line[0] >> (7 - x)) & 1 | ((line[1] >> (7 - x)) & 1) << 1;
+                    let shade = SHADES[(palette >> (color * 2)) as usize & 3];
+                    let pixel = ((tile_y * 8 + y) * WIDTH + tile_x * 8 + x) * 4;
+                    page[pixel..pixel + 4].copy_from_slice(&[shade, shade, shade, 0xFF]);
+                }
+            }
+        }
+        page
+    }
+}
+
+/// Expands run-length encoded data: a byte with the top bit set repeats the next byte
+/// `(n & 0x7F) + 2` times, any other byte is followed by `n + 1` literal bytes.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = vec![];
+    let mut bytes = data.iter().copied();
+    while let Some(header) = bytes.next() {
+        if header & 0x80 != 0 {
+            let value = bytes.next().unwrap_or(0);
+            output.resize(output.len() + (header & 0x7F) as usize + 2, value);
+        } else {
+            output.extend(bytes.by_ref().take(header as usize + 1));
+        }
+    }
+    output
+}
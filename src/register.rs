@@ -17,7 +17,7 @@ pub enum RegisterId {
     L,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, PartialOrd)]
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, PartialOrd)]
 pub struct Register {
     registers: Vec<ByteRegister>,
     pub flags: FlagRegister,
@@ -59,11 +59,12 @@ pub enum ConditionCode {
 }
 
 impl Register {
-    pub fn new(boot_rom: bool) -> Self {
+    /// Registers as the boot ROM leaves them, where A tells games whether they run on a CGB.
+    pub fn new(boot_rom: bool, cgb: bool) -> Self {
         if !boot_rom {
             Self {
                 registers: vec![
-                    ByteRegister { value: 0x01, id: A },
+                    ByteRegister { value: if cgb { 0x11 } else { 0x01 }, id: A },
                     ByteRegister { value: 0x00, id: B },
                     ByteRegister { value: 0x13, id: C },
                     ByteRegister { value: 0x00, id: D },
@@ -1,35 +1,316 @@
-use pixels::Pixels;
-use std::cmp::max;
-use instant::{Duration, Instant};
+use clap::ValueEnum;
+#[cfg(feature = "frontend")]
+use {pixels::{wgpu::PresentMode, Pixels}, std::cmp::max, instant::Instant};
+use instant::Duration;
+use crate::{HEIGHT, WIDTH};
+
+/// Emulation of the color reproduction of real handheld screens, applied to the RGB555 output.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ColorCorrection {
+    /// Raw RGB555 to RGB888 conversion
+    #[default]
+    None,
+    /// Game Boy Color LCD, using the channel mixing matrix from Gambatte/higan:
+    /// R = (26r + 4g + 2b) / 32, G = (24g + 8b) / 32, B = (6r + 4g + 22b) / 32
+    Cgb,
+    /// Game Boy Advance LCD, using the matrix from higan in linear light. Channels are
+    /// linearized with the LCD gamma of 4.0, mixed, and encoded with a 2.2 display gamma:
+    /// R = (255r + 50g) / 255, G = (10r + 230g + 30b) / 255, B = (50r + 10g + 220b) / 255,
+    /// then scaled by 255/280 to account for the darker screen.
+    Gba,
+}
+
+impl ColorCorrection {
+    /// Corrected RGB888 color for every RGB555 color.
+    fn lookup_table(&self) -> Option<Vec<[u8; 3]>> {
+        let correct = match self {
+            ColorCorrection::None => return None,
+            ColorCorrection::Cgb => Self::cgb,
+            ColorCorrection::Gba => Self::gba,
+        };
+        let table = (0..0x8000_u16)
+            .map(|color| {
+                let channel = |shift: u16| ((color >> shift) & 0x1F) as f64 / 31.0;
+                correct([channel(0), channel(5), channel(10)])
+                    .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect();
+        Some(table)
+    }
+
+    fn cgb([r, g, b]: [f64; 3]) -> [f64; 3] {
+        [
+            (26.0 * r + 4.0 * g + 2.0 * b) / 32.0,
+            (24.0 * g + 8.0 * b) / 32.0,
+            (6.0 * r + 4.0 * g + 22.0 * b) / 32.0,
+        ]
+    }
+
+    fn gba(color: [f64; 3]) -> [f64; 3] {
+        const LCD_GAMMA: f64 = 4.0;
+        const DISPLAY_GAMMA: f64 = 2.2;
+
+        let [r, g, b] = color.map(|channel| channel.powf(LCD_GAMMA));
+        [
+            (255.0 * r + 50.0 * g) / 255.0,
+            (10.0 * r + 230.0 * g + 30.0 * b) / 255.0,
+            (50.0 * r + 10.0 * g + 220.0 * b) / 255.0,
+        ]
+        .map(|channel| channel.min(1.0).powf(1.0 / DISPLAY_GAMMA) * 255.0 / 280.0)
+    }
+}
+
+/// How the screen is fit into the window.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Scaling {
+    /// The largest integer multiple of 160x144 that fits, with borders around it
+    #[default]
+    Integer,
+    /// As large as fits while keeping the 10:9 aspect ratio
+    Aspect,
+    /// Fill the whole window
+    Stretch,
+}
+
+impl Scaling {
+    /// Size of the buffer the screen is scaled into for a window of `width` by `height`. Pixels
+    /// only scales its buffer by whole numbers, so the other modes get a buffer the size they fill.
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    pub(crate) fn buffer_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let (buffer_width, buffer_height) = match self {
+            Scaling::Integer => return (WIDTH as u32, HEIGHT as u32),
+            Scaling::Aspect => {
+                let scale = (width as f64 / WIDTH as f64).min(height as f64 / HEIGHT as f64);
+                ((WIDTH as f64 * scale) as u32, (HEIGHT as f64 * scale) as u32)
+            }
+            Scaling::Stretch => (width, height),
+        };
+        (buffer_width.max(WIDTH as u32), buffer_height.max(HEIGHT as u32))
+    }
+}
+
+/// Whether presenting a frame waits for the display's vertical blank.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum VSync {
+    /// Wait for every vertical blank, frames never tear
+    On,
+    /// Present frames as soon as they're drawn, tearing when one lands mid refresh
+    #[default]
+    Off,
+    /// Wait for the vertical blank unless the frame is already late for it, where supported
+    Adaptive,
+}
+
+impl VSync {
+    #[cfg(feature = "frontend")]
+    pub fn present_mode(&self) -> PresentMode {
+        match self {
+            VSync::On => PresentMode::Fifo,
+            VSync::Off => PresentMode::AutoNoVsync,
+            VSync::Adaptive => PresentMode::AutoVsync,
+        }
+    }
+}
+
+/// Emulation of the slow response of the DMG LCD, which leaves a fading trail behind moving or
+/// flickering sprites that some games rely on for transparency.
+#[derive(Default)]
+pub(crate) struct Ghosting {
+    /// Share of the previous frame blended into every new one, 0 shows every frame as is
+    strength: f32,
+    /// The last frame shown, which the next one is blended with
+    displayed: Vec<u8>,
+}
+
+impl Ghosting {
+    /// Mixes the previous frame into `frame`, which becomes the previous frame in turn.
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    pub(crate) fn blend(&mut self, frame: &mut [u8]) {
+        if self.strength == 0.0 {
+            return;
+        }
+        if self.displayed.len() != frame.len() {
+            self.displayed = frame.to_vec();
+        }
+        for (channel, previous) in frame.iter_mut().zip(&mut self.displayed) {
+            *channel = (*channel as f32 * (1.0 - self.strength) + *previous as f32 * self.strength).round() as u8;
+            *previous = *channel;
+        }
+    }
+}
+
+/// Replaces the color of every pixel of an RGBA `frame` with its entry in a color correction
+/// lookup `table`.
+fn correct(table: &[[u8; 3]], frame: &mut [u8]) {
+    for pixel in frame.chunks_exact_mut(4) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|channel| channel as usize >> 3);
+        pixel[..3].copy_from_slice(&table[r | g << 5 | b << 10]);
+    }
+}
 
 #[derive(Default)]
 pub struct Renderer {
-    pub(crate) slowest: Duration,
+    pub slowest: Duration,
+    #[cfg(feature = "frontend")]
     pixels: Option<Pixels>,
+    /// Corrected color for every RGB555 color, if color correction is enabled
+    correction: Option<Vec<[u8; 3]>>,
+    pub(crate) ghosting: Ghosting,
+    #[cfg_attr(not(feature = "frontend"), allow(dead_code))]
+    scaling: Scaling,
+    /// Window size in physical pixels
+    #[cfg(feature = "frontend")]
+    surface: (u32, u32),
+    /// The screen after color correction and ghosting, before scaling
+    #[cfg(feature = "frontend")]
+    frame: Vec<u8>,
+    #[cfg(feature = "frontend")]
+    vsync: VSync,
+    /// Tint the line the PPU is drawing, for frames shown mid-frame while debugging
+    beam_overlay: bool,
+    /// Set while the frame limiter is off, so frames aren't held up waiting for the display
+    #[cfg(feature = "frontend")]
+    fast_forward: bool,
+    /// When the last frame was presented
+    #[cfg(feature = "frontend")]
+    presented: Option<Instant>,
 }
 
 impl Renderer {
     pub fn new() -> Self {
         Self {
             slowest: Duration::from_secs(0),
+            #[cfg(feature = "frontend")]
             pixels: None,
+            correction: None,
+            ghosting: Ghosting::default(),
+            scaling: Scaling::default(),
+            #[cfg(feature = "frontend")]
+            surface: (WIDTH as u32, HEIGHT as u32),
+            #[cfg(feature = "frontend")]
+            frame: vec![0; WIDTH * HEIGHT * 4],
+            #[cfg(feature = "frontend")]
+            vsync: VSync::default(),
+            beam_overlay: false,
+            #[cfg(feature = "frontend")]
+            fast_forward: false,
+            #[cfg(feature = "frontend")]
+            presented: None,
         }
     }
 
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.correction = correction.lookup_table();
+    }
+
+    /// The RGBA `screen` with the same color correction as the window, for frames saved to files.
+    pub fn corrected(&self, screen: &[u8]) -> Vec<u8> {
+        let mut frame = screen.to_vec();
+        if let Some(table) = &self.correction {
+            correct(table, &mut frame);
+        }
+        frame
+    }
+
+    /// Blends `strength` of the previous frame into every new one, from 0 for crisp output to 1.
+    pub fn set_ghosting(&mut self, strength: f32) {
+        self.ghosting = Ghosting {
+            strength: strength.clamp(0.0, 1.0),
+            displayed: Vec::new(),
+        };
+    }
+
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+        #[cfg(feature = "frontend")]
+        self.resize(self.surface.0, self.surface.1);
+    }
+
+    /// Follows a window resize of `width` by `height` physical pixels.
+    #[cfg(feature = "frontend")]
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.surface = (width, height);
+        let Some(pixels) = self.pixels.as_mut() else { return };
+        let (buffer_width, buffer_height) = self.scaling.buffer_size(width, height);
+        pixels.resize_surface(width, height).unwrap();
+        pixels.resize_buffer(buffer_width, buffer_height).unwrap();
+    }
+
+    #[cfg(feature = "frontend")]
     pub fn pixels(&mut self) -> &mut Option<Pixels> {
         &mut self.pixels
     }
 
+    #[cfg(feature = "frontend")]
     pub fn set_pixels(&mut self, pixels: Pixels) {
         self.pixels = Some(pixels);
     }
 
-    pub(crate) fn render(&mut self, screen: &[u8]) {
-        let now = Instant::now();
-        if let Some(pixels) = self.pixels().as_mut() {
-            let frame = pixels.frame_mut();
+    /// Tints the line the PPU is on whenever a frame is shown before it's finished, see
+    /// `MemoryManagementUnit::redraw`. Finished frames have no line being drawn.
+    pub fn set_beam_overlay(&mut self, beam_overlay: bool) {
+        self.beam_overlay = beam_overlay;
+    }
+
+    pub fn beam_overlay(&self) -> bool {
+        self.beam_overlay
+    }
+
+    /// Records the present mode the window's pixels were built with.
+    #[cfg(feature = "frontend")]
+    pub fn set_vsync(&mut self, vsync: VSync) {
+        self.vsync = vsync;
+    }
+
+    /// Presenting with VSync blocks until the display is ready, which would hold fast-forward to
+    /// the refresh rate. While it's on, frames that come faster than a refresh are dropped instead.
+    #[cfg(feature = "frontend")]
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    /// Presents the screen in the window, if there is one, with the beam overlay on `scanline` if
+    /// the screen is still being drawn. Headless builds have nothing to draw.
+    pub(crate) fn render(&mut self, screen: &[u8], scanline: Option<u8>) {
+        #[cfg(not(feature = "frontend"))]
+        let _ = (screen, scanline);
+
+        #[cfg(feature = "frontend")]
+        if let Some(pixels) = self.pixels.as_mut() {
+            let refresh = Duration::from_secs(1) / 60;
+            let dropped = self.presented.is_some_and(|presented| presented.elapsed() < refresh);
+            if self.fast_forward && self.vsync != VSync::Off && dropped {
+                return;
+            }
+            let now = Instant::now();
+            let frame = &mut self.frame;
             frame.copy_from_slice(screen);
+            if let Some(table) = &self.correction {
+                correct(table, frame);
+            }
+            self.ghosting.blend(frame);
+            if let Some(line) = scanline.map(usize::from).filter(|line| self.beam_overlay && *line < HEIGHT) {
+                for pixel in frame[line * WIDTH * 4..][..WIDTH * 4].chunks_exact_mut(4) {
+                    let tinted = [pixel[0] / 2 + 0x80, pixel[1] / 2, pixel[2] / 2];
+                    pixel[..3].copy_from_slice(&tinted);
+                }
+            }
+            let width = pixels.texture().width() as usize;
+            let scaled = pixels.frame_mut();
+            if scaled.len() == frame.len() {
+                scaled.copy_from_slice(frame);
+            } else {
+                // Nearest neighbor, every buffer pixel takes the screen pixel it falls on
+                let height = scaled.len() / 4 / width;
+                for (y, row) in scaled.chunks_exact_mut(width * 4).enumerate() {
+                    let line = &frame[y * HEIGHT / height * WIDTH * 4..][..WIDTH * 4];
+                    for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                        pixel.copy_from_slice(&line[x * WIDTH / width * 4..][..4]);
+                    }
+                }
+            }
             pixels.render().unwrap();
+            self.presented = Some(Instant::now());
             let duration = Instant::now() - now;
             // println!("Render took {:?}", duration);
             self.slowest = max(self.slowest, duration);
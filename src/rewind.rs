@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use iron_boy::Gameboy;
+
+/// Frames between snapshots
+const SNAPSHOT_INTERVAL: u32 = 4;
+/// Memory the history may take up, the oldest snapshots are dropped past it
+const MEMORY_BUDGET: usize = 128 << 20;
+
+/// Recent snapshots of the Game Boy, for stepping back in time while the rewind key is held.
+pub struct Rewind {
+    history: Arc<Mutex<History>>,
+    snapshots: SyncSender<Vec<u8>>,
+    frames: u32,
+}
+
+impl Rewind {
+    /// Keeps up to `seconds` of history, `None` if that's no history at all.
+    pub fn new(seconds: u32) -> Option<Self> {
+        let capacity = (seconds.saturating_mul(60) / SNAPSHOT_INTERVAL) as usize;
+        if capacity == 0 {
+            return None;
+        }
+
+        let history = Arc::new(Mutex::new(History { capacity, ..Default::default() }));
+        let (snapshots, received) = sync_channel::<Vec<u8>>(1);
+        let worker = history.clone();
+        thread::spawn(move || {
+            for snapshot in received {
+                worker.lock().unwrap().push(snapshot);
+            }
+        });
+
+        Some(Self { history, snapshots, frames: 0 })
+    }
+
+    /// Called after every frame, snapshots the Game Boy every few of them.
+    pub fn record(&mut self, gameboy: &Gameboy) {
+        self.frames = (self.frames + 1) % SNAPSHOT_INTERVAL;
+        if self.frames != 0 {
+            return;
+        }
+
+        // The Game Boy can't leave the frame thread so it's serialized here, which is mostly
+        // copying its memory since that's serialized as plain bytes. The worker delta encodes and
+        // stores it, and the snapshot is dropped if the worker is still busy with the last one.
+        let snapshot = bincode::serialize(gameboy).unwrap();
+        let _ = self.snapshots.try_send(snapshot);
+    }
+
+    /// Loads the newest snapshot into the Game Boy and forgets it. Returns false once the history
+    /// runs out.
+    pub fn step_back(&mut self, gameboy: &mut Gameboy) -> bool {
+        let Some(snapshot) = self.history.lock().unwrap().pop() else {
+            return false;
+        };
+        gameboy.load_state(bincode::deserialize(&snapshot).unwrap());
+        self.frames = 0;
+        true
+    }
+}
+
+/// Consecutive snapshots are nearly identical, the ROM alone makes up most of them. Only the
+/// newest one is stored whole, every older one as its difference from the snapshot after it.
+#[derive(Default)]
+struct History {
+    newest: Option<Vec<u8>>,
+    /// Oldest first
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+    size: usize,
+}
+
+impl History {
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.newest.replace(snapshot) {
+            let delta = delta(&previous, self.newest.as_ref().unwrap());
+            self.size += delta.len();
+            self.deltas.push_back(delta);
+        }
+
+        while self.deltas.len() >= self.capacity || self.size > MEMORY_BUDGET {
+            let Some(oldest) = self.deltas.pop_front() else { break };
+            self.size -= oldest.len();
+        }
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        if let Some(delta) = self.deltas.pop_back() {
+            self.size -= delta.len();
+            self.newest = Some(apply(&newest, &delta));
+        }
+        Some(newest)
+    }
+}
+
+/// Encodes `old` against `new` as alternating runs of unchanged and changed bytes, each run
+/// prefixed by its length as a little endian u32. Changed bytes are stored XORed with `new`.
+fn delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| old[i] ^ new.get(i).copied().unwrap_or(0);
+
+    let mut delta = vec![];
+    let mut i = 0;
+    while i < old.len() {
+        let unchanged = i;
+        while i < old.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let changed = i;
+        while i < old.len() && xor(i) != 0 {
+            i += 1;
+        }
+        delta.extend(((changed - unchanged) as u32).to_le_bytes());
+        delta.extend(((i - changed) as u32).to_le_bytes());
+        delta.extend((changed..i).map(xor));
+    }
+    delta
+}
+
+/// Rebuilds the older snapshot from `new` and the [`delta`] between them.
+fn apply(new: &[u8], mut delta: &[u8]) -> Vec<u8> {
+    let byte = |i: usize| new.get(i).copied().unwrap_or(0);
+    let length = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap()) as usize;
+
+    let mut old = vec![];
+    while !delta.is_empty() {
+        let (unchanged, changed) = (length(&delta[0..4]), length(&delta[4..8]));
+        let start = old.len();
+        old.extend((start..start + unchanged).map(byte));
+        let start = old.len();
+        old.extend(delta[8..8 + changed].iter().enumerate().map(|(i, xor)| xor ^ byte(start + i)));
+        delta = &delta[8 + changed..];
+    }
+    old
+}
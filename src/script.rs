@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+
+use crate::gameboy::Gameboy;
+use crate::joypad::Buttons;
+use crate::logger::Logger;
+use crate::register::RegisterId::{A, B, C, D, E, H, L};
+
+/// State shared between the emulator and the functions exposed to the script. The script only
+/// runs between frames, so it works on a snapshot of the address space and its writes are queued
+/// until the callback returns.
+#[derive(Default)]
+struct Frame {
+    number: i64,
+    memory: Vec<u8>,
+    /// AF, BC, DE, HL, SP and PC
+    registers: [u16; 6],
+    pokes: Vec<(u16, u8)>,
+    buttons: Buttons,
+    save_state: bool,
+}
+
+/// A Rhai script driven by the emulator, for automation and RAM exploration.
+///
+/// Top-level statements run once when the script is loaded, then `on_frame()` is called after
+/// every frame. Exposed API:
+/// - `peek(address)`: byte at `address`, as seen by the CPU at the end of the frame.
+/// - `poke(address, value)`: writes `value` to `address` before the next frame.
+/// - `reg(name)`: value of a register, one of `a b c d e f h l af bc de hl sp pc`.
+/// - `press(button)`: holds a button during the next frame, one of
+///   `a b select start up down left right`.
+/// - `frame()`: number of frames run since the script was loaded.
+/// - `save_state()`: saves the state to disk, the same as pressing S.
+/// - `print(value)`: logs `value`.
+///
+/// Loading states isn't exposed, since a loaded state needs its window and audio stream set up
+/// again. Restart the emulator with the save file instead.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    frame: Rc<RefCell<Frame>>,
+}
+
+impl Script {
+    pub fn load(path: PathBuf) -> Result<Self, Box<EvalAltResult>> {
+        let frame = Rc::new(RefCell::new(Frame::default()));
+        let mut engine = Engine::new();
+        engine.on_print(|text| Logger::info(text));
+
+        let state = frame.clone();
+        engine.register_fn("peek", move |address: i64| -> i64 {
+            state.borrow().memory[address as u16 as usize] as i64
+        });
+
+        let state = frame.clone();
+        engine.register_fn("poke", move |address: i64, value: i64| {
+            let mut state = state.borrow_mut();
+            state.memory[address as u16 as usize] = value as u8;
+            state.pokes.push((address as u16, value as u8));
+        });
+
+        let state = frame.clone();
+        engine.register_fn("reg", move |name: &str| -> Result<i64, Box<EvalAltResult>> {
+            let [af, bc, de, hl, sp, pc] = state.borrow().registers.map(i64::from);
+            Ok(match name {
+                "a" => af >> 8,
+                "f" => af & 0xFF,
+                "b" => bc >> 8,
+                "c" => bc & 0xFF,
+                "d" => de >> 8,
+                "e" => de & 0xFF,
+                "h" => hl >> 8,
+                "l" => hl & 0xFF,
+                "af" => af,
+                "bc" => bc,
+                "de" => de,
+                "hl" => hl,
+                "sp" => sp,
+                "pc" => pc,
+                _ => return Err(format!("Unknown register {name}").into()),
+            })
+        });
+
+        let state = frame.clone();
+        engine.register_fn("press", move |button: &str| -> Result<(), Box<EvalAltResult>> {
+            let Some(button) = Buttons::named(button) else {
+                return Err(format!("Unknown button {button}").into());
+            };
+            state.borrow_mut().buttons |= button;
+            Ok(())
+        });
+
+        let state = frame.clone();
+        engine.register_fn("frame", move || state.borrow().number);
+
+        let state = frame.clone();
+        engine.register_fn("save_state", move || state.borrow_mut().save_state = true);
+
+        let ast = engine.compile_file(path)?;
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)?;
+
+        Ok(Self { engine, ast, scope, frame })
+    }
+
+    /// Runs the script's `on_frame` callback and applies its writes and button presses.
+    /// Returns whether the script asked for the state to be saved.
+    pub fn on_frame(&mut self, gameboy: &mut Gameboy) -> Result<bool, Box<EvalAltResult>> {
+        {
+            let mut frame = self.frame.borrow_mut();
+            frame.number += 1;
+            frame.memory = (0..=0xFFFF).map(|address| gameboy.mmu.internal_read(address)).collect();
+            let flags = gameboy.reg.flags;
+            let f = u8::from(flags.z) << 7 | u8::from(flags.n) << 6 | u8::from(flags.h) << 5 | u8::from(flags.c) << 4;
+            let word = |high: u8, low: u8| u16::from_le_bytes([low, high]);
+            frame.registers = [
+                word(gameboy[A].value, f),
+                word(gameboy[B].value, gameboy[C].value),
+                word(gameboy[D].value, gameboy[E].value),
+                word(gameboy[H].value, gameboy[L].value),
+                gameboy.reg.sp.value(),
+                gameboy.reg.pc.value(),
+            ];
+        }
+
+        let _: Dynamic = self.engine.call_fn(&mut self.scope, &self.ast, "on_frame", ())?;
+
+        let mut frame = self.frame.borrow_mut();
+        for (address, value) in frame.pokes.drain(..) {
+            gameboy.mmu.internal_write(address as usize, value);
+        }
+        gameboy.mmu.joypad.scripted = std::mem::take(&mut frame.buttons);
+        Ok(std::mem::take(&mut frame.save_state))
+    }
+}
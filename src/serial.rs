@@ -1,4 +1,11 @@
+#[cfg(any(unix, windows))]
+use crate::link::{LinkPeer, Reply};
+#[cfg(any(unix, windows))]
+use crate::logger::Logger;
+#[cfg(any(unix, windows))]
+use std::io::{stdout, Write};
 use crate::mmu::MemoryArea;
+use crate::printer::Printer;
 use crate::serial::State::{Off, Transfer};
 
 use serde::{Deserialize, Serialize};
@@ -9,11 +16,53 @@ pub enum State {
     Transfer(u8),
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, PartialOrd)]
+/// M-cycles per bit with the internal clock at 8192 Hz, a byte taking 1024 of them
+const BIT_CYCLES: u16 = 128;
+
+/// M-cycles per bit with the CGB's fast internal clock at 262144 Hz
+const FAST_BIT_CYCLES: u16 = 4;
+
+/// M-cycles between checks of the socket, for a transfer driven by the peer or for the peer's
+/// answer to ours. Checking it every cycle would slow emulation down, and real transfers take
+/// 1024 cycles anyway.
+#[cfg(any(unix, windows))]
+const PEER_POLL_CYCLES: u8 = 128;
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct LinkCable {
     pub(crate) data: u8,
     pub(crate) control: u8,
     pub(crate) transfer: State,
+    /// M-cycles into shifting the current bit
+    clock: u16,
+    /// Every byte sent over the cable, if capturing
+    #[serde(skip)]
+    pub(crate) output: Option<Vec<u8>>,
+    /// Print captured bytes to stdout as they're sent
+    #[serde(skip)]
+    pub(crate) echo: bool,
+    /// Peripheral on the other end of the cable, if any
+    #[serde(skip)]
+    pub(crate) printer: Option<Printer>,
+    /// Another Game Boy on the other end of the cable, if linked
+    #[cfg(any(unix, windows))]
+    #[serde(skip)]
+    pub(crate) peer: Option<LinkPeer>,
+    #[cfg(any(unix, windows))]
+    #[serde(skip)]
+    peer_poll: u8,
+    /// The transfer driven with the internal clock is stalled until the peer answers
+    #[cfg(any(unix, windows))]
+    #[serde(skip)]
+    awaiting_peer: bool,
+    /// Byte shifted in during the current transfer
+    #[serde(skip, default = "disconnected")]
+    incoming: u8,
+}
+
+/// What the Game Boy reads from an unplugged cable
+fn disconnected() -> u8 {
+    0xFF
 }
 
 impl MemoryArea for LinkCable {
@@ -41,33 +90,169 @@ impl LinkCable {
             data: 0,
             control: 0,
             transfer: Off,
+            clock: 0,
+            output: None,
+            echo: false,
+            printer: None,
+            #[cfg(any(unix, windows))]
+            peer: None,
+            #[cfg(any(unix, windows))]
+            peer_poll: 0,
+            #[cfg(any(unix, windows))]
+            awaiting_peer: false,
+            incoming: disconnected(),
         }
     }
 
+    pub(crate) fn reset(&mut self) {
+        *self = Self {
+            output: self.output.take(),
+            echo: self.echo,
+            printer: self.printer.take().map(|_| Printer::new()),
+            #[cfg(any(unix, windows))]
+            peer: self.peer.take(),
+            ..Self::new()
+        };
+    }
+
+    /// Everything sent over the cable since capturing started, which is how blargg's test ROMs
+    /// report their results.
+    pub fn serial_log(&self) -> String {
+        String::from_utf8_lossy(self.output.as_deref().unwrap_or_default()).into_owned()
+    }
+
     fn set_control(&mut self, control: u8) {
+        if control & 0x81 == 0x81 {
+            if let Some(output) = self.output.as_mut() {
+                output.push(self.data);
+                #[cfg(any(unix, windows))]
+                if self.echo {
+                    print!("{}", self.data as char);
+                    let _ = stdout().flush();
+                }
+            }
+            self.incoming = self.exchange(self.data);
+        }
         self.control = control;
-        self.transfer = Transfer(0);
-        if self.control & 1 == 1 {
-            self.data = 0xFF;
-            self.control &= 0x7F;
+        self.transfer = if control & 0x80 != 0 { Transfer(0) } else { Off };
+        self.clock = 0;
+    }
+
+    /// Drives a transfer with the internal clock, returning the byte sent back by whatever is on
+    /// the other end of the cable. A linked Game Boy answers later, see [`Self::await_peer`].
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if let Some(printer) = self.printer.as_mut() {
+            return printer.exchange(byte);
         }
+
+        #[cfg(any(unix, windows))]
+        if let Some(peer) = self.peer.as_mut() {
+            match peer.send(byte) {
+                Ok(()) => {
+                    self.awaiting_peer = true;
+                    self.peer_poll = 0;
+                }
+                Err(e) => self.disconnect(e),
+            }
+        }
+
+        disconnected()
     }
 
-    pub(crate) fn machine_cycle(&mut self) -> bool {
-        if self.control & 1 != 1 {
+    /// Polls for the peer's answer to the transfer we're driving, returning true while it's
+    /// still on its way. Without one in time the transfer goes on as if the cable were unplugged.
+    #[cfg(any(unix, windows))]
+    fn await_peer(&mut self) -> bool {
+        let Some(peer) = self.peer.as_mut() else {
+            self.awaiting_peer = false;
             return false;
+        };
+        self.peer_poll = (self.peer_poll + 1) % PEER_POLL_CYCLES;
+        if self.peer_poll != 0 {
+            return true;
         }
 
-        self.transfer = match self.transfer {
-            Transfer(x) => Transfer(x + 1),
-            Off => Off,
+        match peer.reply() {
+            Ok(Reply::Pending) => return true,
+            Ok(Reply::Received(byte)) => self.incoming = byte,
+            Ok(Reply::TimedOut) => {}
+            Err(e) => self.disconnect(e),
+        }
+        self.awaiting_peer = false;
+        false
+    }
+
+    /// With the external clock a transfer completes whenever the peer drives one, the byte it
+    /// sends replaces the one sent back.
+    #[cfg(any(unix, windows))]
+    fn follow_peer(&mut self) -> bool {
+        let Some(peer) = self.peer.as_mut() else {
+            return false;
         };
+        self.peer_poll = (self.peer_poll + 1) % PEER_POLL_CYCLES;
+        if self.peer_poll != 0 {
+            return false;
+        }
 
-        if self.transfer != Transfer(8) {
-            false
-        } else {
-            self.transfer = Off;
-            true
+        match peer.receive(self.data) {
+            Ok(Some(byte)) => {
+                self.data = byte;
+                self.control &= 0x7F;
+                self.transfer = Off;
+                true
+            }
+            Ok(None) => false,
+            Err(e) => {
+                self.disconnect(e);
+                false
+            }
         }
     }
+
+    /// Falls back to an unplugged cable once the peer is gone.
+    #[cfg(any(unix, windows))]
+    fn disconnect(&mut self, error: std::io::Error) {
+        Logger::error(format!("Link cable disconnected: {error}"));
+        self.peer = None;
+        self.awaiting_peer = false;
+    }
+
+    /// Shifts a bit out and one in every `BIT_CYCLES` of a transfer with the internal clock,
+    /// returning true when the 8th completes it. In CGB mode bit 1 of SC picks the fast clock.
+    /// With the external clock the transfer waits for the peer, forever if there's none, and with
+    /// the internal clock it doesn't start shifting until a linked peer answers or times out.
+    pub(crate) fn machine_cycle(&mut self, cgb: bool) -> bool {
+        #[cfg(any(unix, windows))]
+        if self.control & 0x81 == 0x80 {
+            return self.follow_peer();
+        }
+
+        let Transfer(bit) = self.transfer else {
+            return false;
+        };
+        if self.control & 0x81 != 0x81 {
+            return false;
+        }
+        #[cfg(any(unix, windows))]
+        if self.awaiting_peer && self.await_peer() {
+            return false;
+        }
+
+        let period = if cgb && self.control & 0x02 != 0 { FAST_BIT_CYCLES } else { BIT_CYCLES };
+        self.clock += 1;
+        if self.clock < period {
+            return false;
+        }
+        self.clock = 0;
+
+        // Shift out the top bit and shift in the peer's
+        self.data = (self.data << 1) | (self.incoming >> (7 - bit) & 1);
+        if bit < 7 {
+            self.transfer = Transfer(bit + 1);
+            return false;
+        }
+        self.transfer = Off;
+        self.control &= 0x7F;
+        true
+    }
 }
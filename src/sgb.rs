@@ -0,0 +1,270 @@
+use std::cmp::Ordering;
+use std::mem::{replace, take};
+
+use serde::{Deserialize, Serialize};
+
+use crate::logger::Logger;
+use crate::ppu::Color;
+
+/// The SGB's default palette, from lightest to darkest, in RGB555
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+/// Screen size in tiles, the resolution of the attribute map
+const COLUMNS: usize = 20;
+const ROWS: usize = 18;
+
+/// The Super Game Boy side of a SGB enhanced game. Commands arrive as 16 byte packets pulsed bit
+/// by bit over the joypad select lines, and pick which of 4 palettes colors each tile on screen.
+/// The border and sound commands aren't supported.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub struct SuperGameBoy {
+    /// Joypad select lines (P14 and P15) as last written
+    lines: u8,
+    /// Bit of the packet being received, none until a reset pulse
+    bit: Option<usize>,
+    packet: [u8; 16],
+    /// Packets received so far for a multi-packet command
+    command: Vec<u8>,
+    /// RGB555 colors, color 0 is shared by all palettes
+    palettes: [[u16; 4]; 4],
+    /// Palettes sent with PAL_TRN, which PAL_SET picks from
+    system_palettes: Vec<u16>,
+    /// Palette of every tile on screen
+    attributes: Vec<u8>,
+    /// What MASK_EN shows instead of the game: 0 nothing, 1 the frozen screen, 2 black, 3 color 0
+    mask: u8,
+    /// Controllers enabled by MLT_REQ and the one the joypad reads next
+    players: u8,
+    player: u8,
+}
+
+impl SuperGameBoy {
+    pub fn new() -> Self {
+        Self {
+            lines: 0x30,
+            bit: None,
+            packet: [0; 16],
+            command: vec![],
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![],
+            attributes: vec![0; COLUMNS * ROWS],
+            mask: 0,
+            players: 1,
+            player: 0,
+        }
+    }
+
+    /// With several players enabled, the joypad reads the current player's ID while both select
+    /// lines are high. The unused top bits read high as they always do.
+    pub(crate) fn read(&self, address: usize) -> Option<u8> {
+        match address {
+            0xFF00 if self.players > 1 && self.lines == 0x30 => Some(0xF0 | (0x0F - self.player)),
+            _ => None,
+        }
+    }
+
+    /// Follows a write to the joypad register. Pulling both lines low starts a packet, after which
+    /// P14 low sends a 0 and P15 low a 1, with both lines going back high between bits. A 0 after
+    /// the 128th bit ends the packet.
+    pub(crate) fn write(&mut self, value: u8, vram: &[u8], lcdc: u8) {
+        let lines = value & 0x30;
+        let previous = replace(&mut self.lines, lines);
+        if previous & 0x20 == 0 && lines & 0x20 != 0 {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        match (self.bit, lines) {
+            (_, 0x00) => {
+                self.bit = Some(0);
+                self.packet = [0; 16];
+            }
+            (Some(128), 0x10 | 0x20) if previous == 0x30 => {
+                self.bit = None;
+                self.command.extend(self.packet);
+                let length = (self.command[0] & 0x07).max(1) as usize;
+                if self.command.len() >= length * 16 {
+                    let command = take(&mut self.command);
+                    self.execute(&command, vram, lcdc);
+                }
+            }
+            (Some(bit), 0x10 | 0x20) if previous == 0x30 => {
+                if lines == 0x10 {
+                    self.packet[bit / 8] |= 1 << (bit % 8);
+                }
+                self.bit = Some(bit + 1);
+            }
+            _ => (),
+        }
+    }
+
+    /// The color of `shade` for the pixel at (`x`, `y`), or none while the screen is frozen.
+    pub(crate) fn color(&self, x: usize, y: usize, shade: u8) -> Option<Color> {
+        let rgb555 = match self.mask {
+            1 => return None,
+            2 => 0,
+            3 => self.palettes[0][0],
+            _ => self.palettes[self.attributes[y / 8 * COLUMNS + x / 8] as usize][shade as usize],
+        };
+        Some(Color::from_rgb555(rgb555))
+    }
+
+    /// Runs a command, `data` being all of its packets back to back.
+    fn execute(&mut self, data: &[u8], vram: &[u8], lcdc: u8) {
+        match data[0] >> 3 {
+            0x00 => self.set_palettes(0, 1, data),
+            0x01 => self.set_palettes(2, 3, data),
+            0x02 => self.set_palettes(0, 3, data),
+            0x03 => self.set_palettes(1, 2, data),
+            0x04 => self.attribute_blocks(data),
+            0x05 => self.attribute_lines(data),
+            0x06 => self.attribute_division(data),
+            0x07 => self.attribute_characters(data),
+            0x0A => self.set_system_palettes(data),
+            0x0B => self.system_palettes = colors(&vram_transfer(vram, lcdc)),
+            0x11 => {
+                self.players = match data[1] & 0x03 {
+                    0x01 => 2,
+                    0x03 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            0x17 => self.mask = data[1] & 0x03,
+            command => Logger::info(format!("Ignoring unsupported SGB command {command:#04X}")),
+        }
+    }
+
+    /// PAL01, PAL23, PAL03 and PAL12: the shared color 0, then colors 1-3 of both palettes.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let colors = colors(&data[1..15]);
+        self.palettes[first][1..].copy_from_slice(&colors[1..4]);
+        self.palettes[second][1..].copy_from_slice(&colors[4..7]);
+        self.share_color_0(colors[0]);
+    }
+
+    /// PAL_SET: loads the 4 palettes from the system palettes sent with PAL_TRN.
+    fn set_system_palettes(&mut self, data: &[u8]) {
+        for (palette, number) in data[1..9].chunks_exact(2).enumerate() {
+            let number = u16::from_le_bytes([number[0], number[1]]) as usize & 0x1FF;
+            if let Some(colors) = self.system_palettes.get(number * 4..number * 4 + 4) {
+                self.palettes[palette].copy_from_slice(colors);
+            }
+        }
+        self.share_color_0(self.palettes[0][0]);
+        if data[9] & 0x40 != 0 {
+            self.mask = 0;
+        }
+    }
+
+    fn share_color_0(&mut self, color: u16) {
+        for palette in &mut self.palettes {
+            palette[0] = color;
+        }
+    }
+
+    /// Sets the palette of every tile picked by `palette`, which gets each tile's column and row.
+    fn paint(&mut self, palette: impl Fn(usize, usize) -> Option<u8>) {
+        for (index, attribute) in self.attributes.iter_mut().enumerate() {
+            if let Some(palette) = palette(index % COLUMNS, index / COLUMNS) {
+                *attribute = palette;
+            }
+        }
+    }
+
+    /// ATTR_BLK: rectangles with separate palettes for their inside, border and outside. When only
+    /// the inside or the outside changes, the border changes along with it.
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        for block in data[2..].chunks_exact(6).take(data[1] as usize) {
+            let [control, palettes, left, top, right, bottom] = [0, 1, 2, 3, 4, 5].map(|i| block[i] as usize);
+            let [inside, border, outside] = [0, 2, 4].map(|shift| (palettes >> shift) as u8 & 0x03);
+            let border = match control & 0x07 {
+                0x01 => Some(inside),
+                0x04 => Some(outside),
+                control if control & 0x02 != 0 => Some(border),
+                _ => None,
+            };
+            let inside = (control & 0x01 != 0).then_some(inside);
+            let outside = (control & 0x04 != 0).then_some(outside);
+            self.paint(|x, y| {
+                if x > left && x < right && y > top && y < bottom {
+                    inside
+                } else if (left..=right).contains(&x) && (top..=bottom).contains(&y) {
+                    border
+                } else {
+                    outside
+                }
+            });
+        }
+    }
+
+    /// ATTR_LIN: whole rows or columns, each byte holding the line in bits 0-4, the palette in bits
+    /// 5-6 and whether it's a row in bit 7.
+    fn attribute_lines(&mut self, data: &[u8]) {
+        for &line in data[2..].iter().take(data[1] as usize) {
+            let (index, palette, row) = ((line & 0x1F) as usize, (line >> 5) & 0x03, line & 0x80 != 0);
+            self.paint(|x, y| (if row { y } else { x } == index).then_some(palette));
+        }
+    }
+
+    /// ATTR_DIV: splits the screen at a row or column, with a palette for each side and the line.
+    fn attribute_division(&mut self, data: &[u8]) {
+        let [after, before, on] = [0, 2, 4].map(|shift| (data[1] >> shift) & 0x03);
+        let rows = data[1] & 0x40 != 0;
+        let line = data[2] as usize;
+        self.paint(|x, y| {
+            Some(match if rows { y } else { x }.cmp(&line) {
+                Ordering::Less => before,
+                Ordering::Equal => on,
+                Ordering::Greater => after,
+            })
+        });
+    }
+
+    /// ATTR_CHR: palettes for consecutive tiles from a starting one, 4 to a byte from the top bits
+    /// down, going left to right or top to bottom.
+    fn attribute_characters(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 0x01 != 0;
+        for index in 0..count.min(COLUMNS * ROWS) {
+            let Some(byte) = data.get(6 + index / 4) else { break };
+            if x < COLUMNS && y < ROWS {
+                self.attributes[y * COLUMNS + x] = (byte >> (6 - index % 4 * 2)) & 0x03;
+            }
+            if vertical {
+                y += 1;
+                if y == ROWS {
+                    (x, y) = (x + 1, 0);
+                }
+            } else {
+                x += 1;
+                if x == COLUMNS {
+                    (x, y) = (0, y + 1);
+                }
+            }
+        }
+    }
+}
+
+/// Reads little-endian RGB555 colors.
+fn colors(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks_exact(2).map(|color| u16::from_le_bytes([color[0], color[1]])).collect()
+}
+
+/// The 4 KiB a *_TRN command reads off the screen: the tiles of the first 13 background rows, 20
+/// to a row, as the game is expected to lay them out in order.
+fn vram_transfer(vram: &[u8], lcdc: u8) -> Vec<u8> {
+    let map = if lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+    (0..0x100)
+        .flat_map(|index| {
+            let tile = vram[map + index / COLUMNS * 32 + index % COLUMNS];
+            let tile = if lcdc & 0x10 != 0 {
+                tile as usize
+            } else {
+                // 8800 addressing, tile numbers are signed and centered on 9000
+                (0x100 + tile as i8 as i16) as usize
+            };
+            vram[tile * 0x10..tile * 0x10 + 0x10].iter().copied()
+        })
+        .collect()
+}
@@ -66,6 +66,10 @@ impl Timer {
         }
     }
 
+    pub fn reset(&mut self, boot_rom: bool) {
+        *self = Self::new(boot_rom);
+    }
+
     pub fn machine_cycle(&mut self, ticks: u16) -> bool {
         self.interrupt_served = false;
 
@@ -0,0 +1,109 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::instruction::Command;
+use crate::logger::Logger;
+use crate::register::Register;
+use crate::register::RegisterId::A;
+
+/// An executed instruction along with the registers it started with.
+#[derive(Copy, Clone, Debug)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u8,
+    command: Command,
+    /// AF, BC, DE, HL and SP
+    registers: [u16; 5],
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let [af, bc, de, hl, sp] = self.registers;
+        let flags: String = ["Z", "N", "H", "C"]
+            .iter()
+            .enumerate()
+            .map(|(bit, flag)| if af & (0x80 >> bit) != 0 { flag } else { "-" })
+            .collect();
+        let instruction = format!("{:#06X}: {}", self.pc, self.command);
+        write!(
+            f,
+            "{instruction:<24} ; {:02X}  AF={af:04X} BC={bc:04X} DE={de:04X} HL={hl:04X} SP={sp:04X} {flags}",
+            self.opcode
+        )
+    }
+}
+
+/// Keeps the last executed instructions in a preallocated ring, so they can be dumped after a
+/// crash or breakpoint without paying for continuous logging.
+pub struct TraceRing {
+    entries: Vec<Option<TraceEntry>>,
+    next: usize,
+    full: bool,
+}
+
+impl TraceRing {
+    const FILE: &'static str = "trace.txt";
+
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: vec![None; capacity.max(1)],
+            next: 0,
+            full: false,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, reg: &Register, opcode: u8, command: Command) {
+        self.entries[self.next] = Some(TraceEntry {
+            pc: reg.pc.value(),
+            opcode,
+            command,
+            registers: [
+                u16::from_le_bytes([reg.flags.value(), reg[A].value]),
+                reg.bc().value(),
+                reg.de().value(),
+                reg.hl().value(),
+                reg.sp.value(),
+            ],
+        });
+        self.next += 1;
+        if self.next == self.entries.len() {
+            self.next = 0;
+            self.full = true;
+        }
+    }
+
+    /// Traced instructions, from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+        let (newest, oldest) = self.entries.split_at(self.next);
+        let oldest = if self.full { oldest } else { &[] };
+        oldest.iter().chain(newest).flatten()
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.iter().map(TraceEntry::to_string).collect()
+    }
+
+    pub fn dump(&self, reason: &str) {
+        let trace = self.lines();
+
+        #[cfg(any(unix, windows))]
+        match std::fs::write(Self::FILE, trace.join("\n")) {
+            Ok(()) => Logger::info(format!(
+                "Dumped last {} instructions to {} ({reason})",
+                trace.len(),
+                Self::FILE
+            )),
+            Err(e) => Logger::error(format!("Unable to dump trace to {}: {e}", Self::FILE)),
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        Logger::info(format!("Last {} instructions ({reason}):\n{}", trace.len(), trace.join("\n")));
+    }
+
+    /// Prints the trace to stderr, for when the emulator is going down and files may not be
+    /// written anymore.
+    pub fn print(&self, reason: &str) {
+        let trace = self.lines();
+        Logger::error(format!("Last {} instructions ({reason}):\n{}", trace.len(), trace.join("\n")));
+    }
+}